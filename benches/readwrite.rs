@@ -8,6 +8,10 @@ use rand::{
 use rustcask::Rustcask;
 use tempfile::TempDir;
 
+// Counts the allocations made by each benchmark, along with its time.
+#[global_allocator]
+static ALLOC: divan::AllocProfiler = divan::AllocProfiler::system();
+
 fn main() {
     divan::main();
 }
@@ -78,6 +82,35 @@ fn bench_writes_sync_mode(bencher: Bencher) {
         .bench_values(|(kv_pair, mut store)| store.set(kv_pair.0, kv_pair.1));
 }
 
+#[divan::bench(args = [false, true])]
+fn bench_overwrites(bencher: Bencher, in_place_updates: bool) {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_in_place_updates(in_place_updates)
+        .open(temp_dir.path())
+        .unwrap();
+    let mut rng: rand::prelude::ThreadRng = rand::thread_rng();
+    let keys: Vec<Vec<u8>> = (0..OVERWRITE_COUNT)
+        .map(|_| KeyValuePair::random(&mut rng, KEY_SIZE, VAL_SIZE))
+        .map(|kv_pair| {
+            store.set(kv_pair.0.clone(), kv_pair.1).unwrap();
+            kv_pair.0
+        })
+        .collect();
+
+    // Every write overwrites one of the same few keys, with a value of the same size,
+    // so that in-place updates can reuse the previous value's slot.
+    bencher
+        .with_inputs(move || {
+            let mut rng = rand::thread_rng();
+            let key = keys.choose(&mut rng).unwrap().clone();
+            let value = (0..VAL_SIZE).map(|_| rng.gen::<u8>()).collect::<Vec<u8>>();
+            (key, value, store.clone())
+        })
+        .input_counter(|(key, value, _)| BytesCount::new(key.len() + value.len()))
+        .bench_values(|(key, value, mut store)| store.set(key, value));
+}
+
 #[divan::bench]
 fn bench_random_reads(bencher: Bencher) {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
@@ -102,6 +135,82 @@ fn bench_random_reads(bencher: Bencher) {
         .bench_values(|(kv_pair, mut store)| store.get(&kv_pair.0).unwrap());
 }
 
+const BATCH_SIZE: usize = 100;
+
+#[divan::bench(args = [false, true])]
+fn bench_batch_reads(bencher: Bencher, sorted: bool) {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    let mut rng: rand::prelude::ThreadRng = rand::thread_rng();
+    let kv_pairs = KeyValuePair::random_many(&mut rng, COUNT_KV_PAIRS, KEY_SIZE, VAL_SIZE);
+    for kv_pair in kv_pairs.clone() {
+        store.set(kv_pair.0, kv_pair.1).unwrap();
+    }
+
+    bencher
+        .with_inputs(move || {
+            let store = store.clone();
+            let mut rng = rand::thread_rng();
+            let keys: Vec<Vec<u8>> = kv_pairs
+                .choose_multiple(&mut rng, BATCH_SIZE)
+                .map(|kv_pair| kv_pair.0.clone())
+                .collect();
+            (keys, store)
+        })
+        .bench_values(|(keys, mut store)| {
+            if sorted {
+                store.get_many_sorted(&keys).unwrap()
+            } else {
+                store.get_many(&keys).unwrap()
+            }
+        });
+}
+
+const SMALL_VAL_SIZE: usize = 16;
+
+#[divan::bench(args = [0, SMALL_VAL_SIZE])]
+fn bench_small_value_reads(bencher: Bencher, inline_value_threshold: usize) {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_inline_value_threshold(inline_value_threshold)
+        .open(temp_dir.path())
+        .unwrap();
+    let mut rng: rand::prelude::ThreadRng = rand::thread_rng();
+    let kv_pairs = KeyValuePair::random_many(&mut rng, COUNT_KV_PAIRS, KEY_SIZE, SMALL_VAL_SIZE);
+    for kv_pair in kv_pairs.clone() {
+        store.set(kv_pair.0, kv_pair.1).unwrap();
+    }
+
+    bencher
+        .with_inputs(move || {
+            let store = store.clone();
+            let mut rng = rand::thread_rng();
+            let rand_index = rng.gen_range(0..kv_pairs.len());
+            (kv_pairs[rand_index].clone(), store)
+        })
+        .bench_values(|(kv_pair, mut store)| store.get(&kv_pair.0).unwrap());
+}
+
+#[divan::bench(args = [0, 64])]
+fn bench_full_scan(bencher: Bencher, scan_prefetch: usize) {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_scan_prefetch(scan_prefetch)
+        .open(temp_dir.path())
+        .unwrap();
+
+    let mut rng = rand::thread_rng();
+    let mut total_bytes = 0;
+    for kv_pair in KeyValuePair::random_many(&mut rng, COUNT_KV_PAIRS, KEY_SIZE, VAL_SIZE) {
+        total_bytes += kv_pair.0.len() + kv_pair.1.len();
+        store.set(kv_pair.0, kv_pair.1).unwrap();
+    }
+
+    bencher
+        .counter(BytesCount::new(total_bytes))
+        .bench(|| store.read_log_from(0, 0).unwrap().count());
+}
+
 #[divan::bench()]
 fn bench_open_hint_files_disabled(bencher: Bencher) {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
@@ -128,3 +237,61 @@ fn bench_open_hint_files_disabled(bencher: Bencher) {
         Rustcask::builder().open(temp_dir.path()).unwrap();
     });
 }
+
+const LARGE_STORE_KEYS: usize = 100_000;
+
+#[divan::bench(args = [None, Some(0), Some(LARGE_STORE_KEYS)])]
+fn bench_open_large_store(bencher: Bencher, keydir_capacity_hint: Option<usize>) {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    for i in 0..LARGE_STORE_KEYS {
+        store
+            .set(i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec())
+            .unwrap();
+    }
+    drop(store);
+
+    // None estimates the number of keys, and Some(0) disables pre-sizing the keydir.
+    bencher.bench_local(|| {
+        let mut builder = Rustcask::builder();
+        if let Some(keys) = keydir_capacity_hint {
+            builder = builder.set_keydir_capacity_hint(keys);
+        }
+        builder.open(temp_dir.path()).unwrap();
+    });
+}
+
+#[divan::bench(args = [false, true], sample_count = 20)]
+fn bench_bulk_load(bencher: Bencher, reserve: bool) {
+    bencher
+        .with_inputs(|| {
+            let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+            let store = Rustcask::builder().open(temp_dir.path()).unwrap();
+            (temp_dir, store)
+        })
+        .bench_local_values(|(temp_dir, mut store)| {
+            if reserve {
+                store.reserve(LARGE_STORE_KEYS);
+            }
+            for i in 0..LARGE_STORE_KEYS {
+                store
+                    .set(i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec())
+                    .unwrap();
+            }
+            (temp_dir, store)
+        });
+}
+
+#[divan::bench(sample_count = 20)]
+fn bench_merge_small_entries(bencher: Bencher) {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    for i in 0..LARGE_STORE_KEYS {
+        store
+            .set(i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec())
+            .unwrap();
+    }
+
+    // Every merge copies each of the small live entries again.
+    bencher.bench_local(|| store.merge().unwrap());
+}
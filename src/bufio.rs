@@ -33,6 +33,21 @@ where
     pub fn pos(&self) -> u64 {
         self.pos
     }
+
+    /// Seeks relative to the current position. Unlike `seek`, the buffer is kept when the new
+    /// position is within it.
+    pub fn seek_relative(&mut self, offset: i64) -> io::Result<()> {
+        self.reader.seek_relative(offset)?;
+        self.pos = self.pos.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        Ok(())
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.reader.get_ref()
+    }
 }
 
 impl<R> Read for BufReaderWithPos<R>
@@ -40,9 +55,8 @@ where
     R: Read + Seek,
 {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
-        self.reader.read(buf).map(|bytes_read| {
-            self.pos += bytes_read as u64;
-            bytes_read
+        self.reader.read(buf).inspect(|bytes_read| {
+            self.pos += *bytes_read as u64;
         })
     }
 }
@@ -0,0 +1,195 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::GenerationNumber;
+
+/// Controls which value is evicted when the value cache is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheEviction {
+    /// Evict the value that was read least recently.
+    Lru,
+    /// Evict the value of the key that has been read the fewest times, breaking ties by
+    /// evicting the value that was read least recently.
+    ///
+    /// This policy enables per key access counting.
+    /// See [`RustcaskBuilder::set_track_access_counts`](crate::RustcaskBuilder::set_track_access_counts).
+    Lfu,
+}
+
+#[derive(Debug)]
+struct CachedValue {
+    data_file_gen: GenerationNumber,
+    offset: u64,
+    value: Vec<u8>,
+    rank: Rank,
+}
+
+// Cached values are evicted in ascending rank order.
+// The first field is the key's access count (always 0 for LRU), and the second is
+// when the value was last read.
+type Rank = (u64, u64);
+
+/// A size-bounded cache of values, shared by every handle cloned from the same store.
+///
+/// Each cached value records the location in the data files that it was read from, and is only
+/// returned if the keydir still points to that location. This means cached values never have to
+/// be invalidated when a key is overwritten, removed, or moved by a merge. The exception is a value
+/// that's overwritten in place, which has to be removed from the cache.
+#[derive(Debug)]
+pub(crate) struct ValueCache {
+    // The maximum total size, in bytes, of the cached keys and values.
+    capacity: u64,
+    size: u64,
+    eviction: CacheEviction,
+    clock: u64,
+    values: HashMap<Vec<u8>, CachedValue>,
+    ranks: BTreeMap<Rank, Vec<u8>>,
+}
+
+impl ValueCache {
+    pub fn new(capacity: u64, eviction: CacheEviction) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            eviction,
+            clock: 0,
+            values: HashMap::new(),
+            ranks: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the cached value of a key, if it was read from the given data file location.
+    pub fn get(
+        &mut self,
+        key: &[u8],
+        data_file_gen: GenerationNumber,
+        offset: u64,
+        access_count: u64,
+    ) -> Option<&[u8]> {
+        let rank = self.next_rank(access_count);
+        let cached = self.values.get_mut(key)?;
+        if cached.data_file_gen != data_file_gen || cached.offset != offset {
+            return None;
+        }
+
+        let key = self
+            .ranks
+            .remove(&cached.rank)
+            .expect("Every cached value has a rank.");
+        cached.rank = rank;
+        self.ranks.insert(rank, key);
+        Some(&cached.value)
+    }
+
+    /// Caches the value of a key that was read from the given data file location,
+    /// evicting other values as needed to stay within the cache's capacity.
+    pub fn insert(
+        &mut self,
+        key: Vec<u8>,
+        data_file_gen: GenerationNumber,
+        offset: u64,
+        access_count: u64,
+        value: Vec<u8>,
+    ) {
+        self.remove(&key);
+        let size = cached_size(&key, &value);
+        if size > self.capacity {
+            return;
+        }
+        while self.size + size > self.capacity {
+            let (_, evicted_key) = self
+                .ranks
+                .pop_first()
+                .expect("The cache can't be over capacity while it's empty.");
+            self.remove(&evicted_key);
+        }
+
+        let rank = self.next_rank(access_count);
+        self.size += size;
+        self.ranks.insert(rank, key.clone());
+        self.values.insert(
+            key,
+            CachedValue {
+                data_file_gen,
+                offset,
+                value,
+                rank,
+            },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.ranks.clear();
+        self.size = 0;
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        if let Some(cached) = self.values.remove(key) {
+            self.ranks.remove(&cached.rank);
+            self.size -= cached_size(key, &cached.value);
+        }
+    }
+
+    fn next_rank(&mut self, access_count: u64) -> Rank {
+        self.clock += 1;
+        match self.eviction {
+            CacheEviction::Lru => (0, self.clock),
+            CacheEviction::Lfu => (access_count, self.clock),
+        }
+    }
+}
+
+fn cached_size(key: &[u8], value: &[u8]) -> u64 {
+    (key.len() + value.len()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheEviction, ValueCache};
+
+    #[test]
+    fn test_stale_location_misses() {
+        let mut cache = ValueCache::new(1024, CacheEviction::Lru);
+        cache.insert(b"key".to_vec(), 0, 8, 0, b"value".to_vec());
+
+        assert_eq!(cache.get(b"key", 0, 8, 0), Some(b"value".as_slice()));
+        assert_eq!(cache.get(b"key", 0, 64, 0), None);
+        assert_eq!(cache.get(b"key", 1, 8, 0), None);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        // Room for two entries
+        let mut cache = ValueCache::new(20, CacheEviction::Lru);
+        cache.insert(b"a".to_vec(), 0, 0, 0, b"value-a".to_vec());
+        cache.insert(b"b".to_vec(), 0, 1, 0, b"value-b".to_vec());
+        cache.get(b"a", 0, 0, 0);
+        cache.insert(b"c".to_vec(), 0, 2, 0, b"value-c".to_vec());
+
+        assert!(cache.get(b"a", 0, 0, 0).is_some());
+        assert!(cache.get(b"b", 0, 1, 0).is_none());
+        assert!(cache.get(b"c", 0, 2, 0).is_some());
+        assert_eq!(cache.size, 16);
+    }
+
+    #[test]
+    fn test_lfu_eviction() {
+        let mut cache = ValueCache::new(20, CacheEviction::Lfu);
+        cache.insert(b"a".to_vec(), 0, 0, 5, b"value-a".to_vec());
+        cache.insert(b"b".to_vec(), 0, 1, 1, b"value-b".to_vec());
+        cache.insert(b"c".to_vec(), 0, 2, 1, b"value-c".to_vec());
+
+        // "a" was inserted first, but has been read the most.
+        assert!(cache.get(b"a", 0, 0, 6).is_some());
+        assert!(cache.get(b"b", 0, 1, 2).is_none());
+        assert!(cache.get(b"c", 0, 2, 2).is_some());
+    }
+
+    #[test]
+    fn test_value_larger_than_capacity_is_not_cached() {
+        let mut cache = ValueCache::new(4, CacheEviction::Lru);
+        cache.insert(b"key".to_vec(), 0, 0, 0, b"value".to_vec());
+        assert!(cache.get(b"key", 0, 0, 0).is_none());
+        assert_eq!(cache.size, 0);
+    }
+}
@@ -0,0 +1,91 @@
+use std::vec;
+
+use crate::{
+    logfile::{LogFileEntry, LogFileIterator},
+    pins::PinGuard,
+    GenerationNumber,
+};
+
+/// A position in the data files that an iteration over the log can be resumed from.
+/// See [`crate::Rustcask::read_log_from_checkpoint`].
+///
+/// A checkpoint is just a generation and an offset, so it can be persisted alongside whatever
+/// the records were exported to, and recreated with [`LogCheckpoint::new`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LogCheckpoint {
+    generation: GenerationNumber,
+    offset: u64,
+}
+
+impl LogCheckpoint {
+    /// The checkpoint at the beginning of the log.
+    pub fn start() -> Self {
+        Self::default()
+    }
+
+    /// Recreates a checkpoint from the generation and offset it reported.
+    pub fn new(generation: GenerationNumber, offset: u64) -> Self {
+        Self { generation, offset }
+    }
+
+    /// The generation of the data file that iteration resumes in.
+    pub fn generation(&self) -> GenerationNumber {
+        self.generation
+    }
+
+    /// The offset in the data file that iteration resumes at. This is the offset just past the
+    /// last record that was returned.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// Iterates over every record in the data files, in the order the records were written,
+/// while tracking a checkpoint that the iteration can be resumed from.
+/// Created by [`crate::Rustcask::read_log_from_checkpoint`].
+///
+/// The generations that are iterated over are pinned until the iterator is dropped, so a merge
+/// can't delete them in the meantime.
+pub struct CheckpointedLogIter {
+    iterators: vec::IntoIter<(GenerationNumber, LogFileIterator)>,
+    current: Option<(GenerationNumber, LogFileIterator)>,
+    checkpoint: LogCheckpoint,
+    _pins: PinGuard,
+}
+
+impl CheckpointedLogIter {
+    pub(crate) fn new(
+        iterators: Vec<(GenerationNumber, LogFileIterator)>,
+        checkpoint: LogCheckpoint,
+        pins: PinGuard,
+    ) -> Self {
+        let mut iterators = iterators.into_iter();
+        Self {
+            current: iterators.next(),
+            iterators,
+            checkpoint,
+            _pins: pins,
+        }
+    }
+
+    /// Returns the checkpoint just past the last record that was returned. Resuming from it
+    /// returns the records that haven't been returned yet, and none that have.
+    pub fn checkpoint(&self) -> LogCheckpoint {
+        self.checkpoint
+    }
+}
+
+impl Iterator for CheckpointedLogIter {
+    type Item = (LogFileEntry, GenerationNumber, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (generation, iterator) = self.current.as_mut()?;
+            if let Some((entry, index)) = iterator.next() {
+                self.checkpoint = LogCheckpoint::new(*generation, index.offset + index.len);
+                return Some((entry, *generation, index.offset));
+            }
+            self.current = self.iterators.next();
+        }
+    }
+}
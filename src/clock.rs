@@ -0,0 +1,33 @@
+use std::{
+    fmt::Debug,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A source of wall clock time. The store reads the time from its clock when it
+/// sets an expiring value, and when it checks whether a value has expired.
+///
+/// The default clock is [`SystemClock`]. Tests can provide their own clock
+/// with [`crate::RustcaskBuilder::set_clock`] to control when values expire.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// A clock that reads the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Returns the clock's time in milliseconds since the UNIX epoch.
+/// Times before the epoch are treated as the epoch.
+pub(crate) fn now_millis(clock: &dyn Clock) -> u64 {
+    let since_epoch = clock
+        .now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO);
+    u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+}
@@ -0,0 +1,95 @@
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+
+use crate::{
+    report::{GenerationVerifyReport, OpenAnomaly},
+    GenerationNumber,
+};
+
+/// Describes corrupt data that the store ran into.
+///
+/// Passed to the handler set with [`crate::RustcaskBuilder::set_corruption_handler`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorruptionEvent {
+    /// The generation of the data file that holds the corrupt data.
+    pub generation: GenerationNumber,
+    /// The offset of the corrupt entry in the data file.
+    pub offset: u64,
+    pub kind: CorruptionKind,
+}
+
+/// The kind of corruption that was detected. See [`CorruptionEvent`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CorruptionKind {
+    /// An entry couldn't be deserialized, or its checksums didn't match.
+    CorruptEntry,
+    /// The data file ends partway through an entry.
+    TruncatedEntry,
+    /// A keydir entry points past the end of its data file.
+    IndexOutOfRange,
+    /// A keydir entry points to an entry for a different key.
+    KeyMismatch,
+}
+
+/// What reads do when they find that the value of a key is corrupt. See
+/// [`crate::RustcaskBuilder::set_on_read_corruption`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnCorruption {
+    /// Return an error describing the corruption. This is the default.
+    #[default]
+    Error,
+    /// Treat the key as missing, as if it had been removed.
+    SkipReturnNone,
+    /// Panic, naming the corrupt entry.
+    Panic,
+}
+
+/// Calls the corruption handler that the store was configured with.
+#[derive(Clone)]
+pub(crate) struct CorruptionHandler(Arc<dyn Fn(CorruptionEvent) + Send + Sync>);
+
+impl CorruptionHandler {
+    pub fn new(handler: Arc<dyn Fn(CorruptionEvent) + Send + Sync>) -> Self {
+        Self(handler)
+    }
+
+    pub fn report(&self, generation: GenerationNumber, offset: u64, kind: CorruptionKind) {
+        (self.0)(CorruptionEvent {
+            generation,
+            offset,
+            kind,
+        });
+    }
+
+    /// Reports the corrupt and truncated entries that `open` found.
+    pub fn report_anomaly(&self, anomaly: &OpenAnomaly) {
+        match anomaly {
+            OpenAnomaly::CorruptEntry { generation, offset } => {
+                self.report(*generation, *offset, CorruptionKind::CorruptEntry)
+            }
+            OpenAnomaly::TruncatedEntry { generation, offset } => {
+                self.report(*generation, *offset, CorruptionKind::TruncatedEntry)
+            }
+            _ => {}
+        }
+    }
+
+    /// Reports the corrupt and truncated entries that `verify_generation` found.
+    pub fn report_verify(&self, report: &GenerationVerifyReport) {
+        for &offset in &report.corrupt_offsets {
+            self.report(report.generation, offset, CorruptionKind::CorruptEntry);
+        }
+        if let Some(offset) = report.truncated_offset {
+            self.report(report.generation, offset, CorruptionKind::TruncatedEntry);
+        }
+    }
+}
+
+impl Debug for CorruptionHandler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("CorruptionHandler")
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Cumulative operation counts for a Rustcask store.
+///
+/// The counts are shared by every handle cloned from the same [`Rustcask`](crate::Rustcask),
+/// and start at zero when the store is opened.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Counters {
+    /// The number of calls to `get`, including gets for keys that don't exist.
+    pub gets: u64,
+    /// The number of calls to `set`.
+    pub sets: u64,
+    /// The number of calls to `remove`.
+    pub removes: u64,
+    /// The number of completed merges, including automatic merges.
+    pub merges: u64,
+    /// The number of bytes appended to the active data file by `set` and `remove`.
+    pub bytes_written: u64,
+    /// The number of bytes of live entries that merges copied to the merged data files.
+    pub merge_bytes_written: u64,
+    /// The number of writes that were synced to disk before returning, either because sync mode
+    /// is on, or because they were made with `set_durable`.
+    pub synced_writes: u64,
+    /// The number of bytes read from data files by `get`.
+    /// Values returned from the value cache aren't counted.
+    pub bytes_read: u64,
+    /// The number of gets that were served from the value cache.
+    pub cache_hits: u64,
+    /// The number of gets for existing keys that had to read the data files because the value
+    /// wasn't cached. Only counted when the value cache is enabled.
+    pub cache_misses: u64,
+}
+
+/// The atomic counters behind [`Counters`]. These are cheap enough to update on every operation.
+#[derive(Debug, Default)]
+pub(crate) struct SharedCounters {
+    gets: AtomicU64,
+    sets: AtomicU64,
+    removes: AtomicU64,
+    merges: AtomicU64,
+    bytes_written: AtomicU64,
+    merge_bytes_written: AtomicU64,
+    synced_writes: AtomicU64,
+    bytes_read: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl SharedCounters {
+    pub fn record_get(&self) {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_set(&self) {
+        self.sets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_remove(&self) {
+        self.removes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_merge(&self) {
+        self.merges.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bytes_written(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_merge_bytes_written(&self, bytes: u64) {
+        self.merge_bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_synced_write(&self) {
+        self.synced_writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bytes_read(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current value of every counter.
+    ///
+    /// Each counter is read independently, so a snapshot taken during concurrent
+    /// operations may reflect part of an operation.
+    pub fn snapshot(&self) -> Counters {
+        Counters {
+            gets: self.gets.load(Ordering::Relaxed),
+            sets: self.sets.load(Ordering::Relaxed),
+            removes: self.removes.load(Ordering::Relaxed),
+            merges: self.merges.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            merge_bytes_written: self.merge_bytes_written.load(Ordering::Relaxed),
+            synced_writes: self.synced_writes.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+        }
+    }
+}
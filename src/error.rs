@@ -2,6 +2,7 @@ use std::{
     error::Error,
     fmt::{self, Display, Formatter},
     io,
+    string::FromUtf8Error,
 };
 
 use crate::GenerationNumber;
@@ -18,6 +19,7 @@ pub struct MergeError {
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum MergeErrorKind {
     Io(io::Error),
     OutsideMergeWindow,
@@ -62,9 +64,19 @@ pub struct SetError {
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum SetErrorKind {
     Serialize(bincode::Error),
     Io(io::Error),
+    /// The rate limit was exceeded, and rate limiting is configured to fail instead of waiting.
+    RateLimited,
+    /// The value was written and can be read, but rotating the full active data file to a new
+    /// data file failed. Rotation is retried after the next write. Until it succeeds, the active
+    /// data file keeps growing past the maximum data file size.
+    Rotation(io::Error),
+    /// The key's current value couldn't be read, so it wasn't rewritten.
+    /// Only returned by `Rustcask::compact_key`.
+    Read(GetError),
 }
 
 impl Error for SetError {
@@ -72,6 +84,9 @@ impl Error for SetError {
         match &self.kind {
             SetErrorKind::Io(e) => Some(e),
             SetErrorKind::Serialize(e) => Some(e),
+            SetErrorKind::RateLimited => None,
+            SetErrorKind::Rotation(e) => Some(e),
+            SetErrorKind::Read(e) => Some(e),
         }
     }
 }
@@ -80,11 +95,23 @@ impl Display for SetError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         // TODO [RyanStan 04-29-24] Implement a "pretty print" mode, that when disabled, does
         // not try printing the key.
-        write!(
-            f,
-            "error setting key. Bytes of key interpreted as utf 8: {} ",
-            String::from_utf8_lossy(&self.key)
-        )
+        match &self.kind {
+            SetErrorKind::Rotation(_) => write!(
+                f,
+                "key was set, but rotating the active data file failed. Bytes of key interpreted as utf 8: {} ",
+                String::from_utf8_lossy(&self.key)
+            ),
+            SetErrorKind::Read(_) => write!(
+                f,
+                "error reading the value of key to rewrite it. Bytes of key interpreted as utf 8: {} ",
+                String::from_utf8_lossy(&self.key)
+            ),
+            _ => write!(
+                f,
+                "error setting key. Bytes of key interpreted as utf 8: {} ",
+                String::from_utf8_lossy(&self.key)
+            ),
+        }
     }
 }
 
@@ -96,12 +123,18 @@ pub struct RemoveError {
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum RemoveErrorKind {
     Io(io::Error),
     /// A deserialization error indicates there was an error
     /// retrieving the previous value at the key. This may mean that the
     /// existing data was corrupted.
     Deserialize(bincode::Error),
+    /// The rate limit was exceeded, and rate limiting is configured to fail instead of waiting.
+    RateLimited,
+    /// The key was removed, but rotating the full active data file to a new data file failed.
+    /// See [`SetErrorKind::Rotation`].
+    Rotation(io::Error),
 }
 
 impl Error for RemoveError {
@@ -109,17 +142,26 @@ impl Error for RemoveError {
         match &self.kind {
             RemoveErrorKind::Io(e) => Some(e),
             RemoveErrorKind::Deserialize(e) => Some(e),
+            RemoveErrorKind::RateLimited => None,
+            RemoveErrorKind::Rotation(e) => Some(e),
         }
     }
 }
 
 impl Display for RemoveError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "error removing key. Bytes of key interpreted as utf8: {}",
-            String::from_utf8_lossy(&self.key)
-        )
+        match &self.kind {
+            RemoveErrorKind::Rotation(_) => write!(
+                f,
+                "key was removed, but rotating the active data file failed. Bytes of key interpreted as utf8: {}",
+                String::from_utf8_lossy(&self.key)
+            ),
+            _ => write!(
+                f,
+                "error removing key. Bytes of key interpreted as utf8: {}",
+                String::from_utf8_lossy(&self.key)
+            ),
+        }
     }
 }
 
@@ -131,9 +173,18 @@ pub struct OpenError {
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum OpenErrorKind {
     Io(io::Error),
     BadDirectory,
+    /// A data file was written in a format version that this version of rustcask can't read.
+    UnsupportedVersion {
+        generation: GenerationNumber,
+        version: u32,
+    },
+    /// The builder's settings conflict, or can't produce a working store.
+    /// Describes the problem.
+    InvalidConfig(String),
 }
 
 impl Error for OpenError {
@@ -141,44 +192,170 @@ impl Error for OpenError {
         match &self.kind {
             OpenErrorKind::Io(e) => Some(e),
             OpenErrorKind::BadDirectory => None,
+            OpenErrorKind::UnsupportedVersion { .. } => None,
+            OpenErrorKind::InvalidConfig(_) => None,
         }
     }
 }
 
 impl Display for OpenError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "error opening rustcask directory {}", self.rustcask_dir)
+        match &self.kind {
+            OpenErrorKind::UnsupportedVersion {
+                generation,
+                version,
+            } => write!(
+                f,
+                "error opening rustcask directory {}: data file for generation {} \
+                has unsupported format version {}. Data files written by an older version \
+                of rustcask can be upgraded with Rustcask::migrate.",
+                self.rustcask_dir, generation, version
+            ),
+            OpenErrorKind::InvalidConfig(reason) => write!(
+                f,
+                "error opening rustcask directory {}: invalid configuration: {}",
+                self.rustcask_dir, reason
+            ),
+            _ => write!(f, "error opening rustcask directory {}", self.rustcask_dir),
+        }
     }
 }
 
 #[derive(Debug)]
 #[non_exhaustive]
-pub struct GetError<'a> {
+pub struct MigrateError {
+    pub kind: MigrateErrorKind,
+    pub rustcask_dir: String,
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum MigrateErrorKind {
+    Io(io::Error),
+    /// An entry in a data file written in an older format version couldn't be deserialized.
+    Deserialize(bincode::Error),
+    /// A data file was written in a format version that's newer than this version of rustcask.
+    UnsupportedVersion {
+        generation: GenerationNumber,
+        version: u32,
+    },
+}
+
+impl Error for MigrateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            MigrateErrorKind::Io(e) => Some(e),
+            MigrateErrorKind::Deserialize(e) => Some(e),
+            MigrateErrorKind::UnsupportedVersion { .. } => None,
+        }
+    }
+}
+
+impl Display for MigrateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            MigrateErrorKind::UnsupportedVersion {
+                generation,
+                version,
+            } => write!(
+                f,
+                "error migrating rustcask directory {}: data file for generation {} \
+                has unsupported format version {}",
+                self.rustcask_dir, generation, version
+            ),
+            _ => write!(
+                f,
+                "error migrating rustcask directory {}",
+                self.rustcask_dir
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct GetError {
     pub kind: GetErrorKind,
-    pub key: &'a Vec<u8>,
+    pub key: Vec<u8>,
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum GetErrorKind {
     Io(io::Error),
     Deserialize(bincode::Error),
+    /// The stored value is not valid UTF-8, so it can't be returned as a `String`.
+    InvalidUtf8(FromUtf8Error),
+    /// The rate limit was exceeded, and rate limiting is configured to fail instead of waiting.
+    RateLimited,
+    /// The keydir entry for the key points past the end of its data file. This means that a
+    /// hint file or the keydir is corrupt.
+    IndexOutOfRange {
+        generation: GenerationNumber,
+        offset: u64,
+        len: u64,
+        file_len: u64,
+    },
+    /// The keydir entry for the key points to an entry for a different key. This means that a
+    /// hint file or the keydir is corrupt.
+    KeyMismatch {
+        generation: GenerationNumber,
+        offset: u64,
+    },
 }
 
-impl<'a> Error for GetError<'a> {
+impl Error for GetError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match &self.kind {
             GetErrorKind::Io(e) => Some(e),
             GetErrorKind::Deserialize(e) => Some(e),
+            GetErrorKind::InvalidUtf8(e) => Some(e),
+            GetErrorKind::RateLimited => None,
+            GetErrorKind::IndexOutOfRange { .. } => None,
+            GetErrorKind::KeyMismatch { .. } => None,
         }
     }
 }
 
-impl<'a> Display for GetError<'a> {
+impl Display for GetError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "error getting value.  Bytes of key interpreted as utf8: {}",
-            String::from_utf8_lossy(self.key)
-        )
+        match &self.kind {
+            GetErrorKind::InvalidUtf8(_) => write!(
+                f,
+                "value is not valid utf8.  Bytes of key interpreted as utf8: {}",
+                String::from_utf8_lossy(&self.key)
+            ),
+            GetErrorKind::RateLimited => write!(
+                f,
+                "read rate limit exceeded.  Bytes of key interpreted as utf8: {}",
+                String::from_utf8_lossy(&self.key)
+            ),
+            GetErrorKind::IndexOutOfRange {
+                generation,
+                offset,
+                len,
+                file_len,
+            } => write!(
+                f,
+                "entry at offset {} with length {} is past the end of data file {}, which is {} bytes long.  Bytes of key interpreted as utf8: {}",
+                offset,
+                len,
+                generation,
+                file_len,
+                String::from_utf8_lossy(&self.key)
+            ),
+            GetErrorKind::KeyMismatch { generation, offset } => write!(
+                f,
+                "entry at offset {} in data file {} holds a different key.  Bytes of key interpreted as utf8: {}",
+                offset,
+                generation,
+                String::from_utf8_lossy(&self.key)
+            ),
+            _ => write!(
+                f,
+                "error getting value.  Bytes of key interpreted as utf8: {}",
+                String::from_utf8_lossy(&self.key)
+            ),
+        }
     }
 }
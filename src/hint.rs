@@ -0,0 +1,453 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use bincode::Options;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bufio::BufReaderWithPos,
+    logfile::{LogFileEntry, LogIndex},
+};
+
+/// Identifies a hint file. Every hint file starts with this magic,
+/// followed by the hint file's flags as a little endian `u32`.
+/// Hint files that don't start with the magic were written before the header was introduced.
+/// They have no flags set.
+const HINT_FILE_MAGIC: [u8; 4] = *b"RCHT";
+
+/// The length in bytes of the header at the start of each hint file.
+pub const HINT_FILE_HEADER_LEN: u64 = 8;
+
+/// Set in the hint file flags if the entries are compressed with zstd.
+const HINT_FLAG_COMPRESSED: u32 = 1;
+
+/// Set in the hint file flags if the entries record the length of their value. Every hint file
+/// written now sets this flag. Hint files without it can't be used to build the keydir.
+const HINT_FLAG_VALUE_LENS: u32 = 2;
+
+/// Set in the hint file flags if the entries' integers are varint encoded. Every hint file
+/// written now sets this flag. Hint files without it have fixed width integers.
+const HINT_FLAG_VARINT: u32 = 4;
+
+/// The zstd compression level that hint files are compressed with.
+const HINT_COMPRESSION_LEVEL: i32 = 3;
+
+/// The number of hint entries that are checked against the data file
+/// when opening with `HintVerify::Sample`.
+const HINT_VERIFY_SAMPLE_SIZE: usize = 16;
+
+/// Controls how much `open` trusts the hint files it finds in the rustcask directory.
+///
+/// A hint file records the keydir entries for a data file so that the keydir can be rebuilt
+/// without reading every value. If a hint file disagrees with its data file, reads for the
+/// affected keys will return the wrong entry. Hint files that fail verification are ignored,
+/// and the keydir is instead rebuilt from the data file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HintVerify {
+    /// Trust hint files without checking them against their data files.
+    None,
+    /// Check an evenly spaced sample of hint entries against the data file,
+    /// and check that every hint entry ends within the data file.
+    /// This gives a cheap integrity signal without the cost of `Full`.
+    Sample,
+    /// Check every hint entry against the data file.
+    Full,
+}
+
+/// Represents an entry in a hint file.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct HintFileEntry {
+    pub key: Vec<u8>,
+    pub index: LogIndex,
+    // The length of the value as it's stored in the data file.
+    pub value_len: u64,
+    pub expires_at: Option<u64>,
+}
+
+/// Writes a hint file containing the given entries. If `compress` is true, the entries are
+/// compressed with zstd, and the hint file is flagged as compressed.
+pub fn write_hint_file(
+    hint_file: &Path,
+    entries: &[HintFileEntry],
+    compress: bool,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(hint_file)?);
+    let mut flags = HINT_FLAG_VALUE_LENS | HINT_FLAG_VARINT;
+    if compress {
+        flags |= HINT_FLAG_COMPRESSED;
+    }
+    writer.write_all(&HINT_FILE_MAGIC)?;
+    writer.write_all(&flags.to_le_bytes())?;
+
+    if compress {
+        let mut encoder = zstd::Encoder::new(&mut writer, HINT_COMPRESSION_LEVEL)?;
+        write_hint_entries(&mut encoder, entries)?;
+        encoder.finish()?;
+    } else {
+        write_hint_entries(&mut writer, entries)?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()
+}
+
+fn write_hint_entries<W: Write>(mut writer: W, entries: &[HintFileEntry]) -> io::Result<()> {
+    for entry in entries {
+        bincode::DefaultOptions::new()
+            .serialize_into(&mut writer, entry)
+            .map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+/// Returns the size of the hint entry that `write_hint_file` writes for a key of `key_len` bytes
+/// with the given index, value length and expiry time, before compression.
+pub fn hint_entry_len(
+    key_len: usize,
+    index: &LogIndex,
+    value_len: u64,
+    expires_at: Option<u64>,
+) -> u64 {
+    // The key is prefixed with its length, and the expiry time is prefixed with a tag byte.
+    let expires_at_len = expires_at.map_or(0, varint_len);
+    varint_len(key_len as u64)
+        + key_len as u64
+        + varint_len(index.offset)
+        + varint_len(index.len)
+        + varint_len(value_len)
+        + 1
+        + expires_at_len
+}
+
+/// Returns the number of bytes that bincode's varint encoding uses for `value`.
+fn varint_len(value: u64) -> u64 {
+    match value {
+        0..=250 => 1,
+        251..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+/// Reads every entry of a hint file, decompressing them if the hint file is compressed.
+///
+/// Returns an error if the hint file can't be read, if it contains a partially written entry,
+/// if it has flags that this version of rustcask doesn't recognize, or if it was written before
+/// hint entries recorded the length of their value.
+pub fn read_hint_file(hint_file: &Path) -> io::Result<Vec<HintFileEntry>> {
+    let file = File::open(hint_file)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReaderWithPos::new(file)?;
+    let flags = read_hint_file_flags(&mut reader, len)?;
+    if flags & HINT_FLAG_VALUE_LENS == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "hint file {} doesn't record value lengths",
+                hint_file.display()
+            ),
+        ));
+    }
+    if flags & !(HINT_FLAG_COMPRESSED | HINT_FLAG_VALUE_LENS | HINT_FLAG_VARINT) != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "hint file {} has unsupported flags {:#x}",
+                hint_file.display(),
+                flags
+            ),
+        ));
+    }
+
+    let varint = flags & HINT_FLAG_VARINT != 0;
+    let mut entries = Vec::new();
+    if flags & HINT_FLAG_COMPRESSED != 0 {
+        let decompressed = zstd::decode_all(reader)?;
+        let mut remaining = decompressed.as_slice();
+        while !remaining.is_empty() {
+            entries.push(read_hint_entry(&mut remaining, varint)?);
+        }
+    } else {
+        while reader.pos() < len {
+            entries.push(read_hint_entry(&mut reader, varint)?);
+        }
+    }
+    Ok(entries)
+}
+
+fn read_hint_entry<R: Read>(reader: R, varint: bool) -> io::Result<HintFileEntry> {
+    let entry = if varint {
+        bincode::DefaultOptions::new().deserialize_from(reader)
+    } else {
+        bincode::deserialize_from(reader)
+    };
+    entry.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Reads the flags from the start of a hint file of `len` bytes, leaving the reader positioned
+/// at the first entry. Hint files without a header have no flags.
+fn read_hint_file_flags(reader: &mut BufReaderWithPos<File>, len: u64) -> io::Result<u32> {
+    if len < HINT_FILE_HEADER_LEN {
+        return Ok(0);
+    }
+    let mut header = [0; HINT_FILE_HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+    if header[..4] == HINT_FILE_MAGIC {
+        return Ok(u32::from_le_bytes(header[4..].try_into().unwrap()));
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(0)
+}
+
+/// Checks hint entries against the data file they describe.
+///
+/// Unless `mode` is `HintVerify::None`, every entry is checked to end within the data file,
+/// and then the entries chosen by `mode` are compared with the data file's entries.
+///
+/// Returns `Ok(false)` if any of the checked entries doesn't match the data file.
+pub fn verify_hint_entries(
+    data_file: &Path,
+    entries: &[HintFileEntry],
+    mode: HintVerify,
+) -> io::Result<bool> {
+    let sample = match mode {
+        HintVerify::None => return Ok(true),
+        HintVerify::Sample => sample_indices(entries.len(), HINT_VERIFY_SAMPLE_SIZE),
+        HintVerify::Full => (0..entries.len()).collect(),
+    };
+
+    let file = File::open(data_file)?;
+    let file_len = file.metadata()?.len();
+    if let Some(i) = entries
+        .iter()
+        .position(|entry| entry.index.offset.saturating_add(entry.index.len) > file_len)
+    {
+        warn!(
+            "Hint entry {} for data file {} points past the end of the data file.",
+            i,
+            data_file.display()
+        );
+        return Ok(false);
+    }
+
+    let mut reader = BufReaderWithPos::new(file)?;
+    for i in sample {
+        if !hint_entry_matches(&mut reader, &entries[i])? {
+            warn!(
+                "Hint entry {} for data file {} does not match the data file.",
+                i,
+                data_file.display()
+            );
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn hint_entry_matches(
+    reader: &mut BufReaderWithPos<File>,
+    hint_entry: &HintFileEntry,
+) -> io::Result<bool> {
+    reader.seek(SeekFrom::Start(hint_entry.index.offset))?;
+    let mut buffer: Vec<u8> = vec![0; hint_entry.index.len as usize];
+    match reader.read_exact(&mut buffer) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+        Err(err) => return Err(err),
+    }
+
+    let data_file_entry = match LogFileEntry::decode_from(buffer.as_slice()) {
+        Ok(entry) => entry,
+        Err(_) => return Ok(false),
+    };
+    let serialized_len = data_file_entry
+        .encode()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+        .len() as u64;
+
+    Ok(data_file_entry.key == hint_entry.key
+        && data_file_entry
+            .value
+            .is_some_and(|value| value.len() as u64 == hint_entry.value_len)
+        && data_file_entry.expires_at == hint_entry.expires_at
+        && serialized_len == hint_entry.index.len)
+}
+
+/// Returns up to `sample_size` evenly spaced indices into a collection of `len` items.
+/// The first and last items are always included.
+fn sample_indices(len: usize, sample_size: usize) -> Vec<usize> {
+    if len <= sample_size {
+        return (0..len).collect();
+    }
+    (0..sample_size)
+        .map(|i| i * (len - 1) / (sample_size - 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        io::{self, Write},
+    };
+
+    use tempfile::TempDir;
+
+    use crate::{
+        logfile::{write_data_file_header, LogFileEntry, LogIndex, DATA_FILE_HEADER_LEN},
+        utils::{data_file_path, hint_file_path},
+    };
+
+    use super::{
+        hint_entry_len, read_hint_file, sample_indices, verify_hint_entries, write_hint_file,
+        HintFileEntry, HintVerify, HINT_FILE_HEADER_LEN,
+    };
+
+    /// Writes a data file with `count` entries and returns the matching hint entries.
+    fn setup_data_file(temp_dir: &TempDir, count: usize) -> Vec<HintFileEntry> {
+        let mut data_file = File::create(data_file_path(temp_dir.path(), &0)).unwrap();
+        write_data_file_header(&mut data_file).unwrap();
+        let mut hint_entries = Vec::new();
+        let mut offset = DATA_FILE_HEADER_LEN;
+        for i in 0..count {
+            let entry = LogFileEntry {
+                key: format!("key{}", i).into_bytes(),
+                value: Some(format!("value{}", i).into_bytes()),
+                expires_at: None,
+                transforms: Vec::new(),
+            };
+            let encoded = entry.encode().unwrap();
+            data_file.write_all(&encoded).unwrap();
+            hint_entries.push(HintFileEntry {
+                key: entry.key,
+                index: LogIndex {
+                    offset,
+                    len: encoded.len() as u64,
+                },
+                value_len: entry.value.unwrap().len() as u64,
+                expires_at: entry.expires_at,
+            });
+            offset += encoded.len() as u64;
+        }
+        data_file.flush().unwrap();
+        hint_entries
+    }
+
+    #[test]
+    fn test_hint_file_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let hint_entries = setup_data_file(&temp_dir, 3);
+        let hint_file = hint_file_path(temp_dir.path(), 0);
+
+        for compress in [false, true] {
+            write_hint_file(&hint_file, &hint_entries, compress).unwrap();
+            assert_eq!(read_hint_file(&hint_file).unwrap(), hint_entries);
+        }
+
+        // Hint files written before entries were varint encoded have fixed width integers.
+        let mut fixint_hint_file = File::create(&hint_file).unwrap();
+        fixint_hint_file.write_all(b"RCHT").unwrap();
+        fixint_hint_file.write_all(&2u32.to_le_bytes()).unwrap();
+        for entry in &hint_entries {
+            bincode::serialize_into(&mut fixint_hint_file, entry).unwrap();
+        }
+        assert_eq!(read_hint_file(&hint_file).unwrap(), hint_entries);
+
+        // Hint files written before the header was introduced have no header, and hint files
+        // written before entries recorded their value length don't set the flag. Neither can be
+        // used, because their entries don't have value lengths.
+        let mut legacy_hint_file = File::create(&hint_file).unwrap();
+        for entry in &hint_entries {
+            bincode::serialize_into(&mut legacy_hint_file, &(&entry.key, &entry.index)).unwrap();
+            bincode::serialize_into(&mut legacy_hint_file, &entry.expires_at).unwrap();
+        }
+        let err = read_hint_file(&hint_file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut unflagged_hint_file = File::create(&hint_file).unwrap();
+        unflagged_hint_file.write_all(b"RCHT").unwrap();
+        unflagged_hint_file.write_all(&0u32.to_le_bytes()).unwrap();
+        let err = read_hint_file(&hint_file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_hint_entry_len() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut hint_entries = setup_data_file(&temp_dir, 3);
+        // Cover each width of the varint encoding.
+        hint_entries[0].value_len = 70_000;
+        hint_entries[1].expires_at = Some(1000);
+        hint_entries[2].expires_at = Some(1_700_000_000_000);
+        let hint_file = hint_file_path(temp_dir.path(), 0);
+
+        write_hint_file(&hint_file, &hint_entries, false).unwrap();
+
+        let expected_len: u64 = hint_entries
+            .iter()
+            .map(|entry| {
+                hint_entry_len(
+                    entry.key.len(),
+                    &entry.index,
+                    entry.value_len,
+                    entry.expires_at,
+                )
+            })
+            .sum();
+        assert_eq!(
+            fs::metadata(&hint_file).unwrap().len(),
+            HINT_FILE_HEADER_LEN + expected_len
+        );
+    }
+
+    #[test]
+    fn test_verify_valid_hint_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let hint_entries = setup_data_file(&temp_dir, 40);
+        let data_file = data_file_path(temp_dir.path(), &0);
+
+        for mode in [HintVerify::None, HintVerify::Sample, HintVerify::Full] {
+            assert!(verify_hint_entries(&data_file, &hint_entries, mode).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_verify_sampled_entry_is_wrong() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut hint_entries = setup_data_file(&temp_dir, 40);
+        let data_file = data_file_path(temp_dir.path(), &0);
+
+        // The first entry is always part of the sample.
+        hint_entries[0].index.offset = hint_entries[1].index.offset;
+
+        assert!(verify_hint_entries(&data_file, &hint_entries, HintVerify::None).unwrap());
+        assert!(!verify_hint_entries(&data_file, &hint_entries, HintVerify::Sample).unwrap());
+        assert!(!verify_hint_entries(&data_file, &hint_entries, HintVerify::Full).unwrap());
+    }
+
+    #[test]
+    fn test_verify_full_catches_unsampled_entry() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut hint_entries = setup_data_file(&temp_dir, 40);
+        let data_file = data_file_path(temp_dir.path(), &0);
+
+        assert!(!sample_indices(hint_entries.len(), super::HINT_VERIFY_SAMPLE_SIZE).contains(&1));
+        hint_entries[1].index.len += 1;
+
+        assert!(verify_hint_entries(&data_file, &hint_entries, HintVerify::Sample).unwrap());
+        assert!(!verify_hint_entries(&data_file, &hint_entries, HintVerify::Full).unwrap());
+    }
+
+    #[test]
+    fn test_sample_indices() {
+        assert_eq!(sample_indices(3, 16), vec![0, 1, 2]);
+
+        let indices = sample_indices(100, 16);
+        assert_eq!(indices.len(), 16);
+        assert_eq!(indices.first(), Some(&0));
+        assert_eq!(indices.last(), Some(&99));
+    }
+}
@@ -1,74 +1,375 @@
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Seek, SeekFrom},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
+use log::{debug, error, warn};
+
 use crate::{
+    bufio::BufReaderWithPos,
     error::{OpenError, OpenErrorKind},
-    logfile::{LogFileIterator, LogIndex},
-    utils::{data_file_path, list_generations},
-    GenerationNumber,
+    hint::{read_hint_file, verify_hint_entries, HintVerify},
+    logfile::{data_file_version, LogFileEntry, LogFileIterator, LogIndex, DATA_FILE_VERSION},
+    report::{OpenAnomaly, OpenReport},
+    transform::ValueTransforms,
+    utils::{data_file_path, hint_file_path, list_generations},
+    GenerationNumber, RustcaskBuilder,
 };
 
+/// The number of entries sampled to estimate the average entry size when the keydir is sized.
+const KEY_COUNT_SAMPLE_ENTRIES: usize = 64;
+
+/// The most keys the keydir is sized for when the number of keys is estimated.
+const MAX_ESTIMATED_KEYS: usize = 1 << 22;
+
 #[derive(Debug)]
 pub struct KeyDir {
     keydir: HashMap<Vec<u8>, KeyDirEntry>,
+
+    // The number of entries in the data files that are no longer referenced by the keydir:
+    // overwritten values, removed values, and tombstones.
+    dead_entries: u64,
+
+    // Generations whose data files couldn't be read when the store was opened.
+    // See `RustcaskBuilder::set_skip_unreadable_generations`.
+    unreadable_generations: HashSet<GenerationNumber>,
 }
 
 #[derive(Debug)]
 pub struct KeyDirEntry {
     pub data_file_gen: GenerationNumber,
     pub index: LogIndex,
+
+    // The length of the value as it's stored in the data file, after any value transforms.
+    pub value_len: u64,
+
+    // Milliseconds since the UNIX epoch after which the value expires. None if it never expires.
+    pub expires_at: Option<u64>,
+
+    // A copy of the value, kept in memory so that reads don't touch the data file.
+    // Only small values are inlined. See `RustcaskBuilder::set_inline_value_threshold`.
+    pub inline_value: Option<Box<[u8]>>,
+
+    // The number of times the key has been read. Only counted when access counting is enabled.
+    // This is atomic so that reads can count accesses while only holding the keydir read lock.
+    access_count: AtomicU64,
+}
+
+/// Where the value of a key is stored in the data files. Returned by
+/// [`crate::Rustcask::locate`], for tools that inspect or verify the data files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyLocation {
+    generation: GenerationNumber,
+    offset: u64,
+    len: u64,
+    expires_at: Option<u64>,
+}
+
+impl KeyLocation {
+    /// The generation of the data file that holds the value.
+    pub fn generation(&self) -> GenerationNumber {
+        self.generation
+    }
+
+    /// The offset of the value's entry in the data file, including the data file header.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The length of the value's entry in the data file, in bytes. This is the encoded entry,
+    /// including its header and key, rather than the length of the value.
+    // An entry is never empty, so there's no `is_empty`.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// When the value expires, in milliseconds since the UNIX epoch. None if it never expires.
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
+}
+
+impl KeyDirEntry {
+    /// Returns where the value is stored in the data files.
+    pub fn location(&self) -> KeyLocation {
+        KeyLocation {
+            generation: self.data_file_gen,
+            offset: self.index.offset,
+            len: self.index.len,
+            expires_at: self.expires_at,
+        }
+    }
+
+    /// Points the entry at a new value. Any inlined copy of the previous value is dropped.
+    fn repoint(
+        &mut self,
+        data_file: GenerationNumber,
+        log_index: LogIndex,
+        value_len: u64,
+        expires_at: Option<u64>,
+    ) {
+        self.data_file_gen = data_file;
+        self.index = log_index;
+        self.value_len = value_len;
+        self.expires_at = expires_at;
+        self.inline_value = None;
+    }
+
+    /// Counts a read of the key, returning the new access count.
+    pub fn record_access(&self) -> u64 {
+        self.access_count.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn access_count(&self) -> u64 {
+        self.access_count.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the value has expired at the given time, in milliseconds since the UNIX epoch.
+    pub fn is_expired(&self, now_millis: u64) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= now_millis)
+    }
 }
 
-// TODO [RyanStan 3-25-24] Implement hint files.
 impl KeyDir {
     /// Creates a new `KeyDir` instance by parsing the data files in the given RustCask directory.
     ///
     /// This function reads all the data files in the RustCask directory, ordered by generation number.
-    /// It populates the `KeyDir` with the key-value pairs from each data file.
+    /// It populates the `KeyDir` with the key-value pairs from each data file. If a generation has
+    /// a hint file, the hint file is read instead of the data file.
     ///
     /// # Arguments
     ///
     /// * `rustcask_dir` - The path to the RustCask directory containing the data files.
+    /// * `options` - The configuration the store is being opened with. This controls how hint
+    ///   files are verified, which data files are ignored, and which values are inlined.
+    /// * `report` - Records the problems that were worked around, such as corrupt entries.
     ///
     /// # Returns
     ///
     /// * `Ok(KeyDir)` - A `KeyDir` instance populated with the key-value pairs from the data files.
     /// * `Err(OpenError)` - An error if the RustCask directory cannot be read or parsed.
     ///     
-    pub fn new(rustcask_dir: &Path) -> Result<Self, OpenError> {
+    pub fn new(
+        rustcask_dir: &Path,
+        options: &RustcaskBuilder,
+        report: &mut OpenReport,
+    ) -> Result<Self, OpenError> {
         let mut generations: Vec<GenerationNumber> =
-            list_generations(&rustcask_dir).map_err(|err| OpenError {
+            list_generations(rustcask_dir, options.file_filter).map_err(|err| OpenError {
                 kind: OpenErrorKind::Io(err),
                 rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
             })?;
         generations.sort_unstable();
 
-        let mut keydir = KeyDir {
-            keydir: HashMap::new(),
-        };
+        let capacity = options
+            .keydir_capacity_hint
+            .unwrap_or_else(|| estimate_key_count(rustcask_dir, &generations));
+        let mut keydir = KeyDir::with_capacity(capacity);
 
         for gen in generations {
             let data_file = data_file_path(rustcask_dir, &gen);
-            populate_keydir_with_data_file(data_file, &mut keydir, gen);
+            let hint_file = hint_file_path(rustcask_dir, gen);
+
+            match data_file_version(&data_file) {
+                Ok(None | Some(DATA_FILE_VERSION)) => {}
+                Ok(Some(version)) => {
+                    return Err(OpenError {
+                        kind: OpenErrorKind::UnsupportedVersion {
+                            generation: gen,
+                            version,
+                        },
+                        rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
+                    })
+                }
+                Err(err) if options.skip_unreadable_generations => {
+                    error!(
+                        "Unable to read data file {}: {}. Skipping generation {}. \
+                        Keys whose latest value is in this generation are unavailable.",
+                        data_file.display(),
+                        err,
+                        gen
+                    );
+                    keydir.unreadable_generations.insert(gen);
+                    report.record(OpenAnomaly::UnreadableGeneration { generation: gen });
+                    continue;
+                }
+                Err(err) => {
+                    return Err(OpenError {
+                        kind: OpenErrorKind::Io(err),
+                        rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
+                    })
+                }
+            }
+
+            // Entries that the generation overwrites or removes are counted as dead entries.
+            let dead_entries_before = keydir.dead_entries;
+            if hint_file.is_file() {
+                if let Some(entries) = populate_keydir_with_hint_file(
+                    &hint_file,
+                    &data_file,
+                    &mut keydir,
+                    gen,
+                    options.hint_verify,
+                ) {
+                    debug!(
+                        "Loaded generation {} from hint file {}: {} entries, {} dead entries.",
+                        gen,
+                        hint_file.display(),
+                        entries,
+                        keydir.dead_entries - dead_entries_before
+                    );
+                    if options.inline_value_threshold > 0 {
+                        inline_values_from_data_file(
+                            &data_file,
+                            &mut keydir,
+                            gen,
+                            options.inline_value_threshold,
+                            &options.value_transforms,
+                        );
+                    }
+                    continue;
+                }
+                report.record(OpenAnomaly::HintFileRejected { generation: gen });
+            }
+            let entries = populate_keydir_with_data_file(
+                data_file.clone(),
+                &mut keydir,
+                gen,
+                options,
+                report,
+            );
+            debug!(
+                "Loaded generation {} from data file {}: {} entries, {} dead entries.",
+                gen,
+                data_file.display(),
+                entries,
+                keydir.dead_entries - dead_entries_before
+            );
         }
 
         Ok(keydir)
     }
 
     pub fn new_empty() -> Self {
+        KeyDir::with_capacity(0)
+    }
+
+    /// Creates an empty keydir with room for at least `capacity` keys.
+    pub fn with_capacity(capacity: usize) -> Self {
         KeyDir {
-            keydir: HashMap::new(),
+            keydir: HashMap::with_capacity(capacity),
+            dead_entries: 0,
+            unreadable_generations: HashSet::new(),
+        }
+    }
+
+    /// Makes room for at least `additional` more keys than the keydir holds.
+    pub fn reserve(&mut self, additional: usize) {
+        self.keydir.reserve(additional);
+    }
+
+    /// Returns the generations whose data files were skipped because they couldn't be read.
+    pub fn unreadable_generations(&self) -> &HashSet<GenerationNumber> {
+        &self.unreadable_generations
+    }
+
+    /// Sets the location and stored length of a key's most recent value, and when that value
+    /// expires. If the key was already present, its previous value is counted as dead,
+    /// and its access count is kept. Any inlined copy of the previous value is dropped.
+    pub fn set(
+        &mut self,
+        key: Vec<u8>,
+        data_file: GenerationNumber,
+        log_index: LogIndex,
+        value_len: u64,
+        expires_at: Option<u64>,
+    ) {
+        match self.keydir.entry(key) {
+            Entry::Occupied(mut entry) => {
+                entry
+                    .get_mut()
+                    .repoint(data_file, log_index, value_len, expires_at);
+                self.dead_entries += 1;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(KeyDirEntry {
+                    data_file_gen: data_file,
+                    index: log_index,
+                    value_len,
+                    expires_at,
+                    inline_value: None,
+                    access_count: AtomicU64::new(0),
+                });
+            }
         }
     }
 
-    pub fn set(&mut self, key: Vec<u8>, data_file: GenerationNumber, log_index: LogIndex) {
-        let keydir_entry = KeyDirEntry {
-            data_file_gen: data_file,
-            index: log_index,
+    /// Like `set`, but only updates a key that's already present, so that overwriting a key
+    /// doesn't copy it. Returns false, without changing the keydir, if the key isn't present.
+    pub fn overwrite(
+        &mut self,
+        key: &Vec<u8>,
+        data_file: GenerationNumber,
+        log_index: &LogIndex,
+        value_len: u64,
+        expires_at: Option<u64>,
+    ) -> bool {
+        let Some(keydir_entry) = self.keydir.get_mut(key) else {
+            return false;
         };
-        self.keydir.insert(key, keydir_entry);
+        keydir_entry.repoint(data_file, log_index.clone(), value_len, expires_at);
+        self.dead_entries += 1;
+        true
+    }
+
+    /// Records that a key's value was overwritten in place, at the same location in the data
+    /// files. Unlike `set`, the previous value isn't counted as dead, because it no longer exists.
+    /// Any inlined copy of the previous value is dropped.
+    pub fn overwrite_in_place(&mut self, key: &Vec<u8>, value_len: u64, expires_at: Option<u64>) {
+        if let Some(keydir_entry) = self.keydir.get_mut(key) {
+            keydir_entry.value_len = value_len;
+            keydir_entry.expires_at = expires_at;
+            keydir_entry.inline_value = None;
+        }
+    }
+
+    /// Points a key at the copy of its value that a merge wrote, unless the key was written or
+    /// removed since the merge read it from `from`. The key's inlined value and access count are kept.
+    pub fn relocate(
+        &mut self,
+        key: &Vec<u8>,
+        from_gen: GenerationNumber,
+        from_index: &LogIndex,
+        to_gen: GenerationNumber,
+        to_index: LogIndex,
+    ) {
+        if let Some(keydir_entry) = self.keydir.get_mut(key) {
+            if keydir_entry.data_file_gen == from_gen && keydir_entry.index == *from_index {
+                keydir_entry.data_file_gen = to_gen;
+                keydir_entry.index = to_index;
+            }
+        }
+    }
+
+    /// Keeps a copy of a key's current value in memory, if the key is present.
+    pub fn set_inline_value(&mut self, key: &Vec<u8>, value: Box<[u8]>) {
+        if let Some(keydir_entry) = self.keydir.get_mut(key) {
+            keydir_entry.inline_value = Some(value);
+        }
+    }
+
+    /// Sets the access count of a key, if the key is present.
+    pub fn set_access_count(&self, key: &Vec<u8>, access_count: u64) {
+        if let Some(keydir_entry) = self.keydir.get(key) {
+            keydir_entry
+                .access_count
+                .store(access_count, Ordering::Relaxed);
+        }
     }
 
     pub fn get(&self, key: &Vec<u8>) -> Option<&KeyDirEntry> {
@@ -77,31 +378,282 @@ impl KeyDir {
 
     /// Removes a key from the keydir, returning the entry at the key
     /// if the key was previously in the map.
+    ///
+    /// Keys are removed in response to a tombstone, so the tombstone and the removed
+    /// value are counted as dead.
     pub fn remove(&mut self, key: &Vec<u8>) -> Option<KeyDirEntry> {
-        self.keydir.remove(key)
+        self.dead_entries += 1;
+        let removed = self.keydir.remove(key);
+        if removed.is_some() {
+            self.dead_entries += 1;
+        }
+        removed
+    }
+
+    /// Returns the number of live keys.
+    pub fn len(&self) -> usize {
+        self.keydir.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keydir.is_empty()
+    }
+
+    /// Returns the number of entries in the data files that are no longer referenced by the keydir.
+    pub fn dead_entries(&self) -> u64 {
+        self.dead_entries
     }
+
+    /// Stops counting dead entries whose space has been reclaimed.
+    pub fn forget_dead_entries(&mut self, count: u64) {
+        self.dead_entries = self.dead_entries.saturating_sub(count);
+    }
+
+    /// Removes every key whose value is stored in one of the given generations.
+    /// The removed values aren't counted as dead, because their data files are gone.
+    ///
+    /// Returns the number of keys removed.
+    pub fn remove_generations(&mut self, generations: &HashSet<GenerationNumber>) -> usize {
+        let len = self.keydir.len();
+        self.keydir
+            .retain(|_, entry| !generations.contains(&entry.data_file_gen));
+        len - self.keydir.len()
+    }
+
+    /// Returns the ratio of dead entries to live keys. A merge would reclaim the space
+    /// used by the dead entries.
+    pub fn dead_ratio(&self) -> f64 {
+        if self.dead_entries == 0 {
+            return 0.0;
+        }
+        self.dead_entries as f64 / self.keydir.len() as f64
+    }
+}
+
+/// Estimates the number of keys in the data files of the given generations, so that the keydir
+/// can be sized before it's populated. The average entry size is sampled from the start of the
+/// newest data file that has entries.
+///
+/// Overwritten and removed keys are counted as well, so the estimate is capped.
+fn estimate_key_count(rustcask_dir: &Path, generations: &[GenerationNumber]) -> usize {
+    let Some((sampled_entries, sampled_bytes)) = generations.iter().rev().find_map(|gen| {
+        let log_iter = LogFileIterator::new(data_file_path(rustcask_dir, gen)).ok()?;
+        let (entries, bytes) = log_iter
+            .take(KEY_COUNT_SAMPLE_ENTRIES)
+            .fold((0, 0), |(entries, bytes), (_, index)| {
+                (entries + 1, bytes + index.len)
+            });
+        (entries > 0).then_some((entries, bytes))
+    }) else {
+        return 0;
+    };
+
+    let data_file_bytes: u64 = generations
+        .iter()
+        .filter_map(|gen| fs::metadata(data_file_path(rustcask_dir, gen)).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let average_entry_len = (sampled_bytes / sampled_entries).max(1);
+    usize::try_from(data_file_bytes / average_entry_len)
+        .unwrap_or(usize::MAX)
+        .min(MAX_ESTIMATED_KEYS)
 }
 
+/// Populates the keydir with the entries of a data file. Returns the number of entries read.
 fn populate_keydir_with_data_file(
     data_file: PathBuf,
     keydir: &mut KeyDir,
     data_file_gen: GenerationNumber,
-) {
-    let log_iter = LogFileIterator::new(data_file).unwrap_or_else(|_| {
-        panic!(
-            "Unable to create a log file iterator for generation {}. \
+    options: &RustcaskBuilder,
+    report: &mut OpenReport,
+) -> usize {
+    let mut log_iter = LogFileIterator::new(data_file)
+        .unwrap_or_else(|_| {
+            panic!(
+                "Unable to create a log file iterator for generation {}. \
             This iterator is used to populate the keydir on data store open.",
-            data_file_gen
-        )
-    });
+                data_file_gen
+            )
+        })
+        .with_prefetch(options.scan_prefetch);
 
-    for (entry, index) in log_iter {
-        if entry.value.is_none() {
+    // Values are only read if they might be inlined. The rest are skipped over.
+    let mut entries = 0;
+    for (entry, index) in log_iter.keys(options.inline_value_threshold) {
+        entries += 1;
+        let Some(value_len) = entry.value_len else {
             keydir.remove(&entry.key);
-        } else {
-            keydir.set(entry.key, data_file_gen, index);
+            continue;
+        };
+        keydir.set(
+            entry.key.clone(),
+            data_file_gen,
+            index,
+            value_len,
+            entry.expires_at,
+        );
+        if let Some(value) = entry.value.and_then(|value| {
+            inlinable_value(
+                &entry.transforms,
+                value,
+                options.inline_value_threshold,
+                &options.value_transforms,
+            )
+        }) {
+            keydir.set_inline_value(&entry.key, value);
         }
     }
+
+    for &offset in log_iter.corrupt_offsets() {
+        report.record(OpenAnomaly::CorruptEntry {
+            generation: data_file_gen,
+            offset,
+        });
+    }
+    if let Some(offset) = log_iter.truncated_offset() {
+        report.record(OpenAnomaly::TruncatedEntry {
+            generation: data_file_gen,
+            offset,
+        });
+    }
+    entries
+}
+
+/// Returns the untransformed value of a data file entry, if it should be inlined.
+/// Values are only inlined if both the stored value and the untransformed value are within
+/// the threshold, matching `Writer::set`. Values whose transforms can't be reversed are left
+/// in the data file, where reads will report the error.
+fn inlinable_value(
+    transforms: &[u8],
+    value: Vec<u8>,
+    inline_value_threshold: usize,
+    value_transforms: &ValueTransforms,
+) -> Option<Box<[u8]>> {
+    if value.len() > inline_value_threshold {
+        return None;
+    }
+    if transforms.is_empty() {
+        return Some(value.into_boxed_slice());
+    }
+    value_transforms
+        .reverse(transforms, &value)
+        .ok()
+        .filter(|value| value.len() <= inline_value_threshold)
+        .map(|value| value.into())
+}
+
+/// Inlines the small values of a generation whose keydir entries were read from its hint file.
+///
+/// Hint files don't hold values, so the values are read from the data file. Only entries that
+/// are short enough to hold a value within the threshold are read. If an entry can't be read,
+/// its value is left in the data file, where reads will find it.
+fn inline_values_from_data_file(
+    data_file: &Path,
+    keydir: &mut KeyDir,
+    data_file_gen: GenerationNumber,
+    inline_value_threshold: usize,
+    value_transforms: &ValueTransforms,
+) {
+    let candidates: Vec<(Vec<u8>, LogIndex)> = keydir
+        .keydir
+        .iter()
+        .filter(|(key, entry)| {
+            entry.data_file_gen == data_file_gen
+                && entry.index.len
+                    <= LogFileEntry::max_encoded_len(
+                        key.len(),
+                        inline_value_threshold,
+                        value_transforms.len(),
+                    )
+        })
+        .map(|(key, entry)| (key.clone(), entry.index.clone()))
+        .collect();
+    if candidates.is_empty() {
+        return;
+    }
+
+    let result = File::open(data_file).and_then(|file| {
+        let mut reader = BufReaderWithPos::new(file)?;
+        for (key, index) in candidates {
+            reader.seek(SeekFrom::Start(index.offset))?;
+            let entry = LogFileEntry::decode_from(&mut reader)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if let Some(value) = entry.value.and_then(|value| {
+                inlinable_value(
+                    &entry.transforms,
+                    value,
+                    inline_value_threshold,
+                    value_transforms,
+                )
+            }) {
+                keydir.set_inline_value(&key, value);
+            }
+        }
+        Ok(())
+    });
+    if let Err(err) = result {
+        warn!(
+            "Unable to inline values from data file {}: {}. They will be read from disk instead.",
+            data_file.display(),
+            err
+        );
+    }
+}
+
+/// Populates the keydir with the entries of a hint file.
+///
+/// Returns the number of entries read, or `None`, without modifying the keydir, if the
+/// hint file can't be read or fails verification. The caller should then fall back to
+/// the data file.
+fn populate_keydir_with_hint_file(
+    hint_file: &Path,
+    data_file: &Path,
+    keydir: &mut KeyDir,
+    data_file_gen: GenerationNumber,
+    hint_verify: HintVerify,
+) -> Option<usize> {
+    let hint_entries = match read_hint_file(hint_file) {
+        Ok(hint_entries) => hint_entries,
+        Err(err) => {
+            warn!(
+                "Unable to read hint file {}: {}. Falling back to the data file.",
+                hint_file.display(),
+                err
+            );
+            return None;
+        }
+    };
+
+    match verify_hint_entries(data_file, &hint_entries, hint_verify) {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!(
+                "Hint file {} failed verification. Falling back to the data file.",
+                hint_file.display()
+            );
+            return None;
+        }
+        Err(err) => {
+            warn!(
+                "Unable to verify hint file {}: {}. Falling back to the data file.",
+                hint_file.display(),
+                err
+            );
+            return None;
+        }
+    }
+
+    let entries = hint_entries.len();
+    for hint_entry in hint_entries {
+        keydir.set(
+            hint_entry.key,
+            data_file_gen,
+            hint_entry.index,
+            hint_entry.value_len,
+            hint_entry.expires_at,
+        );
+    }
+    Some(entries)
 }
 
 impl<'a> IntoIterator for &'a KeyDir {
@@ -124,16 +676,124 @@ impl IntoIterator for KeyDir {
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, io::Write};
+    use std::{
+        fs::File,
+        io::Write,
+        sync::{Mutex, Once},
+    };
+
+    use log::{LevelFilter, Log, Metadata, Record};
 
     use tempfile::TempDir;
 
     use crate::{
-        logfile::{LogFileEntry, LogIndex},
+        logfile::{
+            write_data_file_header, LogFileEntry, LogFileIterator, LogIndex, DATA_FILE_HEADER_LEN,
+        },
+        report::OpenReport,
         utils::data_file_path,
+        RustcaskBuilder,
     };
 
-    use super::{populate_keydir_with_data_file, KeyDir};
+    use super::{estimate_key_count, populate_keydir_with_data_file, KeyDir};
+
+    /// Captures the log records emitted by the tests in this module.
+    struct TestLogger {
+        records: Mutex<Vec<String>>,
+    }
+
+    impl Log for TestLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.records
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", record.level(), record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static TEST_LOGGER: TestLogger = TestLogger {
+        records: Mutex::new(Vec::new()),
+    };
+
+    fn install_test_logger() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&TEST_LOGGER).unwrap();
+            log::set_max_level(LevelFilter::Debug);
+        });
+    }
+
+    fn write_data_file(path: &std::path::Path, keys: &[&str]) {
+        let mut data_file = File::create(path).unwrap();
+        write_data_file_header(&mut data_file).unwrap();
+        for key in keys {
+            let entry = LogFileEntry {
+                key: key.as_bytes().to_vec(),
+                value: Some("value".as_bytes().to_vec()),
+                expires_at: None,
+                transforms: Vec::new(),
+            };
+            data_file.write_all(&entry.encode().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_dead_entry_accounting() {
+        let mut keydir = KeyDir::new_empty();
+        let index = || LogIndex { offset: 0, len: 1 };
+
+        keydir.set("a".as_bytes().to_vec(), 0, index(), 0, None);
+        keydir.set("b".as_bytes().to_vec(), 0, index(), 0, None);
+        assert_eq!(keydir.dead_entries(), 0);
+        assert_eq!(keydir.dead_ratio(), 0.0);
+
+        // Overwriting a key leaves its previous value dead
+        keydir.set("a".as_bytes().to_vec(), 0, index(), 0, None);
+        assert_eq!(keydir.dead_entries(), 1);
+        assert_eq!(keydir.dead_ratio(), 0.5);
+
+        // Overwrite only updates keys that are present
+        assert!(!keydir.overwrite(&"c".as_bytes().to_vec(), 1, &index(), 0, None));
+        assert!(keydir.overwrite(&"a".as_bytes().to_vec(), 1, &index(), 0, None));
+        assert_eq!(
+            keydir.get(&"a".as_bytes().to_vec()).unwrap().data_file_gen,
+            1
+        );
+        assert_eq!(keydir.dead_entries(), 2);
+        assert_eq!(keydir.len(), 2);
+
+        // Removing a key leaves both its value and the tombstone dead
+        keydir.remove(&"b".as_bytes().to_vec());
+        assert_eq!(keydir.dead_entries(), 4);
+        assert_eq!(keydir.len(), 1);
+
+        keydir.remove(&"a".as_bytes().to_vec());
+        assert_eq!(keydir.dead_ratio(), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_access_count_kept_on_overwrite() {
+        let mut keydir = KeyDir::new_empty();
+        let key = "key".as_bytes().to_vec();
+        keydir.set(key.clone(), 0, LogIndex { offset: 0, len: 1 }, 0, None);
+
+        let entry = keydir.get(&key).unwrap();
+        assert_eq!(entry.record_access(), 1);
+        assert_eq!(entry.record_access(), 2);
+
+        keydir.set(key.clone(), 1, LogIndex { offset: 8, len: 1 }, 0, None);
+        assert_eq!(keydir.get(&key).unwrap().access_count(), 2);
+
+        keydir.remove(&key);
+        keydir.set(key.clone(), 1, LogIndex { offset: 16, len: 1 }, 0, None);
+        assert_eq!(keydir.get(&key).unwrap().access_count(), 0);
+    }
 
     #[test]
     fn test_populate_keydir_with_data_file() {
@@ -148,18 +808,27 @@ mod tests {
         let data_file_entry = LogFileEntry {
             key: key.clone(),
             value: Some(value.clone()),
+            expires_at: None,
+            transforms: Vec::new(),
         };
 
-        let encoded = bincode::serialize(&data_file_entry).unwrap();
+        let encoded = data_file_entry.encode().unwrap();
 
+        write_data_file_header(&mut data_file).unwrap();
         data_file.write_all(&encoded).unwrap();
         data_file.flush().unwrap();
 
         let mut keydir = KeyDir::new_empty();
-        populate_keydir_with_data_file(data_file_path, &mut keydir, generation);
+        populate_keydir_with_data_file(
+            data_file_path,
+            &mut keydir,
+            generation,
+            &RustcaskBuilder::default(),
+            &mut OpenReport::default(),
+        );
 
         let entry = keydir.get(&key);
-        assert!(matches!(entry, Some(_)));
+        assert!(entry.is_some());
 
         let entry = entry.unwrap();
 
@@ -167,9 +836,138 @@ mod tests {
         assert_eq!(
             entry.index,
             LogIndex {
-                offset: 0,
+                offset: DATA_FILE_HEADER_LEN,
                 len: encoded.len() as u64,
             }
         );
     }
+
+    #[test]
+    fn test_populate_keydir_skipping_values_matches_full_scan() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_file_path = data_file_path(temp_dir.path(), &0);
+        let mut data_file = File::create(&data_file_path).unwrap();
+        write_data_file_header(&mut data_file).unwrap();
+        let entries = [
+            ("small", Some(b"value".to_vec())),
+            ("large", Some(vec![b'x'; 4096])),
+            ("removed", Some(b"value".to_vec())),
+            ("removed", None),
+            ("large", Some(vec![b'y'; 8192])),
+        ];
+        for (key, value) in &entries {
+            let entry = LogFileEntry {
+                key: key.as_bytes().to_vec(),
+                value: value.clone(),
+                expires_at: None,
+                transforms: Vec::new(),
+            };
+            data_file.write_all(&entry.encode().unwrap()).unwrap();
+        }
+        drop(data_file);
+
+        // The keydir that a scan of every entry, values included, produces.
+        let mut expected = KeyDir::new_empty();
+        for (entry, index) in LogFileIterator::new(data_file_path.clone()).unwrap() {
+            match entry.value {
+                None => {
+                    expected.remove(&entry.key);
+                }
+                Some(value) => {
+                    expected.set(entry.key, 0, index, value.len() as u64, entry.expires_at)
+                }
+            }
+        }
+
+        for threshold in [0, 16] {
+            let mut keydir = KeyDir::new_empty();
+            populate_keydir_with_data_file(
+                data_file_path.clone(),
+                &mut keydir,
+                0,
+                &RustcaskBuilder::default().set_inline_value_threshold(threshold),
+                &mut OpenReport::default(),
+            );
+            assert_eq!(keydir.len(), expected.len());
+            assert_eq!(keydir.dead_entries(), expected.dead_entries());
+            for key in ["small", "large"] {
+                let key = key.as_bytes().to_vec();
+                let (entry, expected_entry) =
+                    (keydir.get(&key).unwrap(), expected.get(&key).unwrap());
+                assert_eq!(entry.location(), expected_entry.location());
+                assert_eq!(entry.value_len, expected_entry.value_len);
+            }
+            // Only the small value is read, and only when it can be inlined.
+            let small = keydir.get(&b"small".to_vec()).unwrap();
+            assert_eq!(small.inline_value.is_some(), threshold > 0);
+            assert!(keydir
+                .get(&b"large".to_vec())
+                .unwrap()
+                .inline_value
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_new_logs_each_generation() {
+        install_test_logger();
+        let temp_dir = TempDir::new().unwrap();
+        write_data_file(&data_file_path(temp_dir.path(), &0), &["a", "b"]);
+        write_data_file(&data_file_path(temp_dir.path(), &1), &["a", "c", "d"]);
+
+        KeyDir::new(
+            temp_dir.path(),
+            &RustcaskBuilder::default(),
+            &mut OpenReport::default(),
+        )
+        .unwrap();
+
+        // Other tests may log concurrently, so only look at this store's records.
+        let records: Vec<String> = TEST_LOGGER
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| record.contains(&*temp_dir.path().to_string_lossy()))
+            .cloned()
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                format!(
+                    "DEBUG Loaded generation 0 from data file {}: 2 entries, 0 dead entries.",
+                    data_file_path(temp_dir.path(), &0).display()
+                ),
+                format!(
+                    "DEBUG Loaded generation 1 from data file {}: 3 entries, 1 dead entries.",
+                    data_file_path(temp_dir.path(), &1).display()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_estimate_key_count() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(estimate_key_count(temp_dir.path(), &[]), 0);
+
+        for generation in 0..2 {
+            let mut data_file = File::create(data_file_path(temp_dir.path(), &generation)).unwrap();
+            write_data_file_header(&mut data_file).unwrap();
+            for i in 0..100 {
+                let entry = LogFileEntry {
+                    key: format!("key-{:03}", i).into_bytes(),
+                    value: Some("value".as_bytes().to_vec()),
+                    expires_at: None,
+                    transforms: Vec::new(),
+                };
+                data_file.write_all(&entry.encode().unwrap()).unwrap();
+            }
+        }
+        // The newest data file is empty, so the entries are sampled from the one before it.
+        let mut empty_data_file = File::create(data_file_path(temp_dir.path(), &2)).unwrap();
+        write_data_file_header(&mut empty_data_file).unwrap();
+
+        assert_eq!(estimate_key_count(temp_dir.path(), &[0, 1, 2]), 200);
+    }
 }
@@ -19,22 +19,44 @@
 //! store.get(&key);
 //! ```
 
+pub use cache::CacheEviction;
+pub use checkpoint::{CheckpointedLogIter, LogCheckpoint};
+pub use clock::{Clock, SystemClock};
+use corruption::CorruptionHandler;
+pub use corruption::{CorruptionEvent, CorruptionKind, OnCorruption};
+pub use counters::Counters;
 use error::{
-    GetError, MergeError, MergeErrorKind, OpenError, OpenErrorKind, RemoveError,
+    GetError, MergeError, MergeErrorKind, MigrateError, OpenError, OpenErrorKind, RemoveError,
     SetError,
 };
+pub use hint::HintVerify;
 use keydir::KeyDir;
-use logfile::LogFileEntry;
-use readers::Readers;
-
-use log::{info, trace};
+pub use keydir::KeyLocation;
+use lock::DirLock;
+pub use logfile::LogFileEntry;
+use logfile::LogFileIterator;
+pub use read_only::ReadOnlyRustcask;
+use readers::{Readers, ValueLocation};
+pub use report::{GenerationVerifyReport, OpenAnomaly, OpenReport};
+pub use transform::ValueTransform;
+use transform::ValueTransforms;
+
+use log::{info, trace, warn};
 use writer::Writer;
 
+use std::sync::mpsc::{self, Receiver};
 use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
 use std::{
-    io::{Seek, SeekFrom},
+    fs,
+    io::{self, Write},
     path::{Path, PathBuf},
 };
+use utils::{
+    data_file_path, include_all_files, is_data_file, is_hint_file, list_generations, move_file,
+    parse_generation_number, retired_file_path, FileFilter,
+};
 
 use crate::error::GetErrorKind;
 
@@ -42,18 +64,44 @@ use crate::error::GetErrorKind;
 pub mod error;
 
 mod bufio;
+mod cache;
+mod checkpoint;
+mod clock;
+mod corruption;
+mod counters;
+mod hint;
 mod keydir;
+mod lock;
 mod logfile;
+mod meta;
+mod migrate;
+mod pins;
+mod rate_limit;
+mod read_only;
 mod readers;
+mod report;
+mod transform;
 mod utils;
+#[cfg(test)]
+mod workload;
 mod writer;
 
-type GenerationNumber = u64;
+/// Identifies a data file. Data files are numbered in the order they were created,
+/// and the data file with the highest generation is the active data file.
+pub type GenerationNumber = u64;
+
+/// A key and the result of reading its value, as sent by [`Rustcask::get_batch_stream`].
+pub type StreamedGet = (Vec<u8>, Result<Option<Vec<u8>>, GetError>);
+
+// The number of results `get_batch_stream` reads ahead of the receiver.
+const STREAM_CHANNEL_CAPACITY: usize = 64;
 
 const MAX_DATA_FILE_SIZE: u64 = 2 * 1024 * 1024 * 1024; // 2 GiB
 
+const AUTO_MERGE_RATIO: f64 = 1.0;
+
 /// A handle to interact with a Rustcask storage engine.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Rustcask {
     // Writes to active data file. Performs data file rotation as needed.
     writer: Arc<Mutex<Writer>>,
@@ -63,9 +111,37 @@ pub struct Rustcask {
 
     pub(crate) keydir: Arc<RwLock<KeyDir>>,
 
-    sync_mode: bool,
-
     pub(crate) directory: Arc<PathBuf>,
+
+    // The configuration this store was opened with
+    options: RustcaskBuilder,
+
+    // Held while merging, and by operations that can't run concurrently with a merge.
+    // Shared with the writer.
+    merge_lock: Arc<Mutex<()>>,
+
+    // Shared by every writable handle. Keeps other stores from opening the directory
+    // for writing until the last writable handle is dropped.
+    dir_lock: Arc<DirLock>,
+}
+
+/// Cloning a handle shares the store with the original: writes, the keydir, the value cache,
+/// counters, generation pins, and merges are shared by every clone, so each handle sees writes
+/// made through the others. Each clone opens its own data file readers.
+impl Clone for Rustcask {
+    fn clone(&self) -> Self {
+        // Every field that holds shared state must be cloned by reference, not duplicated.
+        // `Readers` shares the cache, counters, and pins between its clones.
+        Self {
+            writer: self.writer.clone(),
+            readers: self.readers.clone(),
+            keydir: self.keydir.clone(),
+            directory: self.directory.clone(),
+            options: self.options.clone(),
+            merge_lock: self.merge_lock.clone(),
+            dir_lock: self.dir_lock.clone(),
+        }
+    }
 }
 
 impl Rustcask {
@@ -92,6 +168,8 @@ impl Rustcask {
     ///
     /// * The `LogFileEntry` could not be serialized (`SetErrorKind::Serialize`).
     /// * There was an error writing to the active data file.
+    /// * The write rate limit was exceeded and rate limiting is non-blocking (`SetErrorKind::RateLimited`).
+    /// * The value was written, but the full active data file couldn't be rotated (`SetErrorKind::Rotation`).
     ///
     /// # Panics
     ///
@@ -110,6 +188,95 @@ impl Rustcask {
         writer.set(key, value)
     }
 
+    /// Rewrites the current value of a key to the active data file, as if it had just been set
+    /// again, and returns whether the key was found. The value keeps its expiry time.
+    ///
+    /// A frequently overwritten key leaves stale values behind in every generation it was written
+    /// to, and a rarely written key can keep an otherwise dead generation alive. Compacting the
+    /// keys that are still live in an old generation lets it be dropped with
+    /// [`Rustcask::drop_generation`], without a full merge.
+    ///
+    /// Expired keys aren't found, and neither are keys whose value is corrupt when the store is
+    /// configured with `OnCorruption::SkipReturnNone`. A value that's already in the active data
+    /// file isn't rewritten.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SetError` with `SetErrorKind::Read` if the current value couldn't be read.
+    /// Otherwise, a `SetError` is returned under the same conditions as [`Rustcask::set`].
+    pub fn compact_key(&mut self, key: &Vec<u8>) -> Result<bool, SetError> {
+        trace!(
+            "Compact key called with key (as UTF 8) {}",
+            String::from_utf8_lossy(key)
+        );
+
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+        writer.compact_key(key)
+    }
+
+    /// Inserts a key-value pair, choosing for this write alone whether it's synced to disk
+    /// before returning.
+    ///
+    /// With `durable` set to true, the write is synced even if sync mode is off, which suits
+    /// critical writes in a store that's otherwise written quickly. With `durable` set to false,
+    /// the write isn't synced even if sync mode is on, which suits bulk writes that can be
+    /// followed by a single call to [`Rustcask::sync`]. See [`RustcaskBuilder::set_sync_mode`].
+    ///
+    /// # Errors
+    ///
+    /// This function returns a `SetError` under the same conditions as [`Rustcask::set`].
+    pub fn set_durable(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        durable: bool,
+    ) -> Result<(), SetError> {
+        trace!(
+            "Set durable ({}) called with key (as UTF 8) {}",
+            durable,
+            String::from_utf8_lossy(&key)
+        );
+
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+        writer.set_durable(key, value, durable)
+    }
+
+    /// Inserts a key-value pair that expires once `ttl` has passed.
+    ///
+    /// Expiry is measured by the store's clock. See [`RustcaskBuilder::set_clock`].
+    /// Expired values are no longer returned by `get`, and their space is reclaimed by the next merge.
+    /// Setting the key again replaces the expiry along with the value.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a `SetError` under the same conditions as [`Rustcask::set`].
+    pub fn set_with_ttl(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), SetError> {
+        trace!(
+            "Set with ttl called with key (as UTF 8) {}",
+            String::from_utf8_lossy(&key)
+        );
+
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+        writer.set_with_ttl(key, value, ttl)
+    }
+
     /// Returns a reference to the value corresponding to the key.
     ///
     /// # Arguments
@@ -127,8 +294,14 @@ impl Rustcask {
     /// This function may return a `GetError` with the following variants:
     ///
     /// * `GetErrorKind::Io(err)` - An I/O error occurred while reading the data file.
-    /// * `GetErrorKind::Deserialize(err)` - An error occurred while deserializing the data from the data file.
-    pub fn get<'a>(&'a mut self, key: &'a Vec<u8>) -> Result<Option<Vec<u8>>, GetError<'a>> {
+    /// * `GetErrorKind::Deserialize(err)` - An error occurred while deserializing the data from the data file, or its checksums didn't match.
+    /// * `GetErrorKind::RateLimited` - The read rate limit was exceeded and rate limiting is non-blocking.
+    /// * `GetErrorKind::IndexOutOfRange { .. }` - The keydir entry for the key points past the end of its data file.
+    /// * `GetErrorKind::KeyMismatch { .. }` - The keydir entry for the key points to an entry for a different key.
+    ///
+    /// Errors for corrupt values can instead be turned into `Ok(None)` or a panic with
+    /// [`RustcaskBuilder::set_on_read_corruption`].
+    pub fn get(&mut self, key: &Vec<u8>) -> Result<Option<Vec<u8>>, GetError> {
         trace!(
             "Get called with key (as UTF 8) {}",
             String::from_utf8_lossy(key)
@@ -137,40 +310,195 @@ impl Rustcask {
             .keydir
             .read()
             .expect("Another thread panicked while holding the keydir lock. Panicking.");
-        let keydir_entry = keydir.get(key);
-        if keydir_entry.is_none() {
-            return Ok(None);
-        }
-        let keydir_entry = keydir_entry.unwrap();
-
-        let reader = self
-            .readers
-            .get_data_file_reader(keydir_entry.data_file_gen);
-
-        // TODO [RyanStan 3-25-24] This code is duplicated in remove. Extract it into a separate function.
-        let log_index = &keydir_entry.index;
-        reader
-            .seek(SeekFrom::Start(log_index.offset))
-            .map_err(|err| GetError {
-                kind: GetErrorKind::Io(err),
-                key,
-            })?;
-
-        let data_file_entry: LogFileEntry =
-            bincode::deserialize_from(reader).map_err(|err| GetError {
-                kind: GetErrorKind::Deserialize(err),
-                key,
-            })?;
+        self.readers.get(&keydir, key)
+    }
 
-        assert_eq!(
-            &data_file_entry.key, key,
-            "The deserialized entries key does not match the key passed to get. The data store could corrupted."
+    /// Reads the value corresponding to the key into `buf`, replacing its contents.
+    /// Returns whether the key was found. If it wasn't, `buf` is left untouched.
+    ///
+    /// Unlike [`Rustcask::get`], this doesn't allocate a new `Vec` for every value.
+    /// Reusing the same buffer across reads avoids allocating once the buffer is large enough.
+    ///
+    /// # Errors
+    ///
+    /// See [`Rustcask::get`].
+    pub fn get_into(&mut self, key: &Vec<u8>, buf: &mut Vec<u8>) -> Result<bool, GetError> {
+        trace!(
+            "Get into called with key (as UTF 8) {}",
+            String::from_utf8_lossy(key)
         );
+        let keydir = self
+            .keydir
+            .read()
+            .expect("Another thread panicked while holding the keydir lock. Panicking.");
+        self.readers.get_into(&keydir, key, buf)
+    }
+
+    /// Returns the values corresponding to several keys, in the same order as the keys.
+    /// A value is `None` if its key isn't found in the data store.
+    ///
+    /// The keys are read from a consistent view of the store: no writes are applied
+    /// while the values are being read.
+    ///
+    /// # Errors
+    ///
+    /// Returns the `GetError` of the first key whose value couldn't be read.
+    /// See [`Rustcask::get`].
+    pub fn get_many(&mut self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, GetError> {
+        let keydir = self
+            .keydir
+            .read()
+            .expect("Another thread panicked while holding the keydir lock. Panicking.");
+        keys.iter()
+            .map(|key| self.readers.get(&keydir, key))
+            .collect()
+    }
+
+    /// Like [`Rustcask::get_many`], but the data files are read in the order the values are
+    /// stored in them, instead of in the order of the keys. The values are still returned
+    /// in the order of the keys.
+    ///
+    /// Reading in storage order minimizes seeking, which speeds up large batches of reads
+    /// from spinning disks or from data files that aren't in the operating system's page cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns the `GetError` of the first value that couldn't be read, in storage order.
+    /// See [`Rustcask::get`].
+    pub fn get_many_sorted(&mut self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, GetError> {
+        let keydir = self
+            .keydir
+            .read()
+            .expect("Another thread panicked while holding the keydir lock. Panicking.");
+        self.readers.get_many_sorted(&keydir, keys)
+    }
+
+    /// Reads the values of several keys into the value cache, so that reading them later doesn't
+    /// read the data files. This lets an application load a known working set ahead of
+    /// a latency-sensitive phase.
+    ///
+    /// The values are read like [`Rustcask::get_many_sorted`] reads them, and then discarded.
+    /// Keys that aren't found are skipped. Warmed values can still be evicted by later reads.
+    /// Does nothing if the value cache is disabled.
+    /// See [`RustcaskBuilder::set_value_cache_capacity`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the `GetError` of the first value that couldn't be read, in storage order.
+    /// See [`Rustcask::get`].
+    pub fn warm_cache(&self, keys: &[Vec<u8>]) -> Result<(), GetError> {
+        if self.options.value_cache_capacity == 0 {
+            return Ok(());
+        }
+        let keydir = self
+            .keydir
+            .read()
+            .expect("Another thread panicked while holding the keydir lock. Panicking.");
+        self.readers.clone().get_many_sorted(&keydir, keys)?;
+        Ok(())
+    }
+
+    /// Returns a channel that the values of several keys are streamed into, as
+    /// `(key, result)` pairs. A value is `None` if its key isn't found in the data store.
+    ///
+    /// The values are read by a background thread, in the order they're stored in the
+    /// data files rather than in the order of the keys. Keys whose values don't need to be
+    /// read from disk are sent first. The channel is closed once every key has been sent,
+    /// and the background thread stops early if the receiver is dropped.
+    ///
+    /// The keydir is only locked while the keys are looked up, so writes and merges aren't
+    /// blocked while the values are streamed. The values are the ones the keys had when this
+    /// function was called. The data files they're read from are pinned, so a merge that
+    /// replaces them deletes them only once the stream is done with them.
+    ///
+    /// # Errors
+    ///
+    /// A key whose value couldn't be read is sent with its `GetError`. See [`Rustcask::get`].
+    pub fn get_batch_stream(&self, keys: Vec<Vec<u8>>) -> Receiver<StreamedGet> {
+        let (sender, receiver) = mpsc::sync_channel(STREAM_CHANNEL_CAPACITY);
+
+        let mut found = Vec::new();
+        let mut reads = Vec::new();
+        let pins = {
+            let keydir = self
+                .keydir
+                .read()
+                .expect("Another thread panicked while holding the keydir lock. Panicking.");
+            for key in keys {
+                self.readers.counters.record_get();
+                match self.readers.locate_value(&keydir, &key) {
+                    ValueLocation::Missing => found.push((key, None)),
+                    ValueLocation::Inline(value) => {
+                        let value = value.to_vec();
+                        found.push((key, Some(value)));
+                    }
+                    ValueLocation::DataFile {
+                        data_file_gen,
+                        index,
+                        access_count,
+                    } => reads.push((key, data_file_gen, index, access_count)),
+                }
+            }
+            // The generations are pinned while the keydir lock is held,
+            // so that a merge can't delete them first.
+            let generations = reads.iter().map(|(_, gen, _, _)| *gen).collect();
+            self.readers.pins.pin(generations, self.directory.clone())
+        };
+        reads.sort_by_key(|(_, gen, index, _)| (*gen, index.offset));
+
+        let mut readers = self.readers.clone();
+        thread::spawn(move || {
+            for (key, value) in found {
+                if sender.send((key, Ok(value))).is_err() {
+                    return;
+                }
+            }
+            for (key, data_file_gen, index, access_count) in reads {
+                let mut value = Vec::new();
+                let result = readers
+                    .read_value(&key, data_file_gen, &index, access_count, &mut value)
+                    .map(|read| read.then_some(value));
+                if sender.send((key, result)).is_err() {
+                    return;
+                }
+            }
+            // Unpin before closing the channel, so that generations deleted while the stream
+            // was running are gone by the time the receiver sees the end of the stream.
+            drop(pins);
+            drop(sender);
+        });
+        receiver
+    }
+
+    /// Inserts a UTF-8 key-value pair into Rustcask.
+    ///
+    /// This is a convenience wrapper around [`Rustcask::set`] for string keys and values.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a `SetError` under the same conditions as [`Rustcask::set`].
+    pub fn set_str(&mut self, key: &str, value: &str) -> Result<(), SetError> {
+        self.set(key.as_bytes().to_vec(), value.as_bytes().to_vec())
+    }
 
-        Ok(Some(data_file_entry.value.expect(
-            "We returned a tombstone value from get. We should have instead returned None. 
-            The data store may not be corrupted - this indicates a programming bug.",
-        )))
+    /// Returns the value corresponding to a UTF-8 key, as a `String`.
+    ///
+    /// This is a convenience wrapper around [`Rustcask::get`] for string keys and values.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a `GetError` under the same conditions as [`Rustcask::get`].
+    /// Additionally, it returns a `GetError` with `GetErrorKind::InvalidUtf8` if the stored
+    /// value is not valid UTF-8.
+    pub fn get_str(&mut self, key: &str) -> Result<Option<String>, GetError> {
+        let key = key.as_bytes().to_vec();
+        match self.get(&key)? {
+            None => Ok(None),
+            Some(value) => String::from_utf8(value).map(Some).map_err(|err| GetError {
+                kind: GetErrorKind::InvalidUtf8(err),
+                key,
+            }),
+        }
     }
 
     /// Removes a key-value pair from the database.
@@ -196,6 +524,8 @@ impl Rustcask {
     ///
     /// * There was an I/O error seeking or reading from the data file (`RemoveErrorKind::Io`).
     /// * There was an error deserializing the log entry from the data file (`RemoveErrorKind::Deserialize`).
+    /// * The write rate limit was exceeded and rate limiting is non-blocking (`RemoveErrorKind::RateLimited`).
+    /// * The key was removed, but the full active data file couldn't be rotated (`RemoveErrorKind::Rotation`).
     ///
     /// # Panics
     ///
@@ -216,7 +546,7 @@ impl Rustcask {
     /// Compacts the rustcask directory be writing active key-value pairs
     /// to a new set of data files, and removes old data files which may have contained
     /// dead values.
-    /// 
+    ///
     /// # Errors
     ///
     /// This function may return a `MergeError` with the following variants:
@@ -224,161 +554,1528 @@ impl Rustcask {
     /// * `MergeErrorKind::OutsideMergeWindow` - The merge operation was attempted outside of the allowed merge window.
     ///   The `merge_generation` field in this case indicates the next generation number when a merge will be allowed.
     /// * `MergeErrorKind::Io(err)` - An I/O error occurred while reading or writing data files during the merge operation.
-    /// 
-    /// Reads can be performed concurrently with merges. However, writes will be blocked
-    /// until the merge is complete.
+    ///
+    /// Reads and writes can be performed concurrently with merges. When the merge starts, new
+    /// writes move to a fresh active data file, and the live entries of the older data files
+    /// are copied without holding the writer lock. The writer lock is only taken again briefly
+    /// at the end, to point the keydir at the merged data files. Keys that are written or
+    /// removed while the merge is running keep their new values.
+    ///
+    /// Only one merge runs at a time. A merge started while another is running waits for it.
+    ///
+    /// Every merge writes a hint file for each merged data file, and a fresh active data file
+    /// with its own header. A merge only shrinks the directory if it drops more dead entries
+    /// than these files take up, so merging a directory with little garbage can grow it.
+    ///
+    /// If the extra disk space used by merges is limited, the merge runs in steps that each
+    /// replace a few of the oldest generations. See [`RustcaskBuilder::set_merge_max_extra_bytes`].
     pub fn merge(&mut self) -> Result<(), MergeError> {
         // TODO [RyanStan 07/08/24] Instead of relying on the user to call merge,
         //   the open function should spawn a background thread that performs merging based on
         //   a configured interval.
 
-        // Locking the writer prevents concurrent writes
-        let mut writer = self
-            .writer
+        let merge_lock = self.merge_lock.clone();
+        let _merging = merge_lock
             .lock()
-            .expect("Another thread crashed while holding the writer lock. Panicking.");
-
-        if !writer.can_merge() {
-            return Err(MergeError {
-                kind: MergeErrorKind::OutsideMergeWindow,
-                merge_generation: writer.get_active_generation() + 1,
-            });
+            .expect("Another thread crashed while holding the merge lock. Panicking.");
+
+        let (mut progress, mut merge_job) = {
+            let mut writer = self
+                .writer
+                .lock()
+                .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+            if !writer.can_merge() {
+                return Err(MergeError {
+                    kind: MergeErrorKind::OutsideMergeWindow,
+                    merge_generation: writer.get_active_generation() + 1,
+                });
+            }
+
+            let mut progress = writer.begin_merge();
+            let merge_job = writer.start_merge(&mut progress)?;
+            (progress, merge_job)
+        };
+
+        // The merge runs in steps if its extra disk space is limited.
+        // See `RustcaskBuilder::set_merge_max_extra_bytes`.
+        loop {
+            merge_job.copy_live_entries(&mut self.readers)?;
+
+            let mut writer = self
+                .writer
+                .lock()
+                .expect("Another thread crashed while holding the writer lock. Panicking.");
+            writer.finish_merge(merge_job)?;
+            if progress.is_done() {
+                break;
+            }
+            merge_job = writer.start_merge(&mut progress)?;
         }
 
-        writer.merge()?;
-
         // TODO [RyanStan 07/17/24] Output stats about the number of bytes saved.
-        info!("Merged data files.");
+        info!("Merged data files in {}.", self.directory.to_string_lossy());
 
         Ok(())
     }
 
-    // Get active generation and get active data file size are for testing
-    fn get_active_generation(&self) -> GenerationNumber {
-        let writer = self.writer.lock().expect(
-            "Another thread crashed while holding the writer lock. \
-                Panicking because the write lock is required to get the active generation.",
-        );
-        writer.get_active_generation()
+    /// Rewrites the data files in a rustcask directory that were written by an older version
+    /// of rustcask, so that the directory can be opened by this version.
+    ///
+    /// `open` returns an `OpenErrorKind::UnsupportedVersion` error for directories that need
+    /// to be migrated. Data files that are already in the current format are left untouched.
+    /// The directory is locked while it's being migrated, so it can't be open in the meantime.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a `MigrateError` if:
+    ///
+    /// * There was an I/O error reading or rewriting a data file.
+    /// * An entry in an older data file couldn't be deserialized.
+    /// * A data file was written by a newer version of rustcask.
+    /// * The directory is open, in this process or another one. The error is a
+    ///   `MigrateErrorKind::Io` error of kind `WouldBlock`.
+    pub fn migrate(rustcask_dir: &Path) -> Result<(), MigrateError> {
+        migrate::migrate_directory(rustcask_dir)
     }
 
-    fn get_active_data_file_size(&self) -> u64 {
-        let writer = self.writer.lock().expect(
-            "Another thread crashed while holding the writer lock. \
-                Panicking because the write lock is required to get the active data file size.",
-        );
-        writer.get_active_data_file_size()
+    /// Syncs the active data file to disk, so that everything written so far is durable.
+    ///
+    /// `set` and `remove` flush each write to the operating system before returning, so a
+    /// write survives the process crashing. Unless sync mode is enabled, the operating system
+    /// may still hold the write in memory, where it's lost if the machine loses power. `sync`
+    /// waits until the operating system has written the active data file to disk.
+    ///
+    /// Earlier data files are synced when they're rotated out, so only the active data file
+    /// needs to be synced.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` if the active data file can't be synced.
+    pub fn sync(&mut self) -> Result<(), io::Error> {
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+        writer.sync()
     }
-}
 
-/// Simplifies configuration and creation of Rustcask instances.
-/// 
-/// # Example
-/// ```
-/// # use rustcask::Rustcask;
-/// # use tempfile::TempDir;
-/// # let temp_dir = TempDir::new().unwrap();
-/// # let rustcask_dir = temp_dir.path();
-/// let store = Rustcask::builder()
-///     .set_sync_mode(true)
-///     .open(rustcask_dir);
-/// ```
-pub struct RustcaskBuilder {
-    max_data_file_size: u64,
+    /// Writes a snapshot of every live key-value pair to `out`, and returns the number of
+    /// pairs written.
+    ///
+    /// The snapshot is written in the data file format, so it can be saved as `0.rustcask.data`
+    /// in an empty directory and opened as a store.
+    ///
+    /// Writes are blocked while the snapshot is taken. The active data file is flushed before
+    /// it's scanned, so the snapshot includes every write that completed before `export`
+    /// was called.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` if a data file can't be read, or if writing
+    /// to `out` fails.
+    pub fn export<W: Write>(&mut self, out: W) -> Result<u64, io::Error> {
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
 
-    /// When sync mode is true, writes to the data file
-    /// are fsync'ed before returning to the user.
-    /// This guarantees that data is durable and persisted to disk immediately,
-    /// at the expense of reduced performance
-    sync_mode: bool,
-}
+        writer.export(out)
+    }
 
-impl Default for RustcaskBuilder {
-    fn default() -> Self {
-        Self {
-            max_data_file_size: MAX_DATA_FILE_SIZE,
-            sync_mode: false,
+    /// Returns every record in the data files at or after a position, in the order the records
+    /// were written, along with the position of each record as a generation and an offset.
+    ///
+    /// Unlike `get` and the keydir, the records include overwritten values and tombstones
+    /// (records whose `value` is `None`), so they describe every change made to the store.
+    /// This is meant for change data capture and replication. Callers track the position of
+    /// the last record they processed, and pass it back in to resume. The record at the
+    /// position is returned again, so resuming callers should skip it. A generation of 0
+    /// with an offset of 0 starts at the beginning of the log.
+    ///
+    /// Values are returned as they're stored, after the value transforms listed in each record's
+    /// `transforms` were applied. See [`RustcaskBuilder::add_value_transform`].
+    ///
+    /// Only records written before `read_log_from` is called are returned. A merge replaces
+    /// the history of the generations it merges with the live values, written to new
+    /// generations. A position in a merged generation resumes from the next generation that
+    /// still exists.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` if the active data file can't be flushed, or if
+    /// a data file can't be opened.
+    pub fn read_log_from(
+        &self,
+        generation: GenerationNumber,
+        offset: u64,
+    ) -> Result<impl Iterator<Item = (LogFileEntry, GenerationNumber, u64)>, io::Error> {
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+        let (iterators, _) = writer.read_log_from(generation, offset)?;
+        Ok(iterators.into_iter().flat_map(|(generation, iterator)| {
+            iterator.map(move |(entry, index)| (entry, generation, index.offset))
+        }))
+    }
+
+    /// Returns every record in the data files from a checkpoint onwards, in the order the
+    /// records were written, like [`Rustcask::read_log_from`]. The returned iterator reports a
+    /// checkpoint just past the last record it returned, so that an interrupted export can
+    /// resume where it left off. Every record is returned exactly once across the resumed
+    /// iterations. Pass [`LogCheckpoint::start`] to start at the beginning of the log.
+    ///
+    /// A checkpoint stays valid for as long as its generation exists. The generations that are
+    /// read are pinned until the iterator is dropped, so a merge can't delete them during the
+    /// iteration, but a merge after the iterator is dropped can.
+    ///
+    /// # Example
+    /// ```
+    /// # use rustcask::{LogCheckpoint, Rustcask};
+    /// # use tempfile::TempDir;
+    /// # let temp_dir = TempDir::new().unwrap();
+    /// let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    /// store.set_str("a", "1").unwrap();
+    /// store.set_str("b", "2").unwrap();
+    ///
+    /// let mut records = store.read_log_from_checkpoint(LogCheckpoint::start()).unwrap();
+    /// assert_eq!(records.next().unwrap().0.key, b"a");
+    /// let checkpoint = records.checkpoint();
+    /// drop(records);
+    ///
+    /// let mut resumed = store.read_log_from_checkpoint(checkpoint).unwrap();
+    /// assert_eq!(resumed.next().unwrap().0.key, b"b");
+    /// assert!(resumed.next().is_none());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` with `io::ErrorKind::NotFound` if the checkpoint's
+    /// generation no longer exists, for example because it was merged. Otherwise, an
+    /// `io::Error` is returned under the same conditions as [`Rustcask::read_log_from`].
+    pub fn read_log_from_checkpoint(
+        &self,
+        checkpoint: LogCheckpoint,
+    ) -> Result<CheckpointedLogIter, io::Error> {
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+        let (iterators, pins) =
+            writer.read_log_from(checkpoint.generation(), checkpoint.offset())?;
+        let found = iterators
+            .first()
+            .is_some_and(|(generation, _)| *generation == checkpoint.generation());
+        if !found && checkpoint != LogCheckpoint::start() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "generation {} of the checkpoint no longer exists",
+                    checkpoint.generation()
+                ),
+            ));
         }
+        Ok(CheckpointedLogIter::new(iterators, checkpoint, pins))
+    }
+
+    /// Returns the tombstones in the data files, oldest first, as the removed key and the
+    /// position of the tombstone as a generation and an offset.
+    ///
+    /// A tombstone is the record that `remove` writes. The keydir forgets removed keys, so this
+    /// is the only way to find out when a key was removed. A key appears once for every time it
+    /// was removed. Merges drop tombstones, so only removals since the last merge are listed,
+    /// unless a merge left out unreadable generations. Such a merge keeps the tombstones that
+    /// may hide values in the unreadable generations.
+    ///
+    /// # Errors
+    ///
+    /// See [`Rustcask::read_log_from`].
+    pub fn tombstones(
+        &self,
+    ) -> Result<impl Iterator<Item = (Vec<u8>, GenerationNumber, u64)>, io::Error> {
+        Ok(self
+            .read_log_from(0, 0)?
+            .filter(|(entry, _, _)| entry.value.is_none())
+            .map(|(entry, generation, offset)| (entry.key, generation, offset)))
+    }
+
+    /// Returns every live key-value pair, ordered by where the values are stored in the data
+    /// files: by generation, then by offset within the generation.
+    ///
+    /// Unlike the keydir's own order, this order is deterministic. Iterating over a store that
+    /// hasn't changed yields the same sequence every time, including after the store is reopened,
+    /// which makes it suitable for reproducible exports and test snapshots. Writes move the
+    /// written keys to the end, and a merge rewrites the data files, which can change the order.
+    /// The order isn't sorted by key.
+    ///
+    /// The keydir is only locked while the live keys are collected. The values are the ones the
+    /// keys had when this function was called, and the data files they're read from are pinned
+    /// until the iterator is dropped. Expired values are skipped, and so are corrupt values when
+    /// the store is configured with `OnCorruption::SkipReturnNone`.
+    ///
+    /// # Errors
+    ///
+    /// A value that couldn't be read is returned as a `GetError`. See [`Rustcask::get`].
+    pub fn iter_ordered(&self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), GetError>> {
+        let keydir = self
+            .keydir
+            .read()
+            .expect("Another thread panicked while holding the keydir lock. Panicking.");
+        let now = clock::now_millis(self.readers.clock.as_ref());
+        let mut entries: Vec<_> = (&*keydir)
+            .into_iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| {
+                (
+                    key.clone(),
+                    entry.data_file_gen,
+                    entry.index.clone(),
+                    entry.access_count(),
+                    entry.inline_value.clone(),
+                )
+            })
+            .collect();
+        // The generations are pinned while the keydir lock is held,
+        // so that a merge can't delete them first.
+        let generations = entries
+            .iter()
+            .filter(|(_, _, _, _, inline_value)| inline_value.is_none())
+            .map(|(_, gen, _, _, _)| *gen)
+            .collect();
+        let pins = self.readers.pins.pin(generations, self.directory.clone());
+        drop(keydir);
+        entries.sort_unstable_by_key(|(_, gen, index, _, _)| (*gen, index.offset));
+
+        let mut readers = self.readers.clone();
+        entries.into_iter().filter_map(
+            move |(key, data_file_gen, index, access_count, inline_value)| {
+                // The pins are moved into the iterator, and released when it's dropped.
+                let _pins = &pins;
+                let value = match inline_value {
+                    Some(value) => value.into_vec(),
+                    None => {
+                        let mut value = Vec::new();
+                        match readers.read_value(
+                            &key,
+                            data_file_gen,
+                            &index,
+                            access_count,
+                            &mut value,
+                        ) {
+                            Ok(true) => value,
+                            // The value is corrupt, and reads treat the key as missing.
+                            Ok(false) => return None,
+                            Err(err) => return Some(Err(err)),
+                        }
+                    }
+                };
+                Some(Ok((key, value)))
+            },
+        )
+    }
+
+    /// Flushes the active data file and rotates to a new one, so that everything written so far
+    /// lands in a finalized generation that receives no more writes.
+    ///
+    /// The finalized data file is synced to disk and marked read-only. This is useful before
+    /// copying a data file elsewhere or taking a backup.
+    ///
+    /// Returns the generation of the finalized data file.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` if the active data file can't be flushed, synced,
+    /// or marked read-only.
+    pub fn flush_and_rotate(&mut self) -> Result<GenerationNumber, io::Error> {
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+        writer.flush_and_rotate()
+    }
+
+    /// Brings the store back in line with the data files on disk, after data files were deleted
+    /// while the store was open, for example by another process or an operator.
+    ///
+    /// Reading a key whose data file was deleted panics. `reconcile` removes such keys
+    /// from the store, so that they read as missing instead. If the active data file was
+    /// deleted, writes move on to a new active data file, so that handles cloned from this
+    /// one don't read stale values from the deleted file.
+    ///
+    /// Returns the number of keys that were removed.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` if the rustcask directory can't be listed, or if
+    /// the new active data file can't be created.
+    pub fn reconcile(&mut self) -> Result<usize, io::Error> {
+        // A running merge reads data files that reconcile could remove from the keydir.
+        let merge_lock = self.merge_lock.clone();
+        let _merging = merge_lock
+            .lock()
+            .expect("Another thread crashed while holding the merge lock. Panicking.");
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+        let removed_keys = writer.reconcile()?;
+        self.readers.close_missing();
+        Ok(removed_keys)
+    }
+
+    /// Makes room in the keydir for at least `additional` more keys, ahead of a bulk load.
+    /// This avoids growing the keydir repeatedly while the keys are written.
+    ///
+    /// Space in the data files isn't reserved. Data files are read up to their length, so
+    /// preallocated space would be read as entries.
+    ///
+    /// # Example
+    /// ```
+    /// # use rustcask::Rustcask;
+    /// # use tempfile::TempDir;
+    /// # let temp_dir = TempDir::new().unwrap();
+    /// let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    /// store.reserve(1000);
+    /// for i in 0..1000u32 {
+    ///     store.set(i.to_be_bytes().to_vec(), b"value".to_vec()).unwrap();
+    /// }
+    /// ```
+    pub fn reserve(&self, additional: usize) {
+        self.keydir
+            .write()
+            .expect("Another thread crashed while holding keydir lock. Panicking.")
+            .reserve(additional);
+    }
+
+    /// Returns where the value of a key is stored in the data files, or `None` if the key doesn't
+    /// exist or its value has expired. Looking up a key's location doesn't count as a read.
+    ///
+    /// This is meant for tools that inspect or verify the data files. The location changes
+    /// when the key is overwritten or the store is merged.
+    ///
+    /// # Example
+    /// ```
+    /// # use rustcask::Rustcask;
+    /// # use tempfile::TempDir;
+    /// # let temp_dir = TempDir::new().unwrap();
+    /// let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    /// store.set_str("key", "value").unwrap();
+    ///
+    /// let location = store.locate(&"key".as_bytes().to_vec()).unwrap();
+    /// println!(
+    ///     "key is stored in generation {}, at offset {}, in {} bytes",
+    ///     location.generation(),
+    ///     location.offset(),
+    ///     location.len()
+    /// );
+    /// assert_eq!(location.generation(), 0);
+    /// assert!(store.locate(&"missing".as_bytes().to_vec()).is_none());
+    /// ```
+    pub fn locate(&self, key: &Vec<u8>) -> Option<KeyLocation> {
+        let keydir = self
+            .keydir
+            .read()
+            .expect("Another thread crashed while holding keydir lock. Panicking.");
+        let now = clock::now_millis(self.readers.clock.as_ref());
+        keydir
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| entry.location())
+    }
+
+    /// Returns the size in bytes of a key's value as it's stored, or `None` if the key doesn't
+    /// exist or its value has expired. The size is looked up in the keydir, without reading the
+    /// value, and the lookup doesn't count as a read.
+    ///
+    /// This lets a caller decide how to handle a value, for example whether to buffer it, before
+    /// fetching it. If value transforms are configured, the stored value is the transformed one,
+    /// so its size can differ from the size of the value that `get` returns.
+    ///
+    /// # Example
+    /// ```
+    /// # use rustcask::Rustcask;
+    /// # use tempfile::TempDir;
+    /// # let temp_dir = TempDir::new().unwrap();
+    /// let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    /// store.set_str("key", "value").unwrap();
+    ///
+    /// assert_eq!(store.exists_and_stored_size(&"key".as_bytes().to_vec()), Some(5));
+    /// assert_eq!(store.exists_and_stored_size(&"missing".as_bytes().to_vec()), None);
+    /// ```
+    pub fn exists_and_stored_size(&self, key: &Vec<u8>) -> Option<u64> {
+        let keydir = self
+            .keydir
+            .read()
+            .expect("Another thread crashed while holding keydir lock. Panicking.");
+        let now = clock::now_millis(self.readers.clock.as_ref());
+        keydir
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| entry.value_len)
+    }
+
+    /// Returns the number of times the key has been read with `get` since the store was opened.
+    /// Overwriting a key keeps its access count, and removing it resets the count.
+    ///
+    /// Returns `None` if the key doesn't exist, or if access counting isn't enabled.
+    /// See [`RustcaskBuilder::set_track_access_counts`].
+    pub fn access_count(&self, key: &Vec<u8>) -> Option<u64> {
+        if !self.options.tracks_access_counts() {
+            return None;
+        }
+        self.keydir
+            .read()
+            .expect("Another thread crashed while holding keydir lock. Panicking.")
+            .get(key)
+            .map(|keydir_entry| keydir_entry.access_count())
+    }
+
+    /// Returns cumulative operation counts for the store.
+    ///
+    /// The counts are shared by every handle cloned from the handle returned by `open`,
+    /// so they reflect the operations performed through all of them.
+    pub fn counters(&self) -> Counters {
+        self.readers.counters.snapshot()
+    }
+
+    /// Returns the ratio of the bytes written to the data files since the store was opened, to the
+    /// bytes of live entries in the data files.
+    ///
+    /// The bytes written include overwritten values, tombstones, and the live entries that
+    /// merges copy. See [`Counters::bytes_written`] and [`Counters::merge_bytes_written`].
+    /// A high write amplification means that most writes are rewriting data, and can be lowered
+    /// by merging less often or raising the max data file size.
+    ///
+    /// Returns 0 if the store has no live entries. Computing the live bytes visits every key.
+    pub fn write_amplification(&self) -> f64 {
+        let counters = self.readers.counters.snapshot();
+        let bytes_written = counters.bytes_written + counters.merge_bytes_written;
+
+        let keydir = self
+            .keydir
+            .read()
+            .expect("Another thread crashed while holding keydir lock. Panicking.");
+        let now = clock::now_millis(self.readers.clock.as_ref());
+        let live_bytes: u64 = (&*keydir)
+            .into_iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(_, entry)| entry.index.len)
+            .sum();
+        if live_bytes == 0 {
+            return 0.0;
+        }
+        bytes_written as f64 / live_bytes as f64
+    }
+
+    /// Deletes the data and hint files of a generation that no longer holds any live data.
+    ///
+    /// This allows reclaiming space between full merges. A generation can be dropped
+    /// once every key it contains has been overwritten or removed in a later generation.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` if:
+    ///
+    /// * The generation is the active generation, or doesn't exist.
+    /// * Live keys still point into the generation.
+    /// * The generation holds tombstones for values that still exist in older generations.
+    ///   Dropping it would bring those values back the next time the store is opened.
+    pub fn drop_generation(&mut self, generation: GenerationNumber) -> Result<(), io::Error> {
+        // A running merge may be copying live entries out of the generation.
+        let merge_lock = self.merge_lock.clone();
+        let _merging = merge_lock
+            .lock()
+            .expect("Another thread crashed while holding the merge lock. Panicking.");
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+
+        writer.drop_generation(generation)
+    }
+
+    /// Reads every entry of a generation's data file, and reports the entries that are corrupt,
+    /// without opening the rest of the store's data files.
+    ///
+    /// This lets a suspect data file be checked on its own. An entry is corrupt if it can't be
+    /// deserialized, or if it doesn't match its checksums. Corrupt entries are skipped, so the
+    /// entries after them are still checked. If an entry's header is corrupt, its lengths aren't
+    /// trusted, and checking resumes at the next entry whose header checksum matches. The active
+    /// data file is flushed first, so that entries that are still buffered aren't reported as
+    /// truncated.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` if the generation doesn't exist, its data file
+    /// can't be opened, or the active data file can't be flushed.
+    pub fn verify_generation(
+        &self,
+        generation: GenerationNumber,
+    ) -> Result<GenerationVerifyReport, io::Error> {
+        let log_iter = {
+            let mut writer = self
+                .writer
+                .lock()
+                .expect("Another thread crashed while holding the writer lock. Panicking.");
+            writer.flush()?;
+            LogFileIterator::new(data_file_path(&self.directory, &generation))?
+        };
+
+        let report = GenerationVerifyReport::scan(generation, log_iter);
+        if let Some(handler) = &self.options.corruption_handler {
+            handler.report_verify(&report);
+        }
+        Ok(report)
+    }
+
+    /// Converts this handle into a handle that can only read from the store.
+    ///
+    /// The returned handle keeps the keydir and data file readers, but drops this handle's
+    /// reference to the writer and its active data file. Once every writing handle has been
+    /// dropped or converted, the active data file is closed.
+    ///
+    /// Writes made through other handles that still share the writer remain visible
+    /// to the read-only handle.
+    pub fn into_read_only(self) -> ReadOnlyRustcask {
+        let Rustcask {
+            writer,
+            readers,
+            keydir,
+            ..
+        } = self;
+        drop(writer);
+        ReadOnlyRustcask::new(readers, keydir)
+    }
+
+    /// Stores a small application-defined blob, such as a schema version, with the store.
+    /// It replaces any blob that was set before.
+    ///
+    /// The blob is kept in its own file in the rustcask directory, separate from the keys, so it
+    /// isn't affected by merges or expiry. It's written durably before `set_metadata` returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the blob couldn't be written.
+    pub fn set_metadata(&mut self, blob: Vec<u8>) -> Result<(), io::Error> {
+        // The writer lock orders metadata writes from different handles.
+        let _writer = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.");
+        meta::write_metadata(&self.directory, &blob)
+    }
+
+    /// Returns the blob set with [`Rustcask::set_metadata`], or `None` if none has been set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the blob couldn't be read.
+    pub fn metadata(&self) -> Result<Option<Vec<u8>>, io::Error> {
+        meta::read_metadata(&self.directory)
+    }
+
+    /// Relocates the store to a new directory.
+    ///
+    /// The store is closed, all of its data and hint files, and its metadata, are moved into
+    /// `new_dir`, and the store is reopened there with the same configuration. If `new_dir`
+    /// is on a different filesystem, files are copied and then deleted from the old directory.
+    /// `new_dir` is created if it does not exist.
+    ///
+    /// `new_dir` is locked before anything is moved, and the old directory's lock file is
+    /// removed along with the store's files. Generations retired by a merge are left behind,
+    /// to be deleted from the old directory.
+    ///
+    /// Handles that share the store, and other stores opened on the same directory in this
+    /// process, are checked for. A store opened with [`RustcaskBuilder::open_read_only`] doesn't
+    /// lock the directory, and must not be reading from it while it's moved.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` if:
+    ///
+    /// * Other handles to the store are still open: clones, read-only handles created with
+    ///   [`Rustcask::into_read_only`], iterators and streams that are still reading values,
+    ///   or other stores opened on the same directory.
+    /// * `new_dir` already contains rustcask data or hint files.
+    /// * `new_dir` is locked by another store, in this process or another one.
+    ///   The error's kind is `WouldBlock`.
+    /// * A file could not be moved, or the store could not be reopened in `new_dir`.
+    ///   If a move fails partway, the files that were already moved remain in `new_dir`.
+    pub fn move_to(self, new_dir: &Path) -> Result<Rustcask, io::Error> {
+        // The store and its writer each hold the keydir. Read-only handles hold it too, and
+        // iterators and streams pin the generations they read from.
+        if Arc::strong_count(&self.writer) > 1
+            || Arc::strong_count(&self.keydir) > 2
+            || self.readers.pins.has_pins()
+            || self.dir_lock.is_shared()
+        {
+            return Err(io::Error::other(
+                "cannot move a rustcask store while other handles to it are open",
+            ));
+        }
+
+        create_empty_rustcask_dir(new_dir)?;
+        let new_dir_lock = DirLock::acquire_exclusive(new_dir)?;
+
+        // Close the store's file handles before moving its files.
+        let Rustcask {
+            writer,
+            readers,
+            keydir,
+            directory,
+            options,
+            dir_lock,
+            ..
+        } = self;
+        drop((writer, readers, keydir));
+        Arc::into_inner(dir_lock)
+            .expect("The store has no other handles")
+            .release_and_remove()?;
+
+        info!(
+            "Moving Rustcask directory {} to {}.",
+            directory.to_string_lossy(),
+            new_dir.to_string_lossy()
+        );
+
+        // Retired generations are left behind, to be deleted once their merge delete delay elapses.
+        for entry in fs::read_dir(directory.as_path())? {
+            let path = entry?.path();
+            if (is_data_file(&path) || is_hint_file(&path))
+                && !retired_file_path(&directory, parse_generation_number(path.clone())).exists()
+            {
+                let file_name = path.file_name().expect("Rustcask files have a file name");
+                move_file(&path, &new_dir.join(file_name))?;
+            }
+        }
+
+        let meta_file = meta::meta_file_path(&directory);
+        if meta_file.exists() {
+            move_file(&meta_file, &meta::meta_file_path(new_dir))?;
+        }
+
+        options
+            .open_locked(new_dir, Some(new_dir_lock))
+            .map(|(store, _)| store)
+            .map_err(open_error_to_io)
+    }
+
+    /// Copies the live data of the store into `dest`, and opens the copy as an independent store
+    /// with the same configuration. Writes to either store don't affect the other.
+    ///
+    /// The copy is a compacted snapshot of the store at the time `fork` is called: it only holds
+    /// the live values, and writes made while the copy is being written aren't included. The
+    /// metadata blob is copied too.
+    ///
+    /// The writer lock is only held while the live keys are collected. `dest` is created if it
+    /// does not exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an `io::Error` if:
+    ///
+    /// * `dest` already contains rustcask data or hint files.
+    /// * A value couldn't be copied, or the copy couldn't be opened.
+    ///   If the copy fails partway, the files that were already written remain in `dest`.
+    pub fn fork(&self, dest: &Path) -> Result<Rustcask, io::Error> {
+        create_empty_rustcask_dir(dest)?;
+
+        let (mut fork_job, pins) = self
+            .writer
+            .lock()
+            .expect("Another thread crashed while holding the writer lock. Panicking.")
+            .start_fork(Arc::new(dest.to_path_buf()));
+
+        info!(
+            "Forking Rustcask directory {} to {}.",
+            self.directory.to_string_lossy(),
+            dest.to_string_lossy()
+        );
+        let mut readers = self.readers.clone();
+        fork_job
+            .copy_live_entries(&mut readers)
+            .map_err(|err| match err.kind {
+                MergeErrorKind::Io(err) => err,
+                _ => io::Error::other(err.to_string()),
+            })?;
+        drop(pins);
+
+        if let Some(blob) = self.metadata()? {
+            meta::write_metadata(dest, &blob)?;
+        }
+
+        self.options.clone().open(dest).map_err(open_error_to_io)
+    }
+
+    // Get active generation and get active data file size are for testing
+    #[cfg(test)]
+    fn get_active_generation(&self) -> GenerationNumber {
+        let writer = self.writer.lock().expect(
+            "Another thread crashed while holding the writer lock. \
+                Panicking because the write lock is required to get the active generation.",
+        );
+        writer.get_active_generation()
+    }
+
+    #[cfg(test)]
+    fn get_active_data_file_size(&self) -> u64 {
+        let writer = self.writer.lock().expect(
+            "Another thread crashed while holding the writer lock. \
+                Panicking because the write lock is required to get the active data file size.",
+        );
+        writer.get_active_data_file_size()
+    }
+}
+
+/// Simplifies configuration and creation of Rustcask instances.
+///
+/// # Example
+/// ```
+/// # use rustcask::Rustcask;
+/// # use tempfile::TempDir;
+/// # let temp_dir = TempDir::new().unwrap();
+/// # let rustcask_dir = temp_dir.path();
+/// let store = Rustcask::builder()
+///     .set_sync_mode(true)
+///     .open(rustcask_dir);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RustcaskBuilder {
+    max_data_file_size: u64,
+
+    /// When sync mode is true, writes to the data file
+    /// are fsync'ed before returning to the user.
+    /// This guarantees that data is durable and persisted to disk immediately,
+    /// at the expense of reduced performance
+    sync_mode: bool,
+
+    hint_verify: HintVerify,
+
+    hint_compression: bool,
+
+    auto_merge: bool,
+
+    auto_merge_ratio: f64,
+
+    auto_merge_check_interval: u64,
+
+    lazy_active_file: bool,
+
+    merge_threads: usize,
+
+    merge_delete_delay: Duration,
+
+    merge_max_extra_bytes: Option<u64>,
+
+    value_cache_capacity: u64,
+
+    cache_eviction: CacheEviction,
+
+    track_access_counts: bool,
+
+    write_rate_limit: Option<u64>,
+
+    read_rate_limit: Option<u64>,
+
+    rate_limit_blocking: bool,
+
+    clock: Arc<dyn Clock>,
+
+    monotonic_timestamps: bool,
+
+    file_filter: FileFilter,
+
+    inline_value_threshold: usize,
+
+    skip_unreadable_generations: bool,
+
+    value_transforms: ValueTransforms,
+
+    in_place_updates: bool,
+
+    scan_prefetch: usize,
+
+    keydir_capacity_hint: Option<usize>,
+
+    corruption_handler: Option<CorruptionHandler>,
+
+    on_read_corruption: OnCorruption,
+}
+
+impl Default for RustcaskBuilder {
+    fn default() -> Self {
+        Self {
+            max_data_file_size: MAX_DATA_FILE_SIZE,
+            sync_mode: false,
+            hint_verify: HintVerify::Sample,
+            hint_compression: false,
+            auto_merge: false,
+            auto_merge_ratio: AUTO_MERGE_RATIO,
+            auto_merge_check_interval: 1,
+            lazy_active_file: false,
+            merge_threads: 1,
+            merge_delete_delay: Duration::ZERO,
+            merge_max_extra_bytes: None,
+            value_cache_capacity: 0,
+            cache_eviction: CacheEviction::Lru,
+            track_access_counts: false,
+            write_rate_limit: None,
+            read_rate_limit: None,
+            rate_limit_blocking: true,
+            clock: Arc::new(SystemClock),
+            monotonic_timestamps: false,
+            file_filter: include_all_files,
+            inline_value_threshold: 0,
+            skip_unreadable_generations: false,
+            value_transforms: ValueTransforms::default(),
+            in_place_updates: false,
+            scan_prefetch: 0,
+            keydir_capacity_hint: None,
+            corruption_handler: None,
+            on_read_corruption: OnCorruption::default(),
+        }
+    }
+}
+
+impl RustcaskBuilder {
+    /// Sets the maximum data file size. When the active data file
+    /// surpasses this size, it will be marked read-only and a new active data file
+    /// will be created.
+    pub fn set_max_data_file_size(mut self, max_size: u64) -> Self {
+        self.max_data_file_size = max_size;
+        self
+    }
+
+    /// When sync mode is set to true, writes to the data file
+    /// are fsync'ed before returning to the user.
+    /// This guarantees that data is durable and persisted to disk immediately,
+    /// at the expense of reduced performance
+    pub fn set_sync_mode(mut self, sync_mode: bool) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// Controls how hint files are checked against their data files when the keydir
+    /// is rebuilt during open. Hint files that fail verification are ignored, and the keydir
+    /// is rebuilt from the corresponding data file instead.
+    ///
+    /// Defaults to `HintVerify::Sample`.
+    pub fn set_hint_verification(mut self, hint_verify: HintVerify) -> Self {
+        self.hint_verify = hint_verify;
+        self
+    }
+
+    /// Compresses the hint files that merges write with zstd.
+    ///
+    /// Hint files are read in full when the store is opened, so compressing them trades CPU for
+    /// smaller files and less I/O. This helps stores with many keys. Each hint file records
+    /// whether it's compressed, so hint files are read correctly whatever this is set to.
+    ///
+    /// Defaults to false.
+    pub fn set_hint_compression(mut self, hint_compression: bool) -> Self {
+        self.hint_compression = hint_compression;
+        self
+    }
+
+    /// When auto-merge is enabled, `set` and `remove` merge the data files once the
+    /// ratio of dead entries (overwritten values, removed values, and tombstones)
+    /// to live keys reaches the auto-merge ratio. See [`RustcaskBuilder::set_auto_merge_ratio`].
+    ///
+    /// The merge is performed by the writing thread before the write returns.
+    pub fn set_auto_merge(mut self, auto_merge: bool) -> Self {
+        self.auto_merge = auto_merge;
+        self
+    }
+
+    /// Sets the ratio of dead entries to live keys at which an automatic merge is performed.
+    /// Only takes effect when auto-merge is enabled.
+    ///
+    /// After an automatic merge is triggered, another won't be triggered until the ratio has
+    /// fallen back below half of this value.
+    ///
+    /// Defaults to 1.0, i.e. merge once there are as many dead entries as live keys.
+    pub fn set_auto_merge_ratio(mut self, auto_merge_ratio: f64) -> Self {
+        self.auto_merge_ratio = auto_merge_ratio;
+        self
+    }
+
+    /// Sets how many writes (sets and removes) happen between checks of the auto-merge ratio.
+    /// Only takes effect when auto-merge is enabled.
+    ///
+    /// A larger interval spreads the cost of the check over more writes, at the cost of
+    /// letting the ratio overshoot the auto-merge ratio by up to `interval` dead entries.
+    /// An interval of 0 is treated as 1.
+    ///
+    /// Defaults to 1, i.e. the ratio is checked on every write.
+    pub fn set_auto_merge_check_interval(mut self, interval: u64) -> Self {
+        self.auto_merge_check_interval = interval.max(1);
+        self
+    }
+
+    /// When lazy active file creation is enabled, opening a directory whose active data file
+    /// doesn't exist yet, such as an empty directory, doesn't create it.
+    /// The active data file is instead created by the first `set` or `remove`.
+    ///
+    /// This keeps stores that are only read from, or never written to, from creating data files.
+    pub fn set_lazy_active_file(mut self, lazy_active_file: bool) -> Self {
+        self.lazy_active_file = lazy_active_file;
+        self
+    }
+
+    /// Sets the number of threads that read live entries from the data files during a merge.
+    /// Entries are still written to the merged data files by a single thread, in the same order
+    /// regardless of the number of threads.
+    ///
+    /// Reading with multiple threads can speed up merges of large stores. A value of 0 is
+    /// treated as 1. Defaults to 1.
+    pub fn set_merge_threads(mut self, merge_threads: usize) -> Self {
+        self.merge_threads = merge_threads;
+        self
+    }
+
+    /// Sets how long the data files replaced by a merge are kept before they're deleted.
+    ///
+    /// The merge still switches reads to the merged data files as soon as they're written, but
+    /// the replaced generations are left on disk until the delay elapses, and then deleted by a
+    /// background thread. This gives other processes, or snapshots, that read the old data files
+    /// time to finish. Retired generations are ignored by the store, and any that are still on
+    /// disk when the store is opened are deleted.
+    ///
+    /// A delay of 0 deletes the replaced generations when the merge completes. Defaults to 0.
+    pub fn set_merge_delete_delay(mut self, delay: Duration) -> Self {
+        self.merge_delete_delay = delay;
+        self
+    }
+
+    /// Limits the extra disk space, in bytes, that a merge uses at any one time.
+    ///
+    /// A merge writes the live entries to new data files before deleting the generations they
+    /// replace, so merging every generation at once can temporarily need nearly twice the size of
+    /// the store. With a limit, the merge runs in steps. Each step merges the oldest generations
+    /// whose merged data and hint files fit within the limit, and deletes them before the next
+    /// step starts. This lets stores be merged on nearly full disks.
+    ///
+    /// A step always merges at least one generation, so a generation with more live data than the
+    /// limit still exceeds it. Generations that are retired by a merge delete delay, or pinned by
+    /// readers, stay on disk after their step and aren't counted.
+    /// See [`RustcaskBuilder::set_merge_delete_delay`].
+    ///
+    /// A limit of 0 merges every generation in a single step, which is the default.
+    pub fn set_merge_max_extra_bytes(mut self, max_extra_bytes: u64) -> Self {
+        self.merge_max_extra_bytes = (max_extra_bytes > 0).then_some(max_extra_bytes);
+        self
+    }
+
+    /// Sets the capacity, in bytes, of the value cache. Values returned by `get` are kept in the
+    /// cache, so that reading them again doesn't read the data files. The cache is shared by every
+    /// handle cloned from the store, and the size of a cached value includes its key.
+    ///
+    /// A capacity of 0 disables the cache. Defaults to 0.
+    pub fn set_value_cache_capacity(mut self, capacity: u64) -> Self {
+        self.value_cache_capacity = capacity;
+        self
+    }
+
+    /// Sets which value is evicted when the value cache is full.
+    ///
+    /// Defaults to `CacheEviction::Lru`.
+    pub fn set_cache_eviction(mut self, cache_eviction: CacheEviction) -> Self {
+        self.cache_eviction = cache_eviction;
+        self
+    }
+
+    /// When access counting is enabled, every `get` of an existing key increments
+    /// the key's access count. See [`Rustcask::access_count`].
+    ///
+    /// Access counting is always enabled when the value cache uses `CacheEviction::Lfu`.
+    pub fn set_track_access_counts(mut self, track_access_counts: bool) -> Self {
+        self.track_access_counts = track_access_counts;
+        self
+    }
+
+    /// Limits the rate, in bytes per second, at which `set` and `remove` write to the data files.
+    /// The limit is shared by every handle cloned from the store. Merges aren't rate limited.
+    ///
+    /// Up to one second's worth of bytes can be written in a burst. Writes that exceed the limit
+    /// wait, unless rate limiting is non-blocking. See [`RustcaskBuilder::set_rate_limit_blocking`].
+    ///
+    /// A limit of 0 disables write rate limiting, which is the default.
+    pub fn set_write_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.write_rate_limit = (bytes_per_sec > 0).then_some(bytes_per_sec);
+        self
+    }
+
+    /// Limits the rate, in bytes per second, at which `get` reads from the data files.
+    /// The limit is shared by every handle cloned from the store. Values served from the
+    /// value cache don't count toward the limit.
+    ///
+    /// Up to one second's worth of bytes can be read in a burst. Reads that exceed the limit
+    /// wait, unless rate limiting is non-blocking. See [`RustcaskBuilder::set_rate_limit_blocking`].
+    ///
+    /// A limit of 0 disables read rate limiting, which is the default.
+    pub fn set_read_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.read_rate_limit = (bytes_per_sec > 0).then_some(bytes_per_sec);
+        self
+    }
+
+    /// When rate limiting is blocking, operations that exceed a rate limit wait until they're
+    /// allowed to proceed. Otherwise, they fail immediately with a `RateLimited` error kind.
+    ///
+    /// Defaults to true.
+    pub fn set_rate_limit_blocking(mut self, rate_limit_blocking: bool) -> Self {
+        self.rate_limit_blocking = rate_limit_blocking;
+        self
+    }
+
+    /// Sets the clock used to decide when values written with [`Rustcask::set_with_ttl`] expire.
+    /// Defaults to [`SystemClock`].
+    ///
+    /// Expiry times are stored in the data files as wall clock times, so a store should be
+    /// reopened with a clock that agrees with the one it was written with.
+    pub fn set_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// When set, the times that writes read from the clock never go backwards. If the clock
+    /// jumps back, for example because of an NTP correction, each write is given a time 1ms
+    /// after the previous write's, until the clock catches up. The expiry times of values written
+    /// with [`Rustcask::set_with_ttl`] are counted from these times, so a later write never
+    /// expires before an earlier write with the same TTL.
+    ///
+    /// Times are only kept monotonic while the store is open. Defaults to false.
+    pub fn set_monotonic_timestamps(mut self, monotonic_timestamps: bool) -> Self {
+        self.monotonic_timestamps = monotonic_timestamps;
+        self
+    }
+
+    /// Sets a filter that decides which data files in the rustcask directory belong to the store.
+    /// Data files the filter rejects are ignored, even if their names look like rustcask files.
+    /// This is useful when the rustcask directory is shared with other files.
+    ///
+    /// The filter must accept every data file the store creates, otherwise the store will lose
+    /// track of its own data. Files whose names don't look like rustcask files are always ignored.
+    ///
+    /// Defaults to accepting every file.
+    pub fn set_file_filter(mut self, file_filter: fn(&Path) -> bool) -> Self {
+        self.file_filter = file_filter;
+        self
+    }
+
+    /// Values of at most this many bytes are kept in memory in the keydir, in addition to
+    /// being written to the data files. Reads of inlined values don't touch the data files.
+    ///
+    /// Inlined values are rebuilt from the data files when the store is opened.
+    /// Every inlined value uses memory for as long as the store is open, so the threshold
+    /// should be kept small.
+    ///
+    /// Defaults to 0, which disables inlining.
+    pub fn set_inline_value_threshold(mut self, inline_value_threshold: usize) -> Self {
+        self.inline_value_threshold = inline_value_threshold;
+        self
+    }
+
+    /// When enabled, `open` skips data files that can't be read, instead of failing.
+    /// A single bad data file then doesn't make the rest of the store inaccessible.
+    ///
+    /// Keys whose latest value or tombstone is in a skipped data file are unavailable,
+    /// or return the value they had in an older generation. Skipped generations are listed in
+    /// the [`OpenReport`] returned by [`RustcaskBuilder::open_with_report`]. Merges leave
+    /// skipped data files in place, in case they can be recovered.
+    ///
+    /// Defaults to false.
+    pub fn set_skip_unreadable_generations(mut self, skip_unreadable_generations: bool) -> Self {
+        self.skip_unreadable_generations = skip_unreadable_generations;
+        self
+    }
+
+    /// Adds a transform to the end of the value transform pipeline. Values are passed through
+    /// the transforms in the order they were added before they're written, for example to
+    /// compress and then encrypt them. See [`ValueTransform`].
+    ///
+    /// The transforms applied to a value are recorded with it, so the pipeline can be changed
+    /// between opens. Every transform that values in the store were written with must still
+    /// be added, or reading those values fails.
+    ///
+    /// When values are inlined, a value is only inlined if both it and its transformed value
+    /// are within the inline value threshold. See [`RustcaskBuilder::set_inline_value_threshold`].
+    pub fn add_value_transform(mut self, transform: Arc<dyn ValueTransform>) -> Self {
+        self.value_transforms.push(transform);
+        self
+    }
+
+    /// Sets the size, in bytes, below which values aren't compressed. Compressing small values
+    /// costs CPU time and can make them larger. Values at or above the size are passed through
+    /// every value transform, and smaller values skip the transforms that report
+    /// [`ValueTransform::is_compression`]. Each value records whether it was compressed,
+    /// so the size can be changed between opens.
+    ///
+    /// Defaults to 0, which compresses every value.
+    pub fn set_compression_min_size(mut self, bytes: usize) -> Self {
+        self.value_transforms.set_compression_min_size(bytes);
+        self
+    }
+
+    /// When in-place updates are enabled, a `set` that overwrites a value in the active data file
+    /// with a value of exactly the same serialized size rewrites the existing entry, instead of
+    /// appending a new one. This keeps update-heavy workloads with fixed-size values from growing
+    /// the data files.
+    ///
+    /// # Durability
+    ///
+    /// An in-place update isn't crash safe. A crash while the entry is being rewritten can leave
+    /// it partially written, and it's then skipped as corrupt when the store is reopened, losing
+    /// both the old and the new value. Sync mode narrows, but doesn't close, this window.
+    /// Overwritten values also disappear from [`Rustcask::read_log_from`], which may observe a
+    /// partially rewritten entry while an update is in progress.
+    ///
+    /// Only entries in the active data file are rewritten. Defaults to false.
+    pub fn set_in_place_updates(mut self, in_place_updates: bool) -> Self {
+        self.in_place_updates = in_place_updates;
+        self
+    }
+
+    /// Sets how many entries are read ahead at a time when data files are scanned from start to
+    /// finish: when the keydir is built during open, and by `export`, `read_log_from`,
+    /// `tombstones`, and `drop_generation`. Reading ahead in batches can speed up scans of large
+    /// data files.
+    ///
+    /// A value of 0 disables prefetching, which is the default.
+    pub fn set_scan_prefetch(mut self, entries: usize) -> Self {
+        self.scan_prefetch = entries;
+        self
+    }
+
+    /// Sets the number of keys that the keydir is sized for when the store is opened.
+    /// Sizing the keydir up front avoids growing it repeatedly while the data files are read.
+    ///
+    /// By default, the number of keys is estimated from the size of the data files and the
+    /// average size of the entries at the start of the newest data file. Overwritten and
+    /// removed keys are included in the estimate. A value of 0 disables pre-sizing.
+    pub fn set_keydir_capacity_hint(mut self, keys: usize) -> Self {
+        self.keydir_capacity_hint = Some(keys);
+        self
     }
-}
 
-impl RustcaskBuilder {
-    /// Sets the maximum data file size. When the active data file
-    /// surpasses this size, it will be marked read-only and a new active data file
-    /// will be created.
-    pub fn set_max_data_file_size(mut self, max_size: u64) -> Self {
-        self.max_data_file_size = max_size;
+    /// Sets a handler that's called whenever corrupt data is found: when `open` or
+    /// `verify_generation` finds a corrupt or truncated entry, and when `get` reads an entry
+    /// that can't be deserialized, whose checksums don't match, that's cut off, or that's past
+    /// the end of its data file. This lets integrity problems be alerted on in one place, rather
+    /// than by inspecting every error.
+    ///
+    /// The handler is called before the operation returns, and doesn't change its result.
+    /// It may be called from any thread that uses the store.
+    pub fn set_corruption_handler(
+        mut self,
+        handler: Arc<dyn Fn(CorruptionEvent) + Send + Sync>,
+    ) -> Self {
+        self.corruption_handler = Some(CorruptionHandler::new(handler));
         self
     }
 
-    /// When sync mode is set to true, writes to the data file
-    /// are fsync'ed before returning to the user.
-    /// This guarantees that data is durable and persisted to disk immediately,
-    /// at the expense of reduced performance
-    pub fn set_sync_mode(mut self, sync_mode: bool) -> Self {
-        self.sync_mode = sync_mode;
+    /// Sets what reads do when the value of a key is corrupt: when its entry can't be
+    /// deserialized, doesn't match its checksums, is cut off, is past the end of its data file,
+    /// or holds a different key. This applies to `get` and the other operations that read values.
+    /// Values that are held in memory, inlined in the keydir or cached, aren't read again, so
+    /// corruption of their entries is only found once they're read from the data file.
+    ///
+    /// By default, reads return an error. With `OnCorruption::SkipReturnNone`, the key reads as
+    /// missing instead, and with `OnCorruption::Panic`, the read panics. The corruption handler
+    /// is called first in every mode.
+    pub fn set_on_read_corruption(mut self, on_read_corruption: OnCorruption) -> Self {
+        self.on_read_corruption = on_read_corruption;
         self
     }
 
+    /// Checks for settings that would produce a store that can't work,
+    /// and returns a description of the first one found.
+    fn validate(&self) -> Result<(), String> {
+        if self.max_data_file_size == 0 {
+            // Every write would rotate the active data file.
+            return Err("the max data file size must be greater than 0".to_string());
+        }
+        if self.auto_merge && !(self.auto_merge_ratio.is_finite() && self.auto_merge_ratio > 0.0) {
+            return Err(format!(
+                "the auto-merge ratio must be a positive number, but it's {}",
+                self.auto_merge_ratio
+            ));
+        }
+        if let Some(tag) = self.value_transforms.duplicate_tag() {
+            return Err(format!("more than one value transform has the tag {}", tag));
+        }
+        // No allocation can be larger than isize::MAX bytes.
+        if self.value_cache_capacity > isize::MAX as u64 {
+            return Err(format!(
+                "the value cache capacity of {} bytes is larger than the addressable memory",
+                self.value_cache_capacity
+            ));
+        }
+        Ok(())
+    }
+
+    fn tracks_access_counts(&self) -> bool {
+        self.track_access_counts
+            || (self.value_cache_capacity > 0 && self.cache_eviction == CacheEviction::Lfu)
+    }
+
     /// Generates a Rustcask instance.
+    ///
+    /// The directory is locked, so that other processes can't open it for writing until every
+    /// store opened on it in this process has been dropped. Opening the directory again in the
+    /// same process shares the lock instead of failing, but the two stores don't share a writer,
+    /// so only one of them should be written to. Clone the store to write from several threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `OpenErrorKind::Io` error of kind `WouldBlock` if another process holds the
+    /// directory's lock.
     pub fn open(self, rustcask_dir: &Path) -> Result<Rustcask, OpenError> {
+        self.open_with_report(rustcask_dir).map(|(store, _)| store)
+    }
+
+    /// Generates a Rustcask instance, along with a report of the problems that were worked
+    /// around while opening it, such as corrupt entries or leftover temporary files.
+    /// `open` works around the same problems, but only logs them.
+    pub fn open_with_report(
+        self,
+        rustcask_dir: &Path,
+    ) -> Result<(Rustcask, OpenReport), OpenError> {
+        self.open_locked(rustcask_dir, None)
+    }
+
+    /// Opens a store with the lock on its directory, if the caller already holds it.
+    /// Otherwise, the directory is locked once the store is loaded.
+    fn open_locked(
+        self,
+        rustcask_dir: &Path,
+        dir_lock: Option<DirLock>,
+    ) -> Result<(Rustcask, OpenReport), OpenError> {
         trace!(
             "Open called on directory {}",
-            rustcask_dir.to_string_lossy().to_string()
+            rustcask_dir.to_string_lossy()
         );
         let rustcask_dir = Arc::new(PathBuf::from(rustcask_dir));
-
-        if !rustcask_dir.is_dir() {
-            return Err(OpenError {
-                kind: OpenErrorKind::BadDirectory,
+        let (data_file_readers, keydir, report) = self.load(&rustcask_dir)?;
+        let keydir = Arc::new(RwLock::new(keydir));
+
+        // The directory is locked before anything in it is modified.
+        let dir_lock = match dir_lock {
+            Some(dir_lock) => dir_lock,
+            None => DirLock::acquire(&rustcask_dir).map_err(|err| OpenError {
+                kind: OpenErrorKind::Io(err),
                 rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
-            });
-        }
+            })?,
+        };
 
-        let data_file_readers = Readers::new(rustcask_dir.clone()).map_err(|err| OpenError {
+        // Retired generations aren't loaded, so they can be deleted without waiting for their
+        // merge delete delay.
+        pins::delete_retired_generations(&rustcask_dir).map_err(|err| OpenError {
             kind: OpenErrorKind::Io(err),
             rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
         })?;
 
-        let keydir = Arc::new(RwLock::new(KeyDir::new(&rustcask_dir)?));
+        truncate_active_data_file(&rustcask_dir, &self, &report).map_err(|err| OpenError {
+            kind: OpenErrorKind::Io(err),
+            rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
+        })?;
 
         let writer = Arc::new(Mutex::new(Writer::new(
-            self.sync_mode,
-            self.max_data_file_size,
+            &self,
             rustcask_dir.clone(),
             keydir.clone(),
             data_file_readers.clone(),
         )?));
 
+        let merge_lock = {
+            let writer = writer.lock().unwrap();
+            info!(
+                "Opened Rustcask directory {}. Max data file size: {}. Number of existing data files: {}. Active generation: {}{}. Sync mode: {}.",
+                rustcask_dir.to_string_lossy(),
+                self.max_data_file_size,
+                data_file_readers.data_file_readers.len(),
+                writer.get_active_generation(),
+                if writer.has_active_data_file() {
+                    ""
+                } else {
+                    " (not yet created)"
+                },
+                self.sync_mode
+            );
+            writer.merge_lock.clone()
+        };
+        if !report.is_clean() {
+            warn!(
+                "Worked around {} problems while opening Rustcask directory {}: {:?}",
+                report.anomalies().len(),
+                rustcask_dir.to_string_lossy(),
+                report.anomalies()
+            );
+        }
+
+        Ok((
+            Rustcask {
+                readers: data_file_readers,
+                directory: rustcask_dir,
+                keydir,
+                writer,
+                options: self,
+                merge_lock,
+                dir_lock: Arc::new(dir_lock),
+            },
+            report,
+        ))
+    }
+
+    /// Opens a store that can only be read from. Nothing in the rustcask directory is created
+    /// or modified, so stores on read-only filesystems can be opened.
+    ///
+    /// Unlike `open`, a partially written entry at the end of the active data file isn't cut off.
+    /// It's ignored, like other truncated entries.
+    pub fn open_read_only(self, rustcask_dir: &Path) -> Result<ReadOnlyRustcask, OpenError> {
+        trace!(
+            "Open read-only called on directory {}",
+            rustcask_dir.to_string_lossy()
+        );
+        let rustcask_dir = Arc::new(PathBuf::from(rustcask_dir));
+        let (data_file_readers, keydir, report) = self.load(&rustcask_dir)?;
+
         info!(
-            "Opened Rustcask directory {}. Max data file size: {}. Number of existing data files: {}. Active generation: {}. Sync mode: {}.",
-            rustcask_dir.to_string_lossy().to_string(),
-            self.max_data_file_size,
+            "Opened Rustcask directory {} read-only. Number of existing data files: {}.",
+            rustcask_dir.to_string_lossy(),
             data_file_readers.data_file_readers.len(),
-            writer.lock().unwrap().get_active_generation(),
-            self.sync_mode
         );
+        if !report.is_clean() {
+            warn!(
+                "Worked around {} problems while opening Rustcask directory {}: {:?}",
+                report.anomalies().len(),
+                rustcask_dir.to_string_lossy(),
+                report.anomalies()
+            );
+        }
 
-        Ok(Rustcask {
-            readers: data_file_readers,
-            directory: rustcask_dir,
-            keydir,
-            sync_mode: self.sync_mode,
-            writer,
-        })
+        Ok(ReadOnlyRustcask::new(
+            data_file_readers,
+            Arc::new(RwLock::new(keydir)),
+        ))
+    }
+
+    /// Builds the keydir and data file readers of a store without writing to its directory.
+    fn load(
+        &self,
+        rustcask_dir: &Arc<PathBuf>,
+    ) -> Result<(Readers, KeyDir, OpenReport), OpenError> {
+        self.validate().map_err(|reason| OpenError {
+            kind: OpenErrorKind::InvalidConfig(reason),
+            rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
+        })?;
+
+        if !rustcask_dir.is_dir() {
+            return Err(OpenError {
+                kind: OpenErrorKind::BadDirectory,
+                rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
+            });
+        }
+
+        let mut report = OpenReport::default();
+        report
+            .scan_directory(rustcask_dir)
+            .map_err(|err| OpenError {
+                kind: OpenErrorKind::Io(err),
+                rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
+            })?;
+
+        let data_file_readers =
+            Readers::new(rustcask_dir.clone(), self).map_err(|err| OpenError {
+                kind: OpenErrorKind::Io(err),
+                rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
+            })?;
+
+        let keydir = KeyDir::new(rustcask_dir, self, &mut report)?;
+        if let Some(handler) = &self.corruption_handler {
+            for anomaly in report.anomalies() {
+                handler.report_anomaly(anomaly);
+            }
+        }
+        Ok((data_file_readers, keydir, report))
+    }
+}
+
+/// Creates `dir` if it does not exist, and checks that it doesn't contain rustcask files.
+fn create_empty_rustcask_dir(dir: &Path) -> Result<(), io::Error> {
+    fs::create_dir_all(dir)?;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_data_file(&path) || is_hint_file(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already contains rustcask files", dir.to_string_lossy()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Converts an error opening a store into an `io::Error`, for functions that reopen stores.
+fn open_error_to_io(err: OpenError) -> io::Error {
+    match err.kind {
+        OpenErrorKind::Io(err) => err,
+        OpenErrorKind::BadDirectory => io::Error::new(io::ErrorKind::NotFound, err.to_string()),
+        OpenErrorKind::UnsupportedVersion { .. } => {
+            io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+        }
+        OpenErrorKind::InvalidConfig(_) => {
+            io::Error::new(io::ErrorKind::InvalidInput, err.to_string())
+        }
+    }
+}
+
+/// Cuts off a partially written entry at the end of the active data file.
+/// Otherwise, new entries would be appended after it, and couldn't be read back.
+fn truncate_active_data_file(
+    rustcask_dir: &Path,
+    options: &RustcaskBuilder,
+    report: &OpenReport,
+) -> Result<(), io::Error> {
+    let Some(active_generation) = list_generations(rustcask_dir, options.file_filter)?
+        .into_iter()
+        .max()
+    else {
+        return Ok(());
+    };
+    for anomaly in report.anomalies() {
+        if let OpenAnomaly::TruncatedEntry { generation, offset } = anomaly {
+            if *generation == active_generation {
+                info!(
+                    "Truncating active data file for generation {} to {} bytes.",
+                    generation, offset
+                );
+                let data_file = data_file_path(rustcask_dir, generation);
+                let file = fs::OpenOptions::new().write(true).open(data_file)?;
+                file.set_len(*offset)?;
+                file.sync_all()?;
+            }
+        }
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::
-        fs::File
-    ;
+    use std::{fs::File, time::SystemTime};
 
     use super::*;
     use logfile::LogFileIterator;
     use tempfile::{tempdir, TempDir};
-    use utils::{list_generations, tests::{file_names, get_keys, get_keys_values}};
+    use utils::{
+        data_file_path, list_generations,
+        tests::{file_names, get_keys, get_keys_values},
+    };
 
     #[test]
     fn test_open() {
@@ -479,8 +2176,10 @@ mod tests {
 
         store.merge().unwrap();
 
-        let expected_data_files = vec!["1.rustcask.data"];
-        let data_files = file_names(temp_dir_path);
+        // The merged data file is sealed with a hint file, and new writes go to generation 2.
+        let expected_data_files = vec!["1.rustcask.data", "1.rustcask.hint", "2.rustcask.data"];
+        let mut data_files = file_names(temp_dir_path);
+        data_files.sort();
         assert_eq!(data_files, expected_data_files);
 
         let log_file_iter = LogFileIterator::new(temp_dir_path.join("1.rustcask.data")).unwrap();
@@ -557,7 +2256,9 @@ mod tests {
 
         check_generations(temp_dir_path, vec![0, 1, 2, 3]);
         store.merge().unwrap();
-        check_generations(temp_dir_path, vec![4, 5, 6]);
+        // Generations 4 through 7 are reserved for the merged data files,
+        // and new writes go to generation 8.
+        check_generations(temp_dir_path, vec![4, 5, 8]);
 
         drop(store);
         let mut store = Rustcask::builder()
@@ -606,8 +2307,535 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_concurrent_writes_rotate_on_schedule() {
+        const WRITES_PER_HANDLE: usize = 500;
+        const ENTRIES_PER_FILE: u64 = 7;
+
+        // Every entry has the same encoded length, so every data file fills up after
+        // exactly ENTRIES_PER_FILE entries, regardless of which handle wrote them.
+        let entry_len = LogFileEntry {
+            key: "a-0000".as_bytes().to_vec(),
+            value: Some("value".as_bytes().to_vec()),
+            expires_at: None,
+            transforms: Vec::new(),
+        }
+        .encode()
+        .unwrap()
+        .len() as u64;
+
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = Rustcask::builder()
+            .set_max_data_file_size(entry_len * ENTRIES_PER_FILE)
+            .open(temp_dir.path())
+            .unwrap();
+
+        let handles: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|prefix| {
+                let mut store = store.clone();
+                thread::spawn(move || {
+                    for i in 0..WRITES_PER_HANDLE {
+                        store
+                            .set(
+                                format!("{}-{:04}", prefix, i).into_bytes(),
+                                "value".as_bytes().to_vec(),
+                            )
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let total_entries = 2 * WRITES_PER_HANDLE as u64;
+        let full_files = total_entries / ENTRIES_PER_FILE;
+        assert_eq!(store.get_active_generation(), full_files);
+        assert_eq!(
+            store.get_active_data_file_size(),
+            (total_entries % ENTRIES_PER_FILE) * entry_len
+        );
+
+        let mut store = store;
+        store.sync().unwrap();
+        let generations = list_generations(temp_dir.path(), include_all_files).unwrap();
+        assert_eq!(generations.len() as u64, full_files + 1);
+        for generation in generations {
+            let entries = LogFileIterator::new(data_file_path(temp_dir.path(), &generation))
+                .unwrap()
+                .count() as u64;
+            let expected = if generation == full_files {
+                total_entries % ENTRIES_PER_FILE
+            } else {
+                ENTRIES_PER_FILE
+            };
+            assert_eq!(entries, expected, "generation {}", generation);
+        }
+        for prefix in ["a", "b"] {
+            for i in 0..WRITES_PER_HANDLE {
+                let key = format!("{}-{:04}", prefix, i).into_bytes();
+                assert_eq!(store.get(&key).unwrap(), Some("value".as_bytes().to_vec()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_open_with_hint_files() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder().open(temp_dir_path).unwrap();
+        for i in 0..32 {
+            store
+                .set(
+                    format!("key{}", i).into_bytes(),
+                    format!("value{}", i).into_bytes(),
+                )
+                .unwrap();
+        }
+        store.merge().unwrap();
+        drop(store);
+
+        assert!(utils::hint_file_path(temp_dir_path, 1).is_file());
+
+        for mode in [HintVerify::None, HintVerify::Sample, HintVerify::Full] {
+            let mut store = Rustcask::builder()
+                .set_hint_verification(mode)
+                .open(temp_dir_path)
+                .unwrap();
+            for i in 0..32 {
+                assert_eq!(
+                    store.get(&format!("key{}", i).into_bytes()).unwrap(),
+                    Some(format!("value{}", i).into_bytes())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_compressed_hint_file_builds_same_keydir() {
+        // Both stores set the same expiry times.
+        #[derive(Debug)]
+        struct FixedClock(SystemTime);
+        impl Clock for FixedClock {
+            fn now(&self) -> SystemTime {
+                self.0
+            }
+        }
+        let clock = Arc::new(FixedClock(SystemTime::now()));
+
+        let build_keydir = |hint_compression: bool| {
+            let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+            let mut store = Rustcask::builder()
+                .set_clock(clock.clone())
+                .set_hint_compression(hint_compression)
+                .open(temp_dir.path())
+                .unwrap();
+            for i in 0..1000 {
+                let key = format!("key{}", i).into_bytes();
+                let value = format!("value{}", i).into_bytes();
+                if i % 2 == 0 {
+                    store.set(key, value).unwrap();
+                } else {
+                    store
+                        .set_with_ttl(key, value, Duration::from_secs(3600))
+                        .unwrap();
+                }
+            }
+            store.merge().unwrap();
+            drop(store);
+
+            let hint_file_len = fs::metadata(utils::hint_file_path(temp_dir.path(), 1))
+                .unwrap()
+                .len();
+            let mut report = OpenReport::default();
+            let keydir =
+                KeyDir::new(temp_dir.path(), &RustcaskBuilder::default(), &mut report).unwrap();
+            assert!(report.is_clean());
+            let mut entries: Vec<_> = keydir
+                .into_iter()
+                .map(|(key, entry)| (key, entry.data_file_gen, entry.index, entry.expires_at))
+                .collect();
+            entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            (entries, hint_file_len)
+        };
+
+        let (entries, hint_file_len) = build_keydir(false);
+        let (compressed_entries, compressed_hint_file_len) = build_keydir(true);
+        assert_eq!(entries.len(), 1000);
+        assert_eq!(compressed_entries, entries);
+        assert!(compressed_hint_file_len < hint_file_len);
+    }
+
+    #[test]
+    fn test_open_with_corrupt_hint_file() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder().open(temp_dir_path).unwrap();
+        for i in 0..32 {
+            store
+                .set(
+                    format!("key{}", i).into_bytes(),
+                    format!("value{}", i).into_bytes(),
+                )
+                .unwrap();
+        }
+        store.merge().unwrap();
+        drop(store);
+
+        // Point the first hint entry at the second entry's value.
+        let hint_file = utils::hint_file_path(temp_dir_path, 1);
+        let mut hint_entries = hint::read_hint_file(&hint_file).unwrap();
+        let correct_index = hint_entries[0].index.clone();
+        hint_entries[0].index = hint_entries[1].index.clone();
+        hint::write_hint_file(&hint_file, &hint_entries, false).unwrap();
+        let corrupted_key = hint_entries[0].key.clone();
+
+        let store = Rustcask::builder()
+            .set_hint_verification(HintVerify::None)
+            .open(temp_dir_path)
+            .unwrap();
+        assert_eq!(
+            store
+                .keydir
+                .read()
+                .unwrap()
+                .get(&corrupted_key)
+                .unwrap()
+                .index,
+            hint_entries[1].index
+        );
+        drop(store);
+
+        for mode in [HintVerify::Sample, HintVerify::Full] {
+            let mut store = Rustcask::builder()
+                .set_hint_verification(mode)
+                .open(temp_dir_path)
+                .unwrap();
+            assert_eq!(
+                store
+                    .keydir
+                    .read()
+                    .unwrap()
+                    .get(&corrupted_key)
+                    .unwrap()
+                    .index,
+                correct_index
+            );
+            let expected_value = String::from_utf8(corrupted_key.clone())
+                .unwrap()
+                .replace("key", "value")
+                .into_bytes();
+            assert_eq!(store.get(&corrupted_key).unwrap(), Some(expected_value));
+        }
+    }
+
+    #[test]
+    fn test_hint_entry_past_end_of_data_file() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder().open(temp_dir_path).unwrap();
+        for i in 0..4 {
+            store
+                .set(
+                    format!("key{}", i).into_bytes(),
+                    format!("value{}", i).into_bytes(),
+                )
+                .unwrap();
+        }
+        store.merge().unwrap();
+        drop(store);
+
+        // Point the first hint entry past the end of the data file.
+        let file_len = fs::metadata(utils::data_file_path(temp_dir_path, &1))
+            .unwrap()
+            .len();
+        let hint_file = utils::hint_file_path(temp_dir_path, 1);
+        let mut hint_entries = hint::read_hint_file(&hint_file).unwrap();
+        hint_entries[0].index.offset = file_len;
+        hint::write_hint_file(&hint_file, &hint_entries, false).unwrap();
+        let corrupted_key = hint_entries[0].key.clone();
+
+        let mut store = Rustcask::builder()
+            .set_hint_verification(HintVerify::None)
+            .open(temp_dir_path)
+            .unwrap();
+        let err = store.get(&corrupted_key).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            GetErrorKind::IndexOutOfRange {
+                generation: 1,
+                offset,
+                file_len: err_file_len,
+                ..
+            } if offset == file_len && err_file_len == file_len
+        ));
+        drop(store);
+
+        // Sampled verification checks every entry's bounds, and rebuilds from the data file.
+        let (mut store, report) = Rustcask::builder()
+            .set_hint_verification(HintVerify::Sample)
+            .open_with_report(temp_dir_path)
+            .unwrap();
+        assert!(report
+            .anomalies()
+            .contains(&OpenAnomaly::HintFileRejected { generation: 1 }));
+        assert_eq!(store.get(&corrupted_key).unwrap(), Some(b"value0".to_vec()));
+    }
+
+    #[test]
+    fn test_auto_merge() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder()
+            .set_auto_merge(true)
+            .set_auto_merge_ratio(1.0)
+            .open(temp_dir_path)
+            .unwrap();
+
+        let num_keys = 10;
+        for i in 0..num_keys {
+            store.set_str(&format!("key{}", i), "value-a").unwrap();
+        }
+        // Overwrite all but one key. The dead ratio stays below 1.0.
+        for i in 0..num_keys - 1 {
+            store.set_str(&format!("key{}", i), "value-b").unwrap();
+        }
+        check_generations(temp_dir_path, vec![0]);
+
+        // This overwrite brings the dead ratio to 1.0 and triggers a merge.
+        store
+            .set_str(&format!("key{}", num_keys - 1), "value-b")
+            .unwrap();
+        check_generations(temp_dir_path, vec![1, 2]);
+        assert_eq!(store.keydir.read().unwrap().dead_entries(), 0);
+
+        for i in 0..num_keys {
+            assert_eq!(
+                store.get_str(&format!("key{}", i)).unwrap(),
+                Some("value-b".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_auto_merge_check_interval() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder()
+            .set_auto_merge(true)
+            .set_auto_merge_ratio(1.0)
+            .set_auto_merge_check_interval(8)
+            .open(temp_dir_path)
+            .unwrap();
+
+        let num_keys = 10;
+        for i in 0..num_keys {
+            store.set_str(&format!("key{}", i), "value-a").unwrap();
+        }
+        for i in 0..num_keys {
+            store.set_str(&format!("key{}", i), "value-b").unwrap();
+        }
+        // The dead ratio reached 1.0 on the 20th write, but the ratio was last checked on the 16th.
+        check_generations(temp_dir_path, vec![0]);
+        let size_before_merge = store.get_active_data_file_size();
+
+        for _ in 0..4 {
+            store.set_str("key0", "value-c").unwrap();
+        }
+        // The 24th write checks the ratio and merges.
+        check_generations(temp_dir_path, vec![1, 2]);
+        assert_eq!(store.keydir.read().unwrap().dead_entries(), 0);
+        let merged_size = fs::metadata(data_file_path(temp_dir_path, &1))
+            .unwrap()
+            .len();
+        assert!(merged_size < size_before_merge);
+
+        assert_eq!(store.get_str("key0").unwrap(), Some("value-c".to_string()));
+        assert_eq!(store.get_str("key1").unwrap(), Some("value-b".to_string()));
+    }
+
+    #[test]
+    fn test_auto_merge_disabled() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder()
+            .set_auto_merge_ratio(1.0)
+            .open(temp_dir_path)
+            .unwrap();
+
+        for _ in 0..10 {
+            store.set_str("key", "value").unwrap();
+        }
+        check_generations(temp_dir_path, vec![0]);
+    }
+
+    #[test]
+    fn test_flush_and_rotate() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder().open(temp_dir_path).unwrap();
+
+        store.set_str("key", "value").unwrap();
+        assert_eq!(store.get_active_generation(), 0);
+
+        let sealed_generation = store.flush_and_rotate().unwrap();
+
+        assert_eq!(sealed_generation, 0);
+        assert_eq!(store.get_active_generation(), 1);
+        assert_eq!(store.get_active_data_file_size(), 0);
+        let sealed_data_file = data_file_path(temp_dir_path, &sealed_generation);
+        assert!(fs::metadata(&sealed_data_file)
+            .unwrap()
+            .permissions()
+            .readonly());
+        assert_eq!(
+            get_keys_values(temp_dir_path, &"0.rustcask.data".to_string()),
+            vec![("key".as_bytes().to_vec(), "value".as_bytes().to_vec())]
+        );
+
+        // New writes go to the new active data file.
+        store.set_str("key", "new-value").unwrap();
+        assert_eq!(
+            get_keys(temp_dir_path, &"1.rustcask.data".to_string()),
+            vec!["key".as_bytes().to_vec()]
+        );
+        assert_eq!(store.get_str("key").unwrap(), Some("new-value".to_string()));
+    }
+
+    #[test]
+    fn test_lazy_active_file() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder()
+            .set_lazy_active_file(true)
+            .open(temp_dir_path)
+            .unwrap();
+
+        assert_eq!(store.get_str("key").unwrap(), None);
+        assert_eq!(store.get_active_generation(), 0);
+        assert!(file_names(temp_dir_path).is_empty());
+
+        store.set_str("key", "value").unwrap();
+        assert_eq!(file_names(temp_dir_path), vec!["0.rustcask.data"]);
+        assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+    }
+
+    #[test]
+    fn test_parallel_merge_matches_sequential_merge() {
+        let merge_with_threads = |merge_threads: usize| {
+            let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+            let mut store = Rustcask::builder()
+                .set_max_data_file_size(4096)
+                .set_merge_threads(merge_threads)
+                .open(temp_dir.path())
+                .unwrap();
+
+            for round in 0..3 {
+                for i in 0..2000 {
+                    if i % 7 == round {
+                        store.remove(format!("key{}", i).into_bytes()).unwrap();
+                    } else {
+                        store
+                            .set_str(&format!("key{}", i), &format!("value{}-{}", i, round))
+                            .unwrap();
+                    }
+                }
+            }
+            store.merge().unwrap();
+            assert_eq!(store.get_str("key3").unwrap(), Some("value3-2".to_string()));
+            assert_eq!(store.get_str("key2").unwrap(), None);
+
+            let mut files: Vec<(String, Vec<u8>)> = file_names(temp_dir.path())
+                .into_iter()
+                .map(|name| {
+                    let contents = fs::read(temp_dir.path().join(&name)).unwrap();
+                    (name, contents)
+                })
+                .collect();
+            files.sort();
+            files
+        };
+
+        let sequential = merge_with_threads(1);
+        let parallel = merge_with_threads(4);
+        assert!(sequential.len() > 2);
+        assert!(sequential == parallel);
+    }
+
+    #[test]
+    fn test_set_remove_get_in_same_generation() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder().open(temp_dir_path).unwrap();
+
+        store.set_str("leader", "instance-a").unwrap();
+        assert_eq!(
+            store.remove("leader".as_bytes().to_vec()).unwrap(),
+            Some("instance-a".as_bytes().to_vec())
+        );
+        assert_eq!(store.get_str("leader").unwrap(), None);
+
+        // Both the value and its tombstone are still in the active data file.
+        let entries: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            LogFileIterator::new(temp_dir_path.join("0.rustcask.data"))
+                .unwrap()
+                .map(|(entry, _)| (entry.key, entry.value))
+                .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "leader".as_bytes().to_vec(),
+                    Some("instance-a".as_bytes().to_vec())
+                ),
+                ("leader".as_bytes().to_vec(), None),
+            ]
+        );
+
+        // The tombstone still hides the value after the keydir is rebuilt.
+        drop(store);
+        let mut store = Rustcask::builder().open(temp_dir_path).unwrap();
+        assert_eq!(store.get_str("leader").unwrap(), None);
+    }
+
+    #[test]
+    fn test_merge_drops_removed_value_and_tombstone() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let temp_dir_path = temp_dir.path();
+        let mut store = Rustcask::builder().open(temp_dir_path).unwrap();
+
+        store.set_str("leader", "instance-a").unwrap();
+        store.set_str("follower", "instance-b").unwrap();
+        store.remove("leader".as_bytes().to_vec()).unwrap();
+
+        store.merge().unwrap();
+
+        let mut data_files = file_names(temp_dir_path);
+        data_files.sort();
+        assert_eq!(
+            data_files,
+            vec!["1.rustcask.data", "1.rustcask.hint", "2.rustcask.data"]
+        );
+        let entries: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            LogFileIterator::new(temp_dir_path.join("1.rustcask.data"))
+                .unwrap()
+                .map(|(entry, _)| (entry.key, entry.value))
+                .collect();
+        assert_eq!(
+            entries,
+            vec![(
+                "follower".as_bytes().to_vec(),
+                Some("instance-b".as_bytes().to_vec())
+            )]
+        );
+        assert_eq!(store.get_str("leader").unwrap(), None);
+    }
+
     fn check_generations(temp_dir_path: &Path, expected_generations: Vec<GenerationNumber>) {
-        let mut generations: Vec<GenerationNumber> = list_generations(temp_dir_path).unwrap();
+        let mut generations: Vec<GenerationNumber> =
+            list_generations(temp_dir_path, include_all_files).unwrap();
         generations.sort_unstable();
         assert_eq!(generations, expected_generations);
     }
@@ -0,0 +1,167 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions, TryLockError},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The name of the lock file that guards a rustcask directory.
+pub(crate) const LOCK_FILE_NAME: &str = "rustcask.lock";
+
+const LOCKED_DIRS_POISON_ERR: &str =
+    "Another thread crashed while holding the locked directories lock. Panicking.";
+
+/// The directories locked by this process, by canonical path.
+static LOCKED_DIRS: Mutex<BTreeMap<PathBuf, LockedDir>> = Mutex::new(BTreeMap::new());
+
+pub(crate) fn lock_file_path(rustcask_dir: &Path) -> PathBuf {
+    rustcask_dir.join(LOCK_FILE_NAME)
+}
+
+/// A lock on a rustcask directory, held by every writable handle to the store, so that no
+/// other process can open the directory for writing at the same time.
+///
+/// Stores opened in the same process share the lock on their directory, and it's released
+/// when the last of them is dropped. The lock file itself is left in place, because deleting
+/// an unlocked file could let two processes lock different files under the same name.
+#[derive(Debug)]
+pub(crate) struct DirLock {
+    // The canonical path of the locked directory.
+    dir: PathBuf,
+}
+
+#[derive(Debug)]
+struct LockedDir {
+    // Holds the lock until it's closed.
+    _file: File,
+
+    // The number of `DirLock`s on the directory.
+    holders: usize,
+}
+
+impl DirLock {
+    /// Locks `rustcask_dir`, creating its lock file if it does not exist. The lock is shared
+    /// with the stores that already hold it in this process.
+    ///
+    /// Returns an `io::Error` of kind `WouldBlock` if another process holds the lock.
+    pub fn acquire(rustcask_dir: &Path) -> Result<Self, io::Error> {
+        Self::lock(rustcask_dir, false)
+    }
+
+    /// Locks `rustcask_dir` like `acquire`, but also fails if a store in this process holds
+    /// the lock. Used by operations that need the directory to themselves.
+    pub fn acquire_exclusive(rustcask_dir: &Path) -> Result<Self, io::Error> {
+        Self::lock(rustcask_dir, true)
+    }
+
+    fn lock(rustcask_dir: &Path, exclusive: bool) -> Result<Self, io::Error> {
+        let dir = fs::canonicalize(rustcask_dir)?;
+        let mut locked_dirs = LOCKED_DIRS.lock().expect(LOCKED_DIRS_POISON_ERR);
+        if let Some(locked_dir) = locked_dirs.get_mut(&dir) {
+            if exclusive {
+                return Err(locked_error(rustcask_dir, "a store in this process"));
+            }
+            locked_dir.holders += 1;
+            return Ok(Self { dir });
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(lock_file_path(&dir))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(locked_error(rustcask_dir, "another process"))
+            }
+            Err(TryLockError::Error(err)) => return Err(err),
+        }
+        locked_dirs.insert(
+            dir.clone(),
+            LockedDir {
+                _file: file,
+                holders: 1,
+            },
+        );
+        Ok(Self { dir })
+    }
+
+    /// Whether other stores in this process hold the lock too.
+    pub fn is_shared(&self) -> bool {
+        LOCKED_DIRS.lock().expect(LOCKED_DIRS_POISON_ERR)[&self.dir].holders > 1
+    }
+
+    /// Deletes the lock file and releases the lock, for a directory that the store is leaving.
+    /// See [`crate::Rustcask::move_to`].
+    ///
+    /// The file is deleted while it's still locked, so that another process can't lock it
+    /// in between. The lock must not be shared.
+    pub fn release_and_remove(self) -> Result<(), io::Error> {
+        debug_assert!(!self.is_shared());
+        fs::remove_file(lock_file_path(&self.dir))
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        let mut locked_dirs = LOCKED_DIRS.lock().expect(LOCKED_DIRS_POISON_ERR);
+        let locked_dir = locked_dirs
+            .get_mut(&self.dir)
+            .expect("A locked directory is registered");
+        locked_dir.holders -= 1;
+        if locked_dir.holders == 0 {
+            // Closing the lock file releases the lock.
+            locked_dirs.remove(&self.dir);
+        }
+    }
+}
+
+fn locked_error(rustcask_dir: &Path, holder: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::WouldBlock,
+        format!("{} is locked by {}", rustcask_dir.to_string_lossy(), holder),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{File, TryLockError},
+        io,
+    };
+
+    use tempfile::TempDir;
+
+    use super::{lock_file_path, DirLock};
+
+    #[test]
+    fn test_lock_is_shared_within_process_until_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = DirLock::acquire(temp_dir.path()).unwrap();
+        let second = DirLock::acquire(temp_dir.path()).unwrap();
+        assert!(first.is_shared());
+        let err = DirLock::acquire_exclusive(temp_dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        // Another process locks the file through its own handle.
+        let other_process = File::open(lock_file_path(temp_dir.path())).unwrap();
+        assert!(matches!(
+            other_process.try_lock(),
+            Err(TryLockError::WouldBlock)
+        ));
+        drop(first);
+        assert!(!second.is_shared());
+        drop(second);
+        other_process.try_lock().unwrap();
+        let err = DirLock::acquire(temp_dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        drop(other_process);
+
+        let lock = DirLock::acquire_exclusive(temp_dir.path()).unwrap();
+        lock.release_and_remove().unwrap();
+        assert!(!lock_file_path(temp_dir.path()).exists());
+    }
+}
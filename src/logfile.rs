@@ -1,42 +1,424 @@
-use std::{fs::File, io, path::PathBuf};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+};
 
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 use crate::bufio::BufReaderWithPos;
 
+/// Identifies a rustcask data file. Every data file starts with this magic,
+/// followed by the data file format version as a little endian `u32`.
+const DATA_FILE_MAGIC: [u8; 4] = *b"RCSK";
+
+/// The data file format version written by this version of rustcask.
+///
+/// * Version 0 - Data files have no header, and entries are serialized back to back.
+/// * Version 1 - Each entry is prefixed with its serialized length as a little endian `u32`.
+/// * Version 2 - Each entry records when its value expires.
+/// * Version 3 - Each entry records the value transforms that were applied to its value.
+/// * Version 4 - Each entry starts with a fixed size header that holds the lengths of its key
+///   and value, followed by the key and then the value, so that values can be skipped. The
+///   header also holds checksums of the entry.
+pub const DATA_FILE_VERSION: u32 = 4;
+
+/// The length in bytes of the header at the start of each data file.
+pub const DATA_FILE_HEADER_LEN: u64 = 8;
+
+/// The length in bytes of the header at the start of each entry. It holds, in order and in little
+/// endian, the key length as a `u32`, the value length as a `u32`, the number of value transforms
+/// as a `u8`, the entry's flags as a `u8`, the expiry time as a `u64`, and then three checksums as
+/// `u32`s: of the transform tags and the key, of the value, and of the header bytes before it. The
+/// header is followed by the transform tags, the key, and then the value.
+///
+/// The checksums are CRC32s. The lengths are only trusted once the header checksum matches, and
+/// the value has its own checksum, so that it can be skipped without being read.
+const ENTRY_HEADER_LEN: u64 = 30;
+
+/// The offset of the header checksum in the entry header. It covers the bytes before it.
+const HEADER_CHECKSUM_OFFSET: usize = 26;
+
+/// The number of bytes read at a time while looking for the next entry after a corrupt one.
+const RESYNC_CHUNK_LEN: u64 = 64 * 1024;
+
+/// Set in the entry flags if the entry is a tombstone. Tombstones have an empty value.
+const ENTRY_FLAG_TOMBSTONE: u8 = 1;
+
+/// Set in the entry flags if the value expires. Otherwise, the expiry time is 0.
+const ENTRY_FLAG_EXPIRES: u8 = 2;
+
 /// Represents an entry in the data or hint files.
+///
+/// The serde implementation matches the entries of version 3 data files, which were serialized
+/// with bincode. Data files now use the layout described by `ENTRY_HEADER_LEN`.
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq)]
 pub struct LogFileEntry {
-    //TODO [RyanStan 03/05/24] Add CRC and timestamp
     pub key: Vec<u8>,
 
     // None is used as a tombstone marker
     pub value: Option<Vec<u8>>,
+
+    // Milliseconds since the UNIX epoch after which the value expires. None if it never expires.
+    pub expires_at: Option<u64>,
+
+    // The tags of the value transforms that were applied to the value, in the order they
+    // were applied. See `ValueTransform`.
+    pub transforms: Vec<u8>,
 }
 
 impl LogFileEntry {
     pub fn create_tombstone_entry(key: Vec<u8>) -> Self {
-        Self { key, value: None }
+        Self {
+            key,
+            value: None,
+            expires_at: None,
+            transforms: Vec::new(),
+        }
+    }
+
+    /// Serializes the entry as it is stored in a data file: the entry header, followed by the
+    /// transform tags, the key, and the value. The checksums in the header are computed here.
+    pub fn encode(&self) -> bincode::Result<Vec<u8>> {
+        let value = self.value.as_deref().unwrap_or_default();
+        let size_limit = || Box::new(bincode::ErrorKind::SizeLimit);
+        let key_len = u32::try_from(self.key.len()).map_err(|_| size_limit())?;
+        let value_len = u32::try_from(value.len()).map_err(|_| size_limit())?;
+        let transforms_len = u8::try_from(self.transforms.len()).map_err(|_| size_limit())?;
+        let mut flags = 0;
+        if self.value.is_none() {
+            flags |= ENTRY_FLAG_TOMBSTONE;
+        }
+        if self.expires_at.is_some() {
+            flags |= ENTRY_FLAG_EXPIRES;
+        }
+
+        let mut encoded = Vec::with_capacity(
+            ENTRY_HEADER_LEN as usize + self.transforms.len() + self.key.len() + value.len(),
+        );
+        encoded.extend_from_slice(&key_len.to_le_bytes());
+        encoded.extend_from_slice(&value_len.to_le_bytes());
+        encoded.push(transforms_len);
+        encoded.push(flags);
+        encoded.extend_from_slice(&self.expires_at.unwrap_or(0).to_le_bytes());
+        let key_checksum = key_checksum(&self.transforms, &self.key);
+        encoded.extend_from_slice(&key_checksum.to_le_bytes());
+        encoded.extend_from_slice(&crc32fast::hash(value).to_le_bytes());
+        let header_checksum = crc32fast::hash(&encoded);
+        encoded.extend_from_slice(&header_checksum.to_le_bytes());
+        encoded.extend_from_slice(&self.transforms);
+        encoded.extend_from_slice(&self.key);
+        encoded.extend_from_slice(value);
+        Ok(encoded)
+    }
+
+    /// Returns the largest encoded length of an entry with the given key and value lengths,
+    /// and number of value transforms.
+    pub fn max_encoded_len(key_len: usize, value_len: usize, transforms_len: usize) -> u64 {
+        ENTRY_HEADER_LEN + transforms_len as u64 + key_len as u64 + value_len as u64
+    }
+
+    /// Reads a single entry from the reader.
+    pub fn decode_from<R: Read>(reader: R) -> bincode::Result<Self> {
+        let mut payload = Vec::new();
+        read_entry_payload(reader, &mut payload)?;
+        Ok(LogFileEntryRef::decode(&payload)?.into())
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// The fixed size header at the start of each entry. See `ENTRY_HEADER_LEN`.
+#[derive(Debug)]
+struct EntryHeader {
+    key_len: u64,
+    value_len: u64,
+    transforms_len: u64,
+    flags: u8,
+    expires_at: u64,
+    key_checksum: u32,
+    value_checksum: u32,
+}
+
+impl EntryHeader {
+    fn parse(header: &[u8; ENTRY_HEADER_LEN as usize]) -> Self {
+        Self {
+            key_len: u32::from_le_bytes(header[0..4].try_into().unwrap()) as u64,
+            value_len: u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64,
+            transforms_len: header[8] as u64,
+            flags: header[9],
+            expires_at: u64::from_le_bytes(header[10..18].try_into().unwrap()),
+            key_checksum: u32::from_le_bytes(header[18..22].try_into().unwrap()),
+            value_checksum: u32::from_le_bytes(header[22..26].try_into().unwrap()),
+        }
+    }
+
+    /// Returns the length of the whole entry, including the header.
+    fn entry_len(&self) -> u64 {
+        ENTRY_HEADER_LEN + self.transforms_len + self.key_len + self.value_len
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.flags & ENTRY_FLAG_TOMBSTONE != 0
+    }
+
+    fn expires_at(&self) -> Option<u64> {
+        (self.flags & ENTRY_FLAG_EXPIRES != 0).then_some(self.expires_at)
+    }
+
+    /// Parses a header whose checksum matches, and whose flags and expiry time agree with
+    /// each other. The lengths of an entry whose header fails these checks can't be trusted.
+    fn parse_valid(header: &[u8; ENTRY_HEADER_LEN as usize]) -> bincode::Result<Self> {
+        let (checked, checksum) = header.split_at(HEADER_CHECKSUM_OFFSET);
+        if crc32fast::hash(checked) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(Box::new(bincode::ErrorKind::Custom(
+                "entry header checksum doesn't match".to_string(),
+            )));
+        }
+        let parsed = Self::parse(header);
+        parsed.validate()?;
+        Ok(parsed)
+    }
+
+    /// Checks the fields that the lengths don't describe.
+    fn validate(&self) -> bincode::Result<()> {
+        let invalid = |message: String| Err(Box::new(bincode::ErrorKind::Custom(message)));
+        if self.flags & !(ENTRY_FLAG_TOMBSTONE | ENTRY_FLAG_EXPIRES) != 0 {
+            return invalid(format!("invalid entry flags {:#x}", self.flags));
+        }
+        if self.is_tombstone() && (self.value_len != 0 || self.expires_at().is_some()) {
+            return invalid("tombstone with a value or an expiry time".to_string());
+        }
+        if self.expires_at().is_none() && self.expires_at != 0 {
+            return invalid("expiry time on an entry that doesn't expire".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Returns the checksum of an entry's transform tags and key. See `ENTRY_HEADER_LEN`.
+fn key_checksum(transforms: &[u8], key: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(transforms);
+    hasher.update(key);
+    hasher.finalize()
+}
+
+/// Checks an entry's transform tags and key against the checksum in its header.
+fn validate_key(header: &EntryHeader, transforms: &[u8], key: &[u8]) -> bincode::Result<()> {
+    if key_checksum(transforms, key) != header.key_checksum {
+        return Err(Box::new(bincode::ErrorKind::Custom(
+            "entry key checksum doesn't match".to_string(),
+        )));
+    }
+    Ok(())
+}
+
+/// Checks an entry's value against the checksum in its header.
+fn validate_value(header: &EntryHeader, value: &[u8]) -> bincode::Result<()> {
+    if crc32fast::hash(value) != header.value_checksum {
+        return Err(Box::new(bincode::ErrorKind::Custom(
+            "entry value checksum doesn't match".to_string(),
+        )));
+    }
+    Ok(())
+}
+
+/// A serialized `LogFileEntry` whose key and value are borrowed from the serialized bytes,
+/// rather than copied out of them.
+#[derive(Debug)]
+pub struct LogFileEntryRef<'a> {
+    pub key: &'a [u8],
+    pub value: Option<&'a [u8]>,
+    pub expires_at: Option<u64>,
+    pub transforms: &'a [u8],
+}
+
+impl<'a> LogFileEntryRef<'a> {
+    /// Deserializes an entry read by `read_entry_payload`, and checks it against the checksums
+    /// in its header.
+    pub fn decode(payload: &'a [u8]) -> bincode::Result<Self> {
+        let Some((header, body)) = payload.split_first_chunk::<{ ENTRY_HEADER_LEN as usize }>()
+        else {
+            return Err(Box::new(bincode::ErrorKind::Custom(
+                "entry is shorter than its header".to_string(),
+            )));
+        };
+        let header = EntryHeader::parse_valid(header)?;
+        if header.entry_len() != payload.len() as u64 {
+            return Err(Box::new(bincode::ErrorKind::Custom(
+                "entry length doesn't match its header".to_string(),
+            )));
+        }
+
+        let (transforms, body) = body.split_at(header.transforms_len as usize);
+        let (key, value) = body.split_at(header.key_len as usize);
+        validate_key(&header, transforms, key)?;
+        validate_value(&header, value)?;
+        Ok(Self {
+            key,
+            value: (!header.is_tombstone()).then_some(value),
+            expires_at: header.expires_at(),
+            transforms,
+        })
+    }
+}
+
+impl From<LogFileEntryRef<'_>> for LogFileEntry {
+    fn from(entry: LogFileEntryRef<'_>) -> Self {
+        LogFileEntry {
+            key: entry.key.to_vec(),
+            value: entry.value.map(<[u8]>::to_vec),
+            expires_at: entry.expires_at,
+            transforms: entry.transforms.to_vec(),
+        }
+    }
+}
+
+/// Reads a whole entry, including its header, into `payload`, replacing its contents.
+/// The payload's allocation is reused when it's large enough.
+pub fn read_entry_payload<R: Read>(mut reader: R, payload: &mut Vec<u8>) -> io::Result<()> {
+    let mut header = [0; ENTRY_HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+    let entry_len = EntryHeader::parse(&header).entry_len() as usize;
+    payload.clear();
+    payload.resize(entry_len, 0);
+    payload[..header.len()].copy_from_slice(&header);
+    reader.read_exact(&mut payload[header.len()..])
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct LogIndex {
-    // Offset of log entry in bytes
+    // Offset of log entry in bytes, including its header
     pub offset: u64,
-    // Length of log entry in bytes
+    // Length of log entry in bytes, including its header
     pub len: u64,
 }
 
+/// Writes the header that starts every data file.
+pub fn write_data_file_header<W: Write>(mut writer: W) -> io::Result<()> {
+    writer.write_all(&DATA_FILE_MAGIC)?;
+    writer.write_all(&DATA_FILE_VERSION.to_le_bytes())
+}
+
+/// Reads the format version from the start of a data file, leaving the reader
+/// positioned at the first entry.
+///
+/// Returns `None` for an empty data file. Data files that don't start with a
+/// header were written before the header was introduced, and are version 0.
+pub fn read_data_file_version<R: Read + Seek>(reader: &mut R) -> io::Result<Option<u32>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = Vec::with_capacity(DATA_FILE_HEADER_LEN as usize);
+    reader
+        .by_ref()
+        .take(DATA_FILE_HEADER_LEN)
+        .read_to_end(&mut header)?;
+
+    if header.is_empty() {
+        return Ok(None);
+    }
+    if header.len() as u64 == DATA_FILE_HEADER_LEN && header[..4] == DATA_FILE_MAGIC {
+        let version = u32::from_le_bytes(header[4..].try_into().unwrap());
+        return Ok(Some(version));
+    }
+
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(Some(0))
+}
+
+/// Returns the format version of the data file at the given path.
+/// See [`read_data_file_version`].
+pub fn data_file_version(data_file: &Path) -> io::Result<Option<u32>> {
+    read_data_file_version(&mut File::open(data_file)?)
+}
+
+/// Iterates over the entries of a data file.
+///
+/// The iterator only sees entries that were in the data file when it was created. To scan the
+/// active data file, hold the writer lock and flush the active data file first, so that no
+/// entries are buffered or appended during the scan. See `Writer::export`.
 pub struct LogFileIterator {
     log_path: PathBuf,
     reader: BufReaderWithPos<File>,
+    log_len: u64,
+
+    // The offsets of the corrupt entries that were skipped.
+    corrupt_offsets: Vec<u64>,
+
+    // The offset of the truncated entry that ended iteration, if there was one.
+    truncated_offset: Option<u64>,
+
+    // The number of entries read ahead at a time. 0 disables prefetching.
+    prefetch: usize,
+    prefetched: VecDeque<(LogFileEntry, LogIndex)>,
+    // Whether prefetching reached the end of the entries.
+    prefetch_done: bool,
 }
 
 impl LogFileIterator {
     pub fn new(log_path: PathBuf) -> io::Result<Self> {
-        let reader = BufReaderWithPos::new(File::open(&log_path)?)?;
-        Ok(Self { log_path, reader })
+        let file = File::open(&log_path)?;
+        let log_len = file.metadata()?.len();
+        let mut reader = BufReaderWithPos::new(file)?;
+        match read_data_file_version(&mut reader)? {
+            None | Some(DATA_FILE_VERSION) => {}
+            Some(version) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "data file {} has unsupported format version {}",
+                        log_path.display(),
+                        version
+                    ),
+                ))
+            }
+        }
+        Ok(Self {
+            log_path,
+            reader,
+            log_len,
+            corrupt_offsets: Vec::new(),
+            truncated_offset: None,
+            prefetch: 0,
+            prefetched: VecDeque::new(),
+            prefetch_done: false,
+        })
+    }
+
+    /// Makes the iterator read and deserialize up to `entries` entries at a time, and hand them
+    /// out from a queue. Reading in batches keeps the reader's buffer busy and separates I/O from
+    /// the caller's processing of each entry. 0 disables prefetching, which is the default.
+    ///
+    /// Corrupt and truncated entries are reported as soon as they're prefetched, which may be
+    /// before the entries before them are returned.
+    pub fn with_prefetch(mut self, entries: usize) -> Self {
+        self.prefetch = entries;
+        self
+    }
+
+    /// Moves the iterator to the entry at `offset`. Offsets inside the header move the iterator
+    /// to the first entry.
+    ///
+    /// `offset` must be the offset of an entry, or the end of the data file. Otherwise,
+    /// the bytes at `offset` are read as an entry, and are most likely skipped as corrupt.
+    pub fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        self.prefetched.clear();
+        self.prefetch_done = false;
+        let offset = offset.max(self.reader.pos());
+        self.reader.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Returns the offsets of the corrupt entries that have been skipped so far.
+    pub fn corrupt_offsets(&self) -> &[u64] {
+        &self.corrupt_offsets
+    }
+
+    /// Returns the offset of the truncated entry at the end of the data file, once iteration has
+    /// reached it. No complete entry follows a truncated entry.
+    pub fn truncated_offset(&self) -> Option<u64> {
+        self.truncated_offset
     }
 }
 
@@ -45,32 +427,322 @@ impl Iterator for LogFileIterator {
     // instead of just panicking or returning None.
     type Item = (LogFileEntry, LogIndex);
 
+    /// Returns the next valid entry in the data file.
+    ///
+    /// Entries that fail to deserialize, or whose checksums don't match, are skipped. If the
+    /// header of an entry is corrupt, or its lengths run past the end of the file, the next entry
+    /// is found by scanning forward for a header whose checksum matches. If there's no complete
+    /// entry after it, it's a truncated entry at the end of the file, which is what an interrupted
+    /// write leaves behind, and iteration stops there.
     fn next(&mut self) -> Option<Self::Item> {
-        let offset = self.reader.pos();
-        match bincode::deserialize_from::<_, LogFileEntry>(&mut self.reader) {
-            Ok(log_file_entry) => {
-                let len = self.reader.pos() - offset;
-                Some((log_file_entry, LogIndex { offset, len }))
+        let prefetch = self.prefetch;
+        let mut prefetched = mem::take(&mut self.prefetched);
+        let mut prefetch_done = self.prefetch_done;
+        let entry = next_prefetched(prefetch, &mut prefetched, &mut prefetch_done, || {
+            self.read_entry()
+        });
+        self.prefetched = prefetched;
+        self.prefetch_done = prefetch_done;
+        entry
+    }
+}
+
+/// Returns the next item from `read`, reading up to `prefetch` items at a time into `prefetched`.
+/// `done` is set once `read` runs out of items.
+fn next_prefetched<T>(
+    prefetch: usize,
+    prefetched: &mut VecDeque<T>,
+    done: &mut bool,
+    mut read: impl FnMut() -> Option<T>,
+) -> Option<T> {
+    if prefetch == 0 {
+        return read();
+    }
+    if prefetched.is_empty() && !*done {
+        while prefetched.len() < prefetch {
+            match read() {
+                Some(item) => prefetched.push_back(item),
+                None => {
+                    *done = true;
+                    break;
+                }
             }
-            Err(err) => match err.as_ref() {
-                bincode::ErrorKind::Io(io_error) => match io_error.kind() {
-                    std::io::ErrorKind::UnexpectedEof => None,
-                    _ => panic!("Error deserializing data file: {:?}", io_error),
+        }
+    }
+    prefetched.pop_front()
+}
+
+/// The key of a data file entry, read without necessarily reading its value.
+/// See [`LogFileIterator::keys`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogFileKey {
+    pub key: Vec<u8>,
+
+    // The length of the value, or None if the entry is a tombstone.
+    pub value_len: Option<u64>,
+
+    // The value, if it was short enough to be read.
+    pub value: Option<Vec<u8>>,
+
+    pub expires_at: Option<u64>,
+    pub transforms: Vec<u8>,
+}
+
+/// Iterates over the keys of a data file, skipping over values that are longer than a limit.
+/// See [`LogFileIterator::keys`].
+pub struct LogFileKeys<'a> {
+    iter: &'a mut LogFileIterator,
+    max_value_len: u64,
+    prefetched: VecDeque<(LogFileKey, LogIndex)>,
+    prefetch_done: bool,
+}
+
+impl Iterator for LogFileKeys<'_> {
+    type Item = (LogFileKey, LogIndex);
+
+    /// Returns the key of the next valid entry in the data file. Entries are skipped and
+    /// iteration stops the same way as with [`LogFileIterator::next`], except that values
+    /// that aren't read can't be found to be corrupt.
+    fn next(&mut self) -> Option<Self::Item> {
+        let max_value_len = self.max_value_len;
+        let iter = &mut *self.iter;
+        next_prefetched(
+            iter.prefetch,
+            &mut self.prefetched,
+            &mut self.prefetch_done,
+            || iter.read_key(max_value_len),
+        )
+    }
+}
+
+impl LogFileIterator {
+    /// Returns an iterator over the keys of the remaining entries. Values longer than
+    /// `max_value_len` bytes are skipped over without being read, which makes scanning a data
+    /// file with large values much cheaper when only its keys are needed.
+    ///
+    /// Corrupt and truncated entries are still recorded by this iterator.
+    pub fn keys(&mut self, max_value_len: usize) -> LogFileKeys<'_> {
+        self.prefetched.clear();
+        self.prefetch_done = false;
+        LogFileKeys {
+            iter: self,
+            max_value_len: max_value_len as u64,
+            prefetched: VecDeque::new(),
+            prefetch_done: false,
+        }
+    }
+
+    /// Reads the next valid entry from the data file. See [`LogFileIterator::next`].
+    fn read_entry(&mut self) -> Option<(LogFileEntry, LogIndex)> {
+        loop {
+            let (header, transforms, key, index) = self.read_entry_header()?;
+            let mut value = vec![0; header.value_len as usize];
+            self.read_or_panic(&mut value);
+            if let Err(err) = validate_value(&header, &value) {
+                self.skip_corrupt_entry(index.offset, &err);
+                continue;
+            }
+            return Some((
+                LogFileEntry {
+                    key,
+                    value: (!header.is_tombstone()).then_some(value),
+                    expires_at: header.expires_at(),
+                    transforms,
                 },
-                _ => panic!("Error deserializing data file: {:?}", err),
-            },
+                index,
+            ));
+        }
+    }
+
+    /// Reads the key of the next valid entry from the data file, and its value if it's no
+    /// longer than `max_value_len`. See [`LogFileIterator::keys`].
+    fn read_key(&mut self, max_value_len: u64) -> Option<(LogFileKey, LogIndex)> {
+        loop {
+            let (header, transforms, key, index) = self.read_entry_header()?;
+
+            let value_len = (!header.is_tombstone()).then_some(header.value_len);
+            let value = match value_len {
+                Some(value_len) if value_len <= max_value_len => {
+                    let mut value = vec![0; value_len as usize];
+                    self.read_or_panic(&mut value);
+                    if let Err(err) = validate_value(&header, &value) {
+                        self.skip_corrupt_entry(index.offset, &err);
+                        continue;
+                    }
+                    Some(value)
+                }
+                _ => {
+                    self.reader
+                        .seek_relative(header.value_len as i64)
+                        .unwrap_or_else(|err| self.panic_on_read_error(err));
+                    None
+                }
+            };
+            return Some((
+                LogFileKey {
+                    key,
+                    value_len,
+                    value,
+                    expires_at: header.expires_at(),
+                    transforms,
+                },
+                index,
+            ));
+        }
+    }
+
+    /// Reads the header, transform tags, and key of the next valid entry, skipping corrupt
+    /// entries, and leaves the reader at the start of the entry's value. Returns None at the end
+    /// of the entries, or at a truncated entry.
+    #[allow(clippy::type_complexity)]
+    fn read_entry_header(&mut self) -> Option<(EntryHeader, Vec<u8>, Vec<u8>, LogIndex)> {
+        loop {
+            let offset = self.reader.pos();
+            if offset >= self.log_len {
+                return None;
+            }
+
+            let mut header = [0; ENTRY_HEADER_LEN as usize];
+            if offset + ENTRY_HEADER_LEN > self.log_len {
+                return self.truncated_at(offset);
+            }
+            self.read_or_panic(&mut header);
+            let header = match EntryHeader::parse_valid(&header) {
+                Ok(header) => header,
+                Err(err) => {
+                    // The lengths in the header can't be trusted to find the next entry.
+                    self.skip_to_next_entry(offset, &err)?;
+                    continue;
+                }
+            };
+
+            let len = header.entry_len();
+            if offset + len > self.log_len {
+                let err = Box::new(bincode::ErrorKind::Custom(
+                    "entry runs past the end of the data file".to_string(),
+                ));
+                self.skip_to_next_entry(offset, &err)?;
+                continue;
+            }
+
+            let mut transforms = vec![0; header.transforms_len as usize];
+            self.read_or_panic(&mut transforms);
+            let mut key = vec![0; header.key_len as usize];
+            self.read_or_panic(&mut key);
+            match validate_key(&header, &transforms, &key) {
+                Ok(()) => return Some((header, transforms, key, LogIndex { offset, len })),
+                Err(err) => {
+                    self.skip_corrupt_entry(offset, &err);
+                    self.seek_or_panic(offset + len);
+                }
+            }
+        }
+    }
+
+    /// Skips the entry at `offset`, whose lengths can't be used, by moving to the next entry
+    /// after it. If there isn't a complete entry after it, the entry is what an interrupted write
+    /// leaves behind, and the data file is truncated there. Returns None in that case.
+    ///
+    /// Only the end of the data file is treated as truncated, so that a corrupt length in the
+    /// middle of a data file doesn't hide the entries after it.
+    fn skip_to_next_entry(&mut self, offset: u64, err: &bincode::Error) -> Option<()> {
+        match self.find_next_entry(offset) {
+            Some(next_offset) => {
+                self.skip_corrupt_entry(offset, err);
+                self.seek_or_panic(next_offset);
+                Some(())
+            }
+            None => self.truncated_at(offset),
+        }
+    }
+
+    fn skip_corrupt_entry(&mut self, offset: u64, err: &bincode::Error) {
+        warn!(
+            "Skipping corrupt entry at offset {} in data file {}: {}",
+            offset,
+            self.log_path.display(),
+            err
+        );
+        self.corrupt_offsets.push(offset);
+    }
+
+    /// Returns the offset of the first entry after `offset` whose header checksum matches, and
+    /// that ends within the data file, or None if there isn't one. The data file is scanned in
+    /// chunks.
+    fn find_next_entry(&mut self, offset: u64) -> Option<u64> {
+        let mut chunk = Vec::new();
+        let mut chunk_start = offset + 1;
+        while chunk_start + ENTRY_HEADER_LEN <= self.log_len {
+            // Chunks overlap, so that headers that span two chunks are seen whole.
+            let chunk_len =
+                (RESYNC_CHUNK_LEN + ENTRY_HEADER_LEN - 1).min(self.log_len - chunk_start);
+            chunk.resize(chunk_len as usize, 0);
+            self.seek_or_panic(chunk_start);
+            self.read_or_panic(&mut chunk);
+
+            let log_len = self.log_len;
+            let next_entry = chunk
+                .windows(ENTRY_HEADER_LEN as usize)
+                .enumerate()
+                .find_map(|(i, header)| {
+                    let candidate = chunk_start + i as u64;
+                    let header = EntryHeader::parse_valid(header.try_into().unwrap()).ok()?;
+                    (candidate + header.entry_len() <= log_len).then_some(candidate)
+                });
+            if next_entry.is_some() {
+                return next_entry;
+            }
+            chunk_start += RESYNC_CHUNK_LEN;
         }
+        None
+    }
+
+    fn truncated_at<T>(&mut self, offset: u64) -> Option<T> {
+        warn!(
+            "Data file {} ends with a truncated entry at offset {}.",
+            self.log_path.display(),
+            offset
+        );
+        self.truncated_offset = Some(offset);
+        None
+    }
+
+    fn seek_or_panic(&mut self, offset: u64) {
+        if let Err(err) = self.reader.seek(SeekFrom::Start(offset)) {
+            self.panic_on_read_error(err)
+        }
+    }
+
+    fn read_or_panic(&mut self, buf: &mut [u8]) {
+        if let Err(err) = self.reader.read_exact(buf) {
+            self.panic_on_read_error(err)
+        }
+    }
+
+    fn panic_on_read_error(&self, err: io::Error) -> ! {
+        panic!(
+            "Error reading data file {}: {:?}",
+            self.log_path.display(),
+            err
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, io::Write, path::PathBuf};
+    use std::{
+        fs::{self, File},
+        io::Write,
+        path::PathBuf,
+    };
 
     use tempfile::TempDir;
 
     use crate::{
-        logfile::{LogFileEntry, LogFileIterator, LogIndex},
+        logfile::{
+            data_file_version, write_data_file_header, LogFileEntry, LogFileIterator, LogFileKey,
+            LogIndex, DATA_FILE_HEADER_LEN, DATA_FILE_VERSION,
+        },
         utils::data_file_path,
     };
 
@@ -78,22 +750,23 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let data_file_path = data_file_path(temp_dir.path(), &0);
         let mut data_file = File::create(&data_file_path).unwrap();
+        write_data_file_header(&mut data_file).unwrap();
 
         let mut encoded_lens = Vec::new();
         let mut offsets = Vec::new();
 
-        let mut offset = 0;
+        let mut offset = DATA_FILE_HEADER_LEN as usize;
 
         for entry in entries {
-            let encoded = bincode::serialize(&entry).unwrap();
+            let encoded = entry.encode().unwrap();
             let entry_len = encoded.len();
             encoded_lens.push(encoded.len());
             offsets.push(offset);
             offset += entry_len;
-            let _ = data_file.write_all(&encoded).unwrap();
+            data_file.write_all(&encoded).unwrap();
         }
 
-        let _ = data_file.flush().unwrap();
+        data_file.flush().unwrap();
 
         (temp_dir, data_file_path, encoded_lens, offsets)
     }
@@ -103,9 +776,10 @@ mod tests {
         let entry = LogFileEntry {
             key: "key".as_bytes().to_vec(),
             value: Some("value".as_bytes().to_vec()),
+            expires_at: None,
+            transforms: Vec::new(),
         };
-        let mut entries = Vec::new();
-        entries.push(entry);
+        let entries = vec![entry];
         let expected_num_entries = entries.len();
 
         let (_temp_dir, data_file_path, entry_lens, entry_offsets) =
@@ -130,10 +804,14 @@ mod tests {
             LogFileEntry {
                 key: "key".as_bytes().to_vec(),
                 value: Some("value".as_bytes().to_vec()),
+                expires_at: None,
+                transforms: Vec::new(),
             },
             LogFileEntry {
                 key: "key2".as_bytes().to_vec(),
                 value: Some("value2".as_bytes().to_vec()),
+                expires_at: None,
+                transforms: Vec::new(),
             },
         ]);
         let expected_num_entries = entries.len();
@@ -155,4 +833,204 @@ mod tests {
             )
         }
     }
+
+    #[test]
+    fn test_log_iter_skips_corrupt_entry() {
+        let entries: Vec<LogFileEntry> = (0..3)
+            .map(|i| LogFileEntry {
+                key: format!("key{}", i).into_bytes(),
+                value: Some(format!("value{}", i).into_bytes()),
+                expires_at: None,
+                transforms: Vec::new(),
+            })
+            .collect();
+
+        let (_temp_dir, data_file_path, entry_lens, entry_offsets) =
+            setup_data_file(entries.clone());
+        let original = fs::read(&data_file_path).unwrap();
+
+        // Corrupt the flags of the middle entry, and then each of its lengths. The header checksum
+        // no longer matches, so the lengths aren't trusted to find the next entry, whether or not
+        // they still fit within the data file.
+        for (byte, mask) in [(9, 0xff), (0, 1), (5, 1)] {
+            let mut bytes = original.clone();
+            bytes[entry_offsets[1] + byte] ^= mask;
+            fs::write(&data_file_path, bytes).unwrap();
+
+            let mut log_iter = LogFileIterator::new(data_file_path.clone()).unwrap();
+            let data_entries: Vec<(LogFileEntry, LogIndex)> = log_iter.by_ref().collect();
+
+            assert_eq!(data_entries.len(), 2);
+            for (data_entry, i) in data_entries.iter().zip([0, 2]) {
+                assert_eq!(data_entry.0, entries[i]);
+                assert_eq!(
+                    data_entry.1,
+                    LogIndex {
+                        offset: entry_offsets[i] as u64,
+                        len: entry_lens[i] as u64
+                    }
+                );
+            }
+            assert_eq!(log_iter.corrupt_offsets(), [entry_offsets[1] as u64]);
+            assert_eq!(log_iter.truncated_offset(), None);
+        }
+    }
+
+    #[test]
+    fn test_log_iter_stops_at_truncated_entry() {
+        let entries: Vec<LogFileEntry> = (0..2)
+            .map(|i| LogFileEntry {
+                key: format!("key{}", i).into_bytes(),
+                value: Some(format!("value{}", i).into_bytes()),
+                expires_at: None,
+                transforms: Vec::new(),
+            })
+            .collect();
+
+        let (_temp_dir, data_file_path, _, _) = setup_data_file(entries.clone());
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .open(&data_file_path)
+            .unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 1).unwrap();
+
+        let data_entries: Vec<(LogFileEntry, LogIndex)> =
+            LogFileIterator::new(data_file_path).unwrap().collect();
+
+        assert_eq!(data_entries.len(), 1);
+        assert_eq!(data_entries[0].0, entries[0]);
+    }
+
+    #[test]
+    fn test_log_iter_treats_corrupt_last_entry_as_truncated() {
+        let entries: Vec<LogFileEntry> = (0..2)
+            .map(|i| LogFileEntry {
+                key: format!("key{}", i).into_bytes(),
+                value: Some(format!("value{}", i).into_bytes()),
+                expires_at: None,
+                transforms: Vec::new(),
+            })
+            .collect();
+        let (_temp_dir, data_file_path, _, entry_offsets) = setup_data_file(entries.clone());
+
+        // No complete entry follows the corrupt header, so it's what an interrupted write
+        // left behind.
+        let mut bytes = fs::read(&data_file_path).unwrap();
+        bytes[entry_offsets[1]] ^= 1;
+        fs::write(&data_file_path, bytes).unwrap();
+
+        let mut log_iter = LogFileIterator::new(data_file_path).unwrap();
+        let data_entries: Vec<(LogFileEntry, LogIndex)> = log_iter.by_ref().collect();
+        assert_eq!(data_entries.len(), 1);
+        assert_eq!(data_entries[0].0, entries[0]);
+        assert!(log_iter.corrupt_offsets().is_empty());
+        assert_eq!(log_iter.truncated_offset(), Some(entry_offsets[1] as u64));
+    }
+
+    #[test]
+    fn test_log_iter_prefetch_returns_same_entries() {
+        let entries: Vec<LogFileEntry> = (0..5)
+            .map(|i| LogFileEntry {
+                key: format!("key{}", i).into_bytes(),
+                value: Some(format!("value{}", i).into_bytes()),
+                expires_at: None,
+                transforms: Vec::new(),
+            })
+            .collect();
+        let (_temp_dir, data_file_path, _, _) = setup_data_file(entries);
+
+        let expected: Vec<(LogFileEntry, LogIndex)> = LogFileIterator::new(data_file_path.clone())
+            .unwrap()
+            .collect();
+        for prefetch in [1, 2, 5, 8] {
+            let mut log_iter = LogFileIterator::new(data_file_path.clone())
+                .unwrap()
+                .with_prefetch(prefetch);
+            let data_entries: Vec<(LogFileEntry, LogIndex)> = log_iter.by_ref().collect();
+            assert_eq!(data_entries, expected);
+            assert_eq!(log_iter.next(), None);
+            assert_eq!(log_iter.truncated_offset(), None);
+        }
+    }
+
+    #[test]
+    fn test_log_iter_keys_skips_values() {
+        let entries = vec![
+            LogFileEntry {
+                key: b"small".to_vec(),
+                value: Some(b"value".to_vec()),
+                expires_at: None,
+                transforms: Vec::new(),
+            },
+            LogFileEntry {
+                key: b"large".to_vec(),
+                value: Some(vec![7; 64 * 1024]),
+                expires_at: Some(42),
+                transforms: vec![1],
+            },
+            LogFileEntry::create_tombstone_entry(b"small".to_vec()),
+            LogFileEntry {
+                key: b"empty".to_vec(),
+                value: Some(Vec::new()),
+                expires_at: None,
+                transforms: Vec::new(),
+            },
+        ];
+        let (_temp_dir, data_file_path, _, _) = setup_data_file(entries);
+
+        let full: Vec<(LogFileEntry, LogIndex)> = LogFileIterator::new(data_file_path.clone())
+            .unwrap()
+            .collect();
+        for prefetch in [0, 2] {
+            let mut log_iter = LogFileIterator::new(data_file_path.clone())
+                .unwrap()
+                .with_prefetch(prefetch);
+            let keys: Vec<(LogFileKey, LogIndex)> = log_iter.keys(16).collect();
+
+            assert_eq!(keys.len(), full.len());
+            for ((key, key_index), (entry, entry_index)) in keys.iter().zip(&full) {
+                assert_eq!(key_index, entry_index);
+                assert_eq!(key.key, entry.key);
+                assert_eq!(key.expires_at, entry.expires_at);
+                assert_eq!(key.transforms, entry.transforms);
+                assert_eq!(
+                    key.value_len,
+                    entry.value.as_ref().map(|value| value.len() as u64)
+                );
+                // Only values within the limit are read.
+                let expected_value = entry.value.clone().filter(|value| value.len() <= 16);
+                assert_eq!(key.value, expected_value);
+            }
+            assert_eq!(log_iter.next(), None);
+        }
+    }
+
+    #[test]
+    fn test_data_file_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_file_path = data_file_path(temp_dir.path(), &0);
+
+        File::create(&data_file_path).unwrap();
+        assert_eq!(data_file_version(&data_file_path).unwrap(), None);
+
+        let legacy_entry = bincode::serialize(&LogFileEntry {
+            key: "key".as_bytes().to_vec(),
+            value: None,
+            expires_at: None,
+            transforms: Vec::new(),
+        })
+        .unwrap();
+        File::create(&data_file_path)
+            .unwrap()
+            .write_all(&legacy_entry)
+            .unwrap();
+        assert_eq!(data_file_version(&data_file_path).unwrap(), Some(0));
+
+        write_data_file_header(File::create(&data_file_path).unwrap()).unwrap();
+        assert_eq!(
+            data_file_version(&data_file_path).unwrap(),
+            Some(DATA_FILE_VERSION)
+        );
+    }
 }
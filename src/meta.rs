@@ -0,0 +1,39 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// The name of the file that holds the store's metadata blob.
+pub(crate) const META_FILE_NAME: &str = "rustcask.meta";
+
+/// The suffix of the temporary file that a new metadata blob is written to,
+/// before it replaces the metadata file.
+const META_TEMP_FILE_SUFFIX: &str = ".tmp";
+
+pub(crate) fn meta_file_path(rustcask_dir: &Path) -> PathBuf {
+    rustcask_dir.join(META_FILE_NAME)
+}
+
+/// Reads the metadata blob of the store in `rustcask_dir`.
+/// Returns `None` if no metadata has been set.
+pub(crate) fn read_metadata(rustcask_dir: &Path) -> Result<Option<Vec<u8>>, io::Error> {
+    match fs::read(meta_file_path(rustcask_dir)) {
+        Ok(blob) => Ok(Some(blob)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Replaces the metadata blob of the store in `rustcask_dir`.
+///
+/// The blob is written to a temporary file, which is synced and then renamed over the
+/// metadata file, so that an interrupted write leaves the previous blob in place.
+pub(crate) fn write_metadata(rustcask_dir: &Path, blob: &[u8]) -> Result<(), io::Error> {
+    let temp_file = rustcask_dir.join(format!("{}{}", META_FILE_NAME, META_TEMP_FILE_SUFFIX));
+    let mut file = File::create(&temp_file)?;
+    file.write_all(blob)?;
+    file.sync_all()?;
+    fs::rename(&temp_file, meta_file_path(rustcask_dir))?;
+    File::open(rustcask_dir)?.sync_all()
+}
@@ -0,0 +1,473 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    bufio::BufReaderWithPos,
+    error::{MigrateError, MigrateErrorKind},
+    lock::DirLock,
+    logfile::{
+        data_file_version, write_data_file_header, LogFileEntry, DATA_FILE_HEADER_LEN,
+        DATA_FILE_VERSION,
+    },
+    utils::{data_file_path, hint_file_path, include_all_files, list_generations},
+    GenerationNumber,
+};
+
+/// The suffix of the temporary file a data file is rewritten to before it replaces the original.
+pub(crate) const MIGRATE_TEMP_FILE_SUFFIX: &str = ".migrating";
+
+/// An entry in a data file written before entries recorded when their value expires.
+/// Version 0 and version 1 data files store entries in this format.
+#[derive(Serialize, Deserialize, Debug)]
+struct LegacyLogFileEntry {
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+}
+
+impl From<LegacyLogFileEntry> for LogFileEntry {
+    fn from(entry: LegacyLogFileEntry) -> Self {
+        LogFileEntry {
+            key: entry.key,
+            value: entry.value,
+            expires_at: None,
+            transforms: Vec::new(),
+        }
+    }
+}
+
+/// An entry in a data file written before entries recorded their value transforms.
+/// Version 2 data files store entries in this format.
+#[derive(Serialize, Deserialize, Debug)]
+struct Version2LogFileEntry {
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+    expires_at: Option<u64>,
+}
+
+impl From<Version2LogFileEntry> for LogFileEntry {
+    fn from(entry: Version2LogFileEntry) -> Self {
+        LogFileEntry {
+            key: entry.key,
+            value: entry.value,
+            expires_at: entry.expires_at,
+            transforms: Vec::new(),
+        }
+    }
+}
+
+/// An entry in a data file written before entries had a fixed size header.
+/// Version 3 data files store entries in this format.
+///
+/// This is a copy of how `LogFileEntry` was serialized at the time, so that changes to
+/// `LogFileEntry` don't change how version 3 data files are read.
+#[derive(Serialize, Deserialize, Debug)]
+struct Version3LogFileEntry {
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+    expires_at: Option<u64>,
+    transforms: Vec<u8>,
+}
+
+impl From<Version3LogFileEntry> for LogFileEntry {
+    fn from(entry: Version3LogFileEntry) -> Self {
+        LogFileEntry {
+            key: entry.key,
+            value: entry.value,
+            expires_at: entry.expires_at,
+            transforms: entry.transforms,
+        }
+    }
+}
+
+/// Rewrites every data file in the rustcask directory that was written in an older
+/// format version to the current format version.
+///
+/// Each data file is rewritten entry by entry, in order, so tombstones keep shadowing the
+/// values in older generations. Entries are streamed from the original file to the rewritten
+/// one, so only one entry is held in memory at a time. The rewritten file replaces the
+/// original with a rename, and the generation's hint file is deleted because its offsets no
+/// longer match.
+pub fn migrate_directory(rustcask_dir: &Path) -> Result<(), MigrateError> {
+    let migrate_error = |kind| MigrateError {
+        kind,
+        rustcask_dir: rustcask_dir.to_string_lossy().to_string(),
+    };
+
+    // The data files can't be rewritten while a store has them open.
+    let _dir_lock = DirLock::acquire_exclusive(rustcask_dir)
+        .map_err(|err| migrate_error(MigrateErrorKind::Io(err)))?;
+
+    let mut generations = list_generations(rustcask_dir, include_all_files)
+        .map_err(|err| migrate_error(MigrateErrorKind::Io(err)))?;
+    generations.sort_unstable();
+
+    for generation in generations {
+        let data_file = data_file_path(rustcask_dir, &generation);
+        let version = match data_file_version(&data_file)
+            .map_err(|err| migrate_error(MigrateErrorKind::Io(err)))?
+        {
+            None | Some(DATA_FILE_VERSION) => continue,
+            Some(version @ 0..=3) => version,
+            Some(version) => {
+                return Err(migrate_error(MigrateErrorKind::UnsupportedVersion {
+                    generation,
+                    version,
+                }))
+            }
+        };
+        rewrite_data_file(rustcask_dir, generation, version).map_err(migrate_error)?;
+        info!(
+            "Migrated data file for generation {} to format version {}.",
+            generation, DATA_FILE_VERSION
+        );
+    }
+
+    Ok(())
+}
+
+/// Rewrites a data file of the given format version, which must be older than the current
+/// one, to a temporary file in the current format, and then replaces the original with it.
+/// The temporary file is removed if the data file can't be rewritten.
+fn rewrite_data_file(
+    rustcask_dir: &Path,
+    generation: GenerationNumber,
+    version: u32,
+) -> Result<(), MigrateErrorKind> {
+    let data_file = data_file_path(rustcask_dir, &generation);
+    let mut migrated_file_name = data_file.file_name().unwrap().to_os_string();
+    migrated_file_name.push(MIGRATE_TEMP_FILE_SUFFIX);
+    let migrated_data_file: PathBuf = rustcask_dir.join(migrated_file_name);
+
+    let result = write_migrated_data_file(&data_file, &migrated_data_file, version);
+    if result.is_err() {
+        let _ = fs::remove_file(&migrated_data_file);
+    }
+    result?;
+
+    fs::rename(&migrated_data_file, &data_file).map_err(MigrateErrorKind::Io)?;
+
+    let hint_file = hint_file_path(rustcask_dir, generation);
+    if hint_file.exists() {
+        fs::remove_file(hint_file).map_err(MigrateErrorKind::Io)?;
+    }
+    Ok(())
+}
+
+fn write_migrated_data_file(
+    data_file: &Path,
+    migrated_data_file: &Path,
+    version: u32,
+) -> Result<(), MigrateErrorKind> {
+    let mut writer =
+        BufWriter::new(File::create(migrated_data_file).map_err(MigrateErrorKind::Io)?);
+    write_data_file_header(&mut writer).map_err(MigrateErrorKind::Io)?;
+    let mut write_entry = |entry: LogFileEntry| -> Result<(), io::Error> {
+        writer.write_all(&entry.encode().map_err(io::Error::other)?)
+    };
+
+    match version {
+        0 => read_version_0_entries(data_file, &mut write_entry),
+        1 => read_length_prefixed_entries::<LegacyLogFileEntry>(data_file, &mut write_entry),
+        2 => read_length_prefixed_entries::<Version2LogFileEntry>(data_file, &mut write_entry),
+        3 => read_length_prefixed_entries::<Version3LogFileEntry>(data_file, &mut write_entry),
+        _ => unreachable!("Data files of version {} aren't migrated", version),
+    }?;
+
+    writer.flush().map_err(MigrateErrorKind::Io)?;
+    writer.get_ref().sync_all().map_err(MigrateErrorKind::Io)
+}
+
+/// Reads the entries of a version 0 data file, which has no header and stores
+/// serialized entries back to back, and passes them to `write_entry` one at a time.
+fn read_version_0_entries(
+    data_file: &Path,
+    write_entry: &mut impl FnMut(LogFileEntry) -> Result<(), io::Error>,
+) -> Result<(), MigrateErrorKind> {
+    let file = File::open(data_file).map_err(MigrateErrorKind::Io)?;
+    let len = file.metadata().map_err(MigrateErrorKind::Io)?.len();
+    let mut reader = BufReaderWithPos::new(file).map_err(MigrateErrorKind::Io)?;
+
+    while reader.pos() < len {
+        match bincode::deserialize_from::<_, LegacyLogFileEntry>(&mut reader) {
+            Ok(entry) => write_entry(entry.into()).map_err(MigrateErrorKind::Io)?,
+            Err(err) => match err.as_ref() {
+                bincode::ErrorKind::Io(io_error)
+                    if io_error.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    warn!(
+                        "Data file {} ends with a truncated entry. The truncated entry won't be migrated.",
+                        data_file.display()
+                    );
+                    break;
+                }
+                _ => return Err(MigrateErrorKind::Deserialize(err)),
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the entries of a version 1, 2, or 3 data file, which store length prefixed
+/// entries after the header, and passes them to `write_entry` one at a time. `E` is the
+/// format of the entries.
+fn read_length_prefixed_entries<E>(
+    data_file: &Path,
+    write_entry: &mut impl FnMut(LogFileEntry) -> Result<(), io::Error>,
+) -> Result<(), MigrateErrorKind>
+where
+    E: DeserializeOwned + Into<LogFileEntry>,
+{
+    let file = File::open(data_file).map_err(MigrateErrorKind::Io)?;
+    let len = file.metadata().map_err(MigrateErrorKind::Io)?.len();
+    let mut reader = BufReaderWithPos::new(file).map_err(MigrateErrorKind::Io)?;
+    reader
+        .seek(SeekFrom::Start(DATA_FILE_HEADER_LEN))
+        .map_err(MigrateErrorKind::Io)?;
+    let mut payload = Vec::new();
+
+    while reader.pos() < len {
+        match read_length_prefixed_payload(&mut reader, &mut payload) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!(
+                    "Data file {} ends with a truncated entry. The truncated entry won't be migrated.",
+                    data_file.display()
+                );
+                break;
+            }
+            Err(err) => return Err(MigrateErrorKind::Io(err)),
+        }
+        let entry = bincode::deserialize::<E>(&payload).map_err(MigrateErrorKind::Deserialize)?;
+        write_entry(entry.into()).map_err(MigrateErrorKind::Io)?;
+    }
+
+    Ok(())
+}
+
+/// Reads a length prefixed entry into `payload`, replacing its contents. The prefix is the
+/// length of the serialized entry as a little endian `u32`.
+fn read_length_prefixed_payload<R: Read>(mut reader: R, payload: &mut Vec<u8>) -> io::Result<()> {
+    let mut prefix = [0; 4];
+    reader.read_exact(&mut prefix)?;
+    payload.clear();
+    payload.resize(u32::from_le_bytes(prefix) as usize, 0);
+    reader.read_exact(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
+
+    use tempfile::TempDir;
+
+    use crate::{
+        error::{MigrateErrorKind, OpenErrorKind},
+        logfile::{data_file_version, DATA_FILE_VERSION},
+        utils::{data_file_path, tests::file_names},
+        Rustcask,
+    };
+
+    use super::{LegacyLogFileEntry, Version2LogFileEntry, Version3LogFileEntry};
+
+    fn entry(key: &str, value: Option<&str>) -> LegacyLogFileEntry {
+        LegacyLogFileEntry {
+            key: key.as_bytes().to_vec(),
+            value: value.map(|value| value.as_bytes().to_vec()),
+        }
+    }
+
+    /// Writes a data file the way rustcask did before data files had a header.
+    fn write_version_0_data_file(
+        temp_dir: &TempDir,
+        generation: u64,
+        entries: &[LegacyLogFileEntry],
+    ) {
+        let mut data_file = File::create(data_file_path(temp_dir.path(), &generation)).unwrap();
+        for entry in entries {
+            data_file
+                .write_all(&bincode::serialize(entry).unwrap())
+                .unwrap();
+        }
+        data_file.flush().unwrap();
+    }
+
+    #[test]
+    fn test_migrate_version_0_store() {
+        let temp_dir = TempDir::new().unwrap();
+        write_version_0_data_file(
+            &temp_dir,
+            0,
+            &[
+                entry("leader", Some("instance-a")),
+                entry("removed", Some("value")),
+            ],
+        );
+        write_version_0_data_file(
+            &temp_dir,
+            1,
+            &[entry("leader", Some("instance-b")), entry("removed", None)],
+        );
+
+        let err = Rustcask::builder().open(temp_dir.path()).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            OpenErrorKind::UnsupportedVersion {
+                generation: 0,
+                version: 0
+            }
+        ));
+
+        Rustcask::migrate(temp_dir.path()).unwrap();
+
+        for generation in [0, 1] {
+            assert_eq!(
+                data_file_version(&data_file_path(temp_dir.path(), &generation)).unwrap(),
+                Some(DATA_FILE_VERSION)
+            );
+        }
+        let mut file_names = file_names(temp_dir.path());
+        file_names.sort();
+        assert_eq!(file_names, vec!["0.rustcask.data", "1.rustcask.data"]);
+
+        let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+        assert_eq!(
+            store.get_str("leader").unwrap(),
+            Some("instance-b".to_string())
+        );
+        assert_eq!(store.get_str("removed").unwrap(), None);
+
+        // Migrating a store that's already in the current format is a no-op.
+        drop(store);
+        Rustcask::migrate(temp_dir.path()).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_version_1_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut data_file = File::create(data_file_path(temp_dir.path(), &0)).unwrap();
+        data_file.write_all(b"RCSK").unwrap();
+        data_file.write_all(&1u32.to_le_bytes()).unwrap();
+        for entry in [entry("key", Some("value")), entry("removed", None)] {
+            let payload = bincode::serialize(&entry).unwrap();
+            data_file
+                .write_all(&(payload.len() as u32).to_le_bytes())
+                .unwrap();
+            data_file.write_all(&payload).unwrap();
+        }
+        drop(data_file);
+
+        let err = Rustcask::builder().open(temp_dir.path()).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            OpenErrorKind::UnsupportedVersion {
+                generation: 0,
+                version: 1
+            }
+        ));
+
+        Rustcask::migrate(temp_dir.path()).unwrap();
+        assert_eq!(
+            data_file_version(&data_file_path(temp_dir.path(), &0)).unwrap(),
+            Some(DATA_FILE_VERSION)
+        );
+
+        let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+        assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+        assert_eq!(store.get_str("removed").unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate_version_2_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut data_file = File::create(data_file_path(temp_dir.path(), &0)).unwrap();
+        data_file.write_all(b"RCSK").unwrap();
+        data_file.write_all(&2u32.to_le_bytes()).unwrap();
+        let entry = Version2LogFileEntry {
+            key: b"key".to_vec(),
+            value: Some(b"value".to_vec()),
+            expires_at: Some(u64::MAX),
+        };
+        let payload = bincode::serialize(&entry).unwrap();
+        data_file
+            .write_all(&(payload.len() as u32).to_le_bytes())
+            .unwrap();
+        data_file.write_all(&payload).unwrap();
+        drop(data_file);
+
+        Rustcask::migrate(temp_dir.path()).unwrap();
+        assert_eq!(
+            data_file_version(&data_file_path(temp_dir.path(), &0)).unwrap(),
+            Some(DATA_FILE_VERSION)
+        );
+
+        let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+        assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+    }
+
+    #[test]
+    fn test_migrate_version_3_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut data_file = File::create(data_file_path(temp_dir.path(), &0)).unwrap();
+        data_file.write_all(b"RCSK").unwrap();
+        data_file.write_all(&3u32.to_le_bytes()).unwrap();
+        let entries = [
+            Version3LogFileEntry {
+                key: b"key".to_vec(),
+                value: Some(b"value".to_vec()),
+                expires_at: Some(u64::MAX),
+                transforms: Vec::new(),
+            },
+            Version3LogFileEntry {
+                key: b"removed".to_vec(),
+                value: None,
+                expires_at: None,
+                transforms: Vec::new(),
+            },
+        ];
+        for entry in entries {
+            let payload = bincode::serialize(&entry).unwrap();
+            data_file
+                .write_all(&(payload.len() as u32).to_le_bytes())
+                .unwrap();
+            data_file.write_all(&payload).unwrap();
+        }
+        drop(data_file);
+
+        Rustcask::migrate(temp_dir.path()).unwrap();
+        assert_eq!(
+            data_file_version(&data_file_path(temp_dir.path(), &0)).unwrap(),
+            Some(DATA_FILE_VERSION)
+        );
+
+        let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+        assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+        assert_eq!(store.get_str("removed").unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate_keeps_data_file_that_fails_to_migrate() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut contents = b"RCSK".to_vec();
+        contents.extend(1u32.to_le_bytes());
+        let payload = bincode::serialize(&entry("key", Some("value"))).unwrap();
+        contents.extend((payload.len() as u32).to_le_bytes());
+        contents.extend(&payload);
+        // An entry whose key claims to be longer than the entry.
+        contents.extend(8u32.to_le_bytes());
+        contents.extend(u64::MAX.to_le_bytes());
+        let data_file = data_file_path(temp_dir.path(), &0);
+        std::fs::write(&data_file, &contents).unwrap();
+
+        let err = Rustcask::migrate(temp_dir.path()).unwrap_err();
+        assert!(matches!(err.kind, MigrateErrorKind::Deserialize(_)));
+        assert_eq!(std::fs::read(&data_file).unwrap(), contents);
+        assert_eq!(file_names(temp_dir.path()), vec!["0.rustcask.data"]);
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    utils::{
+        classify_file, data_file_path, hint_file_path, parse_generation_number, retired_file_path,
+        FileKind,
+    },
+    GenerationNumber,
+};
+
+const PINS_POISON_ERR: &str =
+    "Another thread crashed while holding the generation pins lock. Panicking.";
+
+/// Keeps the data files of pinned generations on disk while they're being read outside of
+/// the keydir lock.
+///
+/// A merge deletes the generations it replaced. If one of them is pinned, its files are
+/// deleted once the last pin on it is released instead.
+///
+/// When a merge delete delay is configured, the replaced generations are retired instead, and
+/// a background sweeper deletes them once the delay has elapsed.
+/// See [`crate::RustcaskBuilder::set_merge_delete_delay`].
+#[derive(Debug, Default)]
+pub(crate) struct GenerationPins {
+    state: Mutex<PinState>,
+
+    // The number of generations deleted so far. Readers close their readers of deleted
+    // generations when it changes.
+    deletions: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct PinState {
+    // The number of pins on each pinned generation.
+    counts: HashMap<GenerationNumber, usize>,
+
+    // Pinned generations that were replaced by a merge, and should be deleted once unpinned.
+    pending_deletes: HashSet<GenerationNumber>,
+
+    // Retired generations, with the time after which they can be deleted.
+    retired: HashMap<GenerationNumber, (Instant, Arc<PathBuf>)>,
+
+    // Whether a sweeper thread is waiting to delete the retired generations.
+    sweeper_running: bool,
+}
+
+/// Releases its pins when dropped. See [`GenerationPins::pin`].
+#[derive(Debug)]
+pub(crate) struct PinGuard {
+    pins: Arc<GenerationPins>,
+    generations: Vec<GenerationNumber>,
+    rustcask_dir: Arc<PathBuf>,
+}
+
+impl GenerationPins {
+    /// Pins the given generations until the returned guard is dropped.
+    ///
+    /// The caller must hold the keydir lock while pinning the generations it found in the
+    /// keydir. Merges hold the keydir write lock while they delete generations, so the pinned
+    /// generations can't be deleted between the keydir lookup and the pin.
+    pub fn pin(
+        self: &Arc<Self>,
+        generations: HashSet<GenerationNumber>,
+        rustcask_dir: Arc<PathBuf>,
+    ) -> PinGuard {
+        let mut state = self.state.lock().expect(PINS_POISON_ERR);
+        for generation in &generations {
+            *state.counts.entry(*generation).or_insert(0) += 1;
+        }
+        PinGuard {
+            pins: self.clone(),
+            generations: generations.into_iter().collect(),
+            rustcask_dir,
+        }
+    }
+
+    /// Deletes the files of a generation, unless it's pinned. A pinned generation is deleted
+    /// when its last pin is released.
+    ///
+    /// A pinned generation is marked as retired first, so that it's ignored by
+    /// `list_generations`, and deleted by `open` if the store is closed while it's pinned.
+    pub fn delete_generation(
+        &self,
+        rustcask_dir: &Path,
+        generation: GenerationNumber,
+    ) -> Result<(), io::Error> {
+        let mut state = self.state.lock().expect(PINS_POISON_ERR);
+        if state.counts.contains_key(&generation) {
+            debug!(
+                "Generation {} is pinned. Deferring its deletion until it's unpinned.",
+                generation
+            );
+            File::create(retired_file_path(rustcask_dir, generation))?.sync_all()?;
+            state.pending_deletes.insert(generation);
+            return Ok(());
+        }
+        drop(state);
+        self.delete_generation_files(rustcask_dir, generation)
+    }
+
+    /// Retires a generation, so that it's deleted once `delay` has elapsed.
+    ///
+    /// A marker file is written next to the generation's data file, so that the generation is
+    /// ignored by `list_generations`, and deleted by `open` if the store is closed before the
+    /// delay elapses. A sweeper thread is started if one isn't already running.
+    pub fn retire_generation(
+        self: &Arc<Self>,
+        rustcask_dir: Arc<PathBuf>,
+        generation: GenerationNumber,
+        delay: Duration,
+    ) -> Result<(), io::Error> {
+        File::create(retired_file_path(&rustcask_dir, generation))?.sync_all()?;
+
+        let mut state = self.state.lock().expect(PINS_POISON_ERR);
+        state
+            .retired
+            .insert(generation, (Instant::now() + delay, rustcask_dir));
+        if !state.sweeper_running {
+            state.sweeper_running = true;
+            let pins = self.clone();
+            thread::spawn(move || pins.sweep());
+        }
+        Ok(())
+    }
+
+    /// Deletes retired generations as their delays elapse, until none are left.
+    ///
+    /// Every generation is retired with the same delay, so a generation retired while the
+    /// sweeper is sleeping is never due before the one it's waiting for.
+    fn sweep(&self) {
+        loop {
+            let mut state = self.state.lock().expect(PINS_POISON_ERR);
+            let now = Instant::now();
+            let due: Vec<GenerationNumber> = state
+                .retired
+                .iter()
+                .filter(|(_, (delete_at, _))| *delete_at <= now)
+                .map(|(generation, _)| *generation)
+                .collect();
+
+            let mut deletes = Vec::new();
+            for generation in due {
+                let (_, rustcask_dir) = state
+                    .retired
+                    .remove(&generation)
+                    .expect("A due generation is retired");
+                if state.counts.contains_key(&generation) {
+                    state.pending_deletes.insert(generation);
+                } else {
+                    deletes.push((generation, rustcask_dir));
+                }
+            }
+
+            let next_delete_at = state
+                .retired
+                .values()
+                .map(|(delete_at, _)| *delete_at)
+                .min();
+            state.sweeper_running = next_delete_at.is_some();
+            drop(state);
+
+            for (generation, rustcask_dir) in deletes {
+                if let Err(err) = self.delete_generation_files(&rustcask_dir, generation) {
+                    warn!(
+                        "Unable to delete generation {} after its merge delete delay: {}",
+                        generation, err
+                    );
+                }
+            }
+
+            match next_delete_at {
+                Some(delete_at) => thread::sleep(delete_at.saturating_duration_since(now)),
+                None => return,
+            }
+        }
+    }
+
+    /// Whether a merge replaced the generation while it was pinned, so that it's deleted once
+    /// it's unpinned.
+    pub fn is_pending_delete(&self, generation: GenerationNumber) -> bool {
+        self.state
+            .lock()
+            .expect(PINS_POISON_ERR)
+            .pending_deletes
+            .contains(&generation)
+    }
+
+    /// Whether any generation is pinned.
+    pub fn has_pins(&self) -> bool {
+        !self.state.lock().expect(PINS_POISON_ERR).counts.is_empty()
+    }
+
+    pub fn is_pinned(&self, generation: GenerationNumber) -> bool {
+        self.state
+            .lock()
+            .expect(PINS_POISON_ERR)
+            .counts
+            .contains_key(&generation)
+    }
+
+    /// The number of generations deleted so far.
+    pub fn deletions(&self) -> u64 {
+        self.deletions.load(Ordering::Acquire)
+    }
+
+    /// Counts a deletion of data files that the store didn't make itself, so that every handle
+    /// closes its readers of data files that no longer exist.
+    pub fn record_deletion(&self) {
+        self.deletions.fetch_add(1, Ordering::Release);
+    }
+
+    fn delete_generation_files(
+        &self,
+        rustcask_dir: &Path,
+        generation: GenerationNumber,
+    ) -> Result<(), io::Error> {
+        let data_file = data_file_path(rustcask_dir, &generation);
+        debug!("Deleting {}.", data_file.to_string_lossy());
+        fs::remove_file(data_file)?;
+        self.deletions.fetch_add(1, Ordering::Release);
+
+        remove_file_if_exists(&hint_file_path(rustcask_dir, generation))?;
+        // The marker is removed last, so that a retired generation isn't read again
+        // if deleting its files is interrupted.
+        remove_file_if_exists(&retired_file_path(rustcask_dir, generation))
+    }
+}
+
+impl Drop for PinGuard {
+    fn drop(&mut self) {
+        let mut state = self.pins.state.lock().expect(PINS_POISON_ERR);
+        for generation in &self.generations {
+            let count = state
+                .counts
+                .get_mut(generation)
+                .expect("A pinned generation has a pin count");
+            *count -= 1;
+            if *count > 0 {
+                continue;
+            }
+            state.counts.remove(generation);
+            if state.pending_deletes.remove(generation) {
+                if let Err(err) = self
+                    .pins
+                    .delete_generation_files(&self.rustcask_dir, *generation)
+                {
+                    warn!(
+                        "Unable to delete generation {} after it was unpinned: {}",
+                        generation, err
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn remove_file_if_exists(path: &Path) -> Result<(), io::Error> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Deletes the generations that were retired by a merge, but not deleted before the store
+/// was closed.
+pub(crate) fn delete_retired_generations(rustcask_dir: &Path) -> Result<(), io::Error> {
+    for entry in fs::read_dir(rustcask_dir)? {
+        let path = entry?.path();
+        if classify_file(&path) != FileKind::Retired {
+            continue;
+        }
+        let generation = parse_generation_number(path);
+        debug!("Deleting retired generation {}.", generation);
+        remove_file_if_exists(&data_file_path(rustcask_dir, &generation))?;
+        remove_file_if_exists(&hint_file_path(rustcask_dir, generation))?;
+        remove_file_if_exists(&retired_file_path(rustcask_dir, generation))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, fs::File, sync::Arc};
+
+    use tempfile::TempDir;
+
+    use crate::utils::{data_file_path, retired_file_path};
+
+    use super::GenerationPins;
+
+    #[test]
+    fn test_pinned_generation_deleted_when_unpinned() {
+        let temp_dir = TempDir::new().unwrap();
+        let rustcask_dir = Arc::new(temp_dir.path().to_path_buf());
+        for generation in 0..2 {
+            File::create(data_file_path(&rustcask_dir, &generation)).unwrap();
+        }
+
+        let pins = Arc::new(GenerationPins::default());
+        let first = pins.pin(HashSet::from([0]), rustcask_dir.clone());
+        let second = pins.pin(HashSet::from([0]), rustcask_dir.clone());
+
+        pins.delete_generation(&rustcask_dir, 0).unwrap();
+        pins.delete_generation(&rustcask_dir, 1).unwrap();
+        assert!(data_file_path(&rustcask_dir, &0).exists());
+        assert!(!data_file_path(&rustcask_dir, &1).exists());
+
+        // The pinned generation is marked as retired until it's deleted.
+        assert!(retired_file_path(&rustcask_dir, 0).exists());
+        assert!(!retired_file_path(&rustcask_dir, 1).exists());
+
+        drop(first);
+        assert!(data_file_path(&rustcask_dir, &0).exists());
+        drop(second);
+        assert!(!data_file_path(&rustcask_dir, &0).exists());
+        assert!(!retired_file_path(&rustcask_dir, 0).exists());
+        assert!(!pins.is_pinned(0));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Limits the rate at which bytes are read or written, using a token bucket.
+///
+/// The bucket holds up to one second's worth of bytes, so short bursts above the rate
+/// are allowed after a quiet period.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    bucket: Mutex<TokenBucket>,
+    // When true, acquire waits until enough bytes are available.
+    // Otherwise, it fails immediately.
+    blocking: bool,
+}
+
+/// Returned by [`RateLimiter::acquire`] when the limiter isn't blocking and the rate limit
+/// has been exceeded.
+#[derive(Debug)]
+pub(crate) struct RateLimited;
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64, blocking: bool) -> Self {
+        Self {
+            bucket: Mutex::new(TokenBucket::new(bytes_per_sec)),
+            blocking,
+        }
+    }
+
+    /// Takes `bytes` from the bucket, waiting for the bucket to refill if needed.
+    pub fn acquire(&self, bytes: u64) -> Result<(), RateLimited> {
+        loop {
+            // The lock isn't held while sleeping, so that other threads can check the bucket.
+            let wait = match self
+                .bucket
+                .lock()
+                .expect("Another thread crashed while holding the rate limiter lock. Panicking.")
+                .try_acquire(bytes, Instant::now())
+            {
+                Ok(()) => return Ok(()),
+                Err(wait) => wait,
+            };
+            if !self.blocking {
+                return Err(RateLimited);
+            }
+            thread::sleep(wait);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    bytes_per_sec: f64,
+    // The number of bytes that can be taken without waiting.
+    // This goes negative when more bytes than the bucket can hold are taken at once.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            tokens: bytes_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes `bytes` from the bucket, or returns how long to wait before trying again.
+    ///
+    /// Requests larger than the bucket are allowed once the bucket is full, and leave the bucket
+    /// in debt. This keeps large values from being blocked forever.
+    fn try_acquire(&mut self, bytes: u64, now: Instant) -> Result<(), Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+        self.last_refill = now;
+
+        let needed = (bytes as f64).min(self.bytes_per_sec);
+        if self.tokens >= needed {
+            self.tokens -= bytes as f64;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (needed - self.tokens) / self.bytes_per_sec,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::TokenBucket;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(100);
+        let start = bucket.last_refill;
+
+        assert!(bucket.try_acquire(60, start).is_ok());
+        let wait = bucket.try_acquire(60, start).unwrap_err();
+        assert_eq!(wait.as_millis(), 200);
+
+        // After 200ms, 20 more bytes are available.
+        let later = start + Duration::from_millis(200);
+        assert!(bucket.try_acquire(60, later).is_ok());
+        assert!(bucket.try_acquire(1, later).is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_large_request() {
+        let mut bucket = TokenBucket::new(100);
+        let start = bucket.last_refill;
+
+        // A request larger than the bucket is allowed when the bucket is full,
+        // and has to be paid back before the next request.
+        assert!(bucket.try_acquire(250, start).is_ok());
+        let wait = bucket.try_acquire(1, start).unwrap_err();
+        assert!(wait > Duration::from_millis(1500) && wait < Duration::from_millis(1520));
+    }
+}
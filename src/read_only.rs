@@ -0,0 +1,54 @@
+use std::sync::{Arc, RwLock};
+
+use log::trace;
+
+use crate::{
+    error::GetError, keydir::KeyDir, readers::Readers, utils::KEYDIR_POISON_ERR, Counters,
+};
+
+/// A handle that can only read from a Rustcask storage engine.
+///
+/// Created with [`Rustcask::into_read_only`](crate::Rustcask::into_read_only), or opened
+/// directly with [`RustcaskBuilder::open_read_only`](crate::RustcaskBuilder::open_read_only).
+/// A read-only handle can be cloned and shared across threads; each clone gets its own set
+/// of data file readers.
+///
+/// A read-only handle has no way to write to the store:
+/// ```compile_fail
+/// # use rustcask::Rustcask;
+/// # use tempfile::TempDir;
+/// # let temp_dir = TempDir::new().unwrap();
+/// let mut store = Rustcask::builder().open(temp_dir.path()).unwrap().into_read_only();
+/// store.set("key".as_bytes().to_vec(), "value".as_bytes().to_vec());
+/// ```
+#[derive(Clone, Debug)]
+pub struct ReadOnlyRustcask {
+    readers: Readers,
+
+    keydir: Arc<RwLock<KeyDir>>,
+}
+
+impl ReadOnlyRustcask {
+    pub(crate) fn new(readers: Readers, keydir: Arc<RwLock<KeyDir>>) -> Self {
+        Self { readers, keydir }
+    }
+
+    /// Returns the value corresponding to the key.
+    ///
+    /// See [`Rustcask::get`](crate::Rustcask::get).
+    pub fn get(&mut self, key: &Vec<u8>) -> Result<Option<Vec<u8>>, GetError> {
+        trace!(
+            "Read-only get called with key (as UTF 8) {}",
+            String::from_utf8_lossy(key)
+        );
+        let keydir = self.keydir.read().expect(KEYDIR_POISON_ERR);
+        self.readers.get(&keydir, key)
+    }
+
+    /// Returns cumulative operation counts for the store.
+    ///
+    /// See [`Rustcask::counters`](crate::Rustcask::counters).
+    pub fn counters(&self) -> Counters {
+        self.readers.counters.snapshot()
+    }
+}
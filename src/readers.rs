@@ -1,10 +1,24 @@
 use std::collections::hash_map::Entry;
-use std::io::{self};
-use std::sync::Arc;
+use std::io::{self, Seek, SeekFrom};
+use std::mem;
+use std::sync::{Arc, Mutex};
 use std::{collections::HashMap, fs::File, path::PathBuf};
 
-use crate::utils::list_generations;
-use crate::{bufio::BufReaderWithPos, utils::data_file_path, GenerationNumber};
+use crate::cache::ValueCache;
+use crate::clock::{now_millis, Clock};
+use crate::corruption::{CorruptionHandler, CorruptionKind, OnCorruption};
+use crate::counters::SharedCounters;
+use crate::error::{GetError, GetErrorKind};
+use crate::keydir::KeyDir;
+use crate::logfile::{read_entry_payload, LogFileEntryRef, LogIndex};
+use crate::pins::GenerationPins;
+use crate::rate_limit::{RateLimited, RateLimiter};
+use crate::transform::ValueTransforms;
+use crate::utils::{list_generations, FileFilter};
+use crate::{bufio::BufReaderWithPos, utils::data_file_path, GenerationNumber, RustcaskBuilder};
+
+const CACHE_POISON_ERR: &str =
+    "Another thread crashed while holding the value cache lock. Panicking.";
 
 // TODO [RyanStan 07-02-24] Extend this class (or restructure and create new classes) to support hint files.
 //
@@ -17,52 +31,168 @@ pub struct Readers {
     // A buffered reader provides benefits when performing sequential reads of the
     // data and hint files during startup
     pub(crate) data_file_readers: HashMap<GenerationNumber, BufReaderWithPos<File>>,
+
+    // The last known length of each data file with a reader. Entries are checked against it
+    // before they're read, and it's refreshed when an entry ends past it.
+    data_file_lens: HashMap<GenerationNumber, u64>,
     rustcask_dir: Arc<PathBuf>,
+
+    // Shared by every clone, including the one held by the writer.
+    pub(crate) counters: Arc<SharedCounters>,
+
+    // Shared by every clone. None when the value cache is disabled.
+    cache: Option<Arc<Mutex<ValueCache>>>,
+
+    track_access_counts: bool,
+
+    // Data files rejected by the filter are ignored.
+    pub(crate) file_filter: FileFilter,
+
+    // Shared by every clone, including the one held by the writer. Decides when values expire.
+    pub(crate) clock: Arc<dyn Clock>,
+
+    // Shared by every clone. Limits the rate at which get reads from the data files.
+    read_rate_limiter: Option<Arc<RateLimiter>>,
+
+    // Shared by every clone, including the one held by the writer. Keeps the data files
+    // of generations that are read outside of the keydir lock from being deleted by merges.
+    pub(crate) pins: Arc<GenerationPins>,
+
+    // Reversed on the values read from the data files, and applied by the writer.
+    pub(crate) value_transforms: ValueTransforms,
+
+    // Called when get reads corrupt data. None when no handler is configured.
+    corruption_handler: Option<CorruptionHandler>,
+
+    // What reads do when a value is corrupt.
+    on_read_corruption: OnCorruption,
+
+    // Reused to hold serialized entries read by get. Not shared between clones.
+    scratch: Vec<u8>,
+
+    // The number of deleted generations the last time the readers of deleted generations
+    // were closed. See `GenerationPins::deletions`.
+    seen_deletions: u64,
+}
+
+/// Where the value of a key can be read from. See [`Readers::locate_value`].
+#[derive(Debug)]
+pub enum ValueLocation<'a> {
+    // The key isn't in the keydir, or its value has expired.
+    Missing,
+    // The value is stored in the keydir.
+    Inline(&'a [u8]),
+    // The value has to be read from a data file.
+    DataFile {
+        data_file_gen: GenerationNumber,
+        index: LogIndex,
+        access_count: u64,
+    },
 }
 
+/// Clones share the counters, value cache, clock, rate limiter, and generation pins with the
+/// original, but open their own data file readers.
 impl Clone for Readers {
     fn clone(&self) -> Self {
         // TODO [RyanStan 07-01-24] Iterate over readers
         //   and create a BufReaderWithPos for each generation.
         Self {
             data_file_readers: HashMap::new(),
+            data_file_lens: HashMap::new(),
             rustcask_dir: self.rustcask_dir.clone(),
+            counters: self.counters.clone(),
+            cache: self.cache.clone(),
+            track_access_counts: self.track_access_counts,
+            file_filter: self.file_filter,
+            clock: self.clock.clone(),
+            read_rate_limiter: self.read_rate_limiter.clone(),
+            pins: self.pins.clone(),
+            value_transforms: self.value_transforms.clone(),
+            corruption_handler: self.corruption_handler.clone(),
+            on_read_corruption: self.on_read_corruption,
+            scratch: Vec::new(),
+            seen_deletions: 0,
         }
     }
 }
 
 impl Readers {
-    pub fn new(rustcask_dir: Arc<PathBuf>) -> Result<Self, io::Error> {
-        let readers = Readers::create_data_file_readers(rustcask_dir.clone())?;
+    pub fn new(rustcask_dir: Arc<PathBuf>, options: &RustcaskBuilder) -> Result<Self, io::Error> {
+        let readers = Readers::create_data_file_readers(
+            rustcask_dir.clone(),
+            options.file_filter,
+            options.skip_unreadable_generations,
+        )?;
+        let cache = (options.value_cache_capacity > 0).then(|| {
+            Arc::new(Mutex::new(ValueCache::new(
+                options.value_cache_capacity,
+                options.cache_eviction,
+            )))
+        });
         Ok(Self {
             data_file_readers: readers,
+            data_file_lens: HashMap::new(),
             rustcask_dir,
+            counters: Arc::new(SharedCounters::default()),
+            cache,
+            track_access_counts: options.tracks_access_counts(),
+            file_filter: options.file_filter,
+            clock: options.clock.clone(),
+            read_rate_limiter: options.read_rate_limit.map(|bytes_per_sec| {
+                Arc::new(RateLimiter::new(bytes_per_sec, options.rate_limit_blocking))
+            }),
+            pins: Arc::new(GenerationPins::default()),
+            value_transforms: options.value_transforms.clone(),
+            corruption_handler: options.corruption_handler.clone(),
+            on_read_corruption: options.on_read_corruption,
+            scratch: Vec::new(),
+            seen_deletions: 0,
         })
     }
 
+    /// Opens a reader for every data file in the rustcask directory.
+    ///
+    /// When `skip_unreadable` is set, data files that can't be opened are skipped.
+    /// The keydir decides what to do about them.
     fn create_data_file_readers(
         rustcask_dir: Arc<PathBuf>,
+        file_filter: FileFilter,
+        skip_unreadable: bool,
     ) -> Result<HashMap<GenerationNumber, BufReaderWithPos<File>>, io::Error> {
         let mut readers = HashMap::new();
-        let generations = list_generations(&rustcask_dir)?;
+        let generations = list_generations(&rustcask_dir, file_filter)?;
         for generation in generations {
-            let reader = BufReaderWithPos::new(
-                File::open(data_file_path(&rustcask_dir, &generation)).expect(&format!(
-                    "Unable to open data file for generation {}.",
-                    generation
-                )),
-            )?;
-            readers.insert(generation, reader);
+            let reader = File::open(data_file_path(&rustcask_dir, &generation))
+                .and_then(BufReaderWithPos::new);
+            match reader {
+                Ok(reader) => {
+                    readers.insert(generation, reader);
+                }
+                Err(_) if skip_unreadable => continue,
+                Err(err) => {
+                    return Err(io::Error::new(
+                        err.kind(),
+                        format!(
+                            "unable to open data file for generation {}: {}",
+                            generation, err
+                        ),
+                    ))
+                }
+            }
         }
         Ok(readers)
     }
 
     pub fn get_data_file_reader(&mut self, gen: GenerationNumber) -> &mut BufReaderWithPos<File> {
+        if !self.data_file_readers.contains_key(&gen) {
+            self.close_deleted();
+        }
         match self.data_file_readers.entry(gen) {
             Entry::Vacant(entry) => {
                 let reader = BufReaderWithPos::new(
-                    File::open(data_file_path(&self.rustcask_dir, &gen))
-                        .expect(&format!("Unable to open data file for generation {}", gen)),
+                    File::open(data_file_path(&self.rustcask_dir, &gen)).unwrap_or_else(|_| {
+                        panic!("Unable to open data file for generation {}", gen)
+                    }),
                 )
                 .unwrap();
                 entry.insert(reader)
@@ -70,4 +200,356 @@ impl Readers {
             Entry::Occupied(entry) => entry.into_mut(),
         }
     }
+
+    fn report_corruption(&self, gen: GenerationNumber, index: &LogIndex, kind: CorruptionKind) {
+        if let Some(handler) = &self.corruption_handler {
+            handler.report(gen, index.offset, kind);
+        }
+    }
+
+    /// Reports a corrupt value, and then handles it as the store is configured to.
+    /// Returns `Ok(false)` if the key should read as missing.
+    fn handle_read_corruption(
+        &self,
+        gen: GenerationNumber,
+        index: &LogIndex,
+        kind: CorruptionKind,
+        err: GetError,
+    ) -> Result<bool, GetError> {
+        self.report_corruption(gen, index, kind);
+        match self.on_read_corruption {
+            OnCorruption::Error => Err(err),
+            OnCorruption::SkipReturnNone => Ok(false),
+            OnCorruption::Panic => panic!(
+                "Corrupt entry at offset {} in data file {}: {}",
+                index.offset, gen, err
+            ),
+        }
+    }
+
+    /// Closes the reader of a data file.
+    pub fn close(&mut self, gen: GenerationNumber) {
+        self.data_file_readers.remove(&gen);
+        self.data_file_lens.remove(&gen);
+    }
+
+    /// Returns the length of the data file if the entry at `index` ends past it, or `None`
+    /// if the entry is within the file.
+    ///
+    /// The length of the data file is cached, and only looked up again when an entry ends
+    /// past the cached length, because the active data file grows as it's written.
+    fn index_out_of_range(
+        &mut self,
+        gen: GenerationNumber,
+        index: &LogIndex,
+    ) -> io::Result<Option<u64>> {
+        let end = index.offset.saturating_add(index.len);
+        if let Some(file_len) = self.data_file_lens.get(&gen) {
+            if end <= *file_len {
+                return Ok(None);
+            }
+        }
+        let file_len = self.get_data_file_reader(gen).get_ref().metadata()?.len();
+        self.data_file_lens.insert(gen, file_len);
+        Ok((end > file_len).then_some(file_len))
+    }
+
+    /// Closes the readers of data files that no longer exist on disk.
+    ///
+    /// The value cache is also cleared, so that it doesn't hold on to the values of keys
+    /// that were stored in the missing data files.
+    pub fn close_missing(&mut self) {
+        self.close_readers_of_missing_files();
+        if let Some(cache) = &self.cache {
+            cache.lock().expect(CACHE_POISON_ERR).clear();
+        }
+    }
+
+    /// Closes the readers of generations that were deleted since the last time this was called,
+    /// so that the handles of data files replaced by merges don't pile up. Every clone keeps its
+    /// own readers, so each one closes them before it opens another data file.
+    fn close_deleted(&mut self) {
+        let deletions = self.pins.deletions();
+        if deletions != self.seen_deletions {
+            self.seen_deletions = deletions;
+            self.close_readers_of_missing_files();
+        }
+    }
+
+    fn close_readers_of_missing_files(&mut self) {
+        let rustcask_dir = self.rustcask_dir.clone();
+        self.data_file_readers
+            .retain(|generation, _| data_file_path(&rustcask_dir, generation).is_file());
+        let data_file_readers = &self.data_file_readers;
+        self.data_file_lens
+            .retain(|generation, _| data_file_readers.contains_key(generation));
+    }
+
+    /// Removes the cached value of a key. Only needed when the value is overwritten in place,
+    /// because the cache otherwise notices when the keydir points to a new value.
+    pub fn forget_cached_value(&self, key: &[u8]) {
+        if let Some(cache) = &self.cache {
+            cache.lock().expect(CACHE_POISON_ERR).remove(key);
+        }
+    }
+
+    /// Returns the value of a key, reading it from the data file referenced by the keydir.
+    ///
+    /// # Errors
+    ///
+    /// This function may return a `GetError` with the following variants:
+    ///
+    /// * `GetErrorKind::Io(err)` - An I/O error occurred while reading the data file.
+    /// * `GetErrorKind::Deserialize(err)` - An error occurred while deserializing the data from the data file, or its checksums didn't match.
+    /// * `GetErrorKind::RateLimited` - The read rate limit was exceeded and rate limiting is non-blocking.
+    /// * `GetErrorKind::IndexOutOfRange { .. }` - The keydir entry for the key points past the end of its data file.
+    /// * `GetErrorKind::KeyMismatch { .. }` - The keydir entry for the key points to an entry for a different key.
+    ///
+    /// Corrupt values are only returned as errors with `OnCorruption::Error`.
+    /// See [`Readers::read_value`].
+    pub fn get(&mut self, keydir: &KeyDir, key: &Vec<u8>) -> Result<Option<Vec<u8>>, GetError> {
+        let mut value = Vec::new();
+        Ok(self.get_into(keydir, key, &mut value)?.then_some(value))
+    }
+
+    /// Reads the value of a key into `buf`, replacing its contents.
+    /// Returns whether the key was found. `buf` is left untouched if it wasn't.
+    /// Expired values aren't found.
+    ///
+    /// The entry is read into a scratch buffer owned by this `Readers`, so that
+    /// repeated reads don't allocate once the buffers are large enough.
+    ///
+    /// # Errors
+    ///
+    /// See [`Readers::get`].
+    pub fn get_into(
+        &mut self,
+        keydir: &KeyDir,
+        key: &Vec<u8>,
+        buf: &mut Vec<u8>,
+    ) -> Result<bool, GetError> {
+        self.counters.record_get();
+        match self.locate_value(keydir, key) {
+            ValueLocation::Missing => Ok(false),
+            ValueLocation::Inline(inline_value) => {
+                buf.clear();
+                buf.extend_from_slice(inline_value);
+                Ok(true)
+            }
+            ValueLocation::DataFile {
+                data_file_gen,
+                index,
+                access_count,
+            } => self.read_value(key, data_file_gen, &index, access_count, buf),
+        }
+    }
+
+    /// Looks up where the value of a key can be read from, and records the access.
+    /// Expired values are missing.
+    pub fn locate_value<'a>(&self, keydir: &'a KeyDir, key: &Vec<u8>) -> ValueLocation<'a> {
+        let keydir_entry = match keydir.get(key) {
+            Some(keydir_entry) => keydir_entry,
+            None => return ValueLocation::Missing,
+        };
+        if keydir_entry.is_expired(now_millis(self.clock.as_ref())) {
+            return ValueLocation::Missing;
+        }
+
+        let access_count = if self.track_access_counts {
+            keydir_entry.record_access()
+        } else {
+            0
+        };
+
+        match &keydir_entry.inline_value {
+            Some(inline_value) => ValueLocation::Inline(inline_value),
+            None => ValueLocation::DataFile {
+                data_file_gen: keydir_entry.data_file_gen,
+                index: keydir_entry.index.clone(),
+                access_count,
+            },
+        }
+    }
+
+    /// Reads the value of a key from the data file entry at `index` into `buf`, replacing its
+    /// contents. The value cache is checked first, and filled on a miss.
+    ///
+    /// Returns whether the value was read. If the entry is corrupt, and the store is configured
+    /// with `OnCorruption::SkipReturnNone`, `buf` is left untouched and false is returned.
+    ///
+    /// # Errors
+    ///
+    /// See [`Readers::get`].
+    pub fn read_value(
+        &mut self,
+        key: &Vec<u8>,
+        data_file_gen: GenerationNumber,
+        index: &LogIndex,
+        access_count: u64,
+        buf: &mut Vec<u8>,
+    ) -> Result<bool, GetError> {
+        if let Some(cache) = &self.cache {
+            let mut cache = cache.lock().expect(CACHE_POISON_ERR);
+            if let Some(cached_value) = cache.get(key, data_file_gen, index.offset, access_count) {
+                buf.clear();
+                buf.extend_from_slice(cached_value);
+                self.counters.record_cache_hit();
+                return Ok(true);
+            }
+            self.counters.record_cache_miss();
+        }
+
+        if let Some(read_rate_limiter) = &self.read_rate_limiter {
+            read_rate_limiter
+                .acquire(index.len)
+                .map_err(|RateLimited| GetError {
+                    kind: GetErrorKind::RateLimited,
+                    key: key.clone(),
+                })?;
+        }
+
+        let out_of_range = self
+            .index_out_of_range(data_file_gen, index)
+            .map_err(|err| GetError {
+                kind: GetErrorKind::Io(err),
+                key: key.clone(),
+            })?;
+        if let Some(file_len) = out_of_range {
+            let err = GetError {
+                kind: GetErrorKind::IndexOutOfRange {
+                    generation: data_file_gen,
+                    offset: index.offset,
+                    len: index.len,
+                    file_len,
+                },
+                key: key.clone(),
+            };
+            return self.handle_read_corruption(
+                data_file_gen,
+                index,
+                CorruptionKind::IndexOutOfRange,
+                err,
+            );
+        }
+
+        self.counters.record_bytes_read(index.len);
+        // The scratch buffer is taken out of self while the data file reader is borrowed.
+        let mut payload = mem::take(&mut self.scratch);
+        let reader = self.get_data_file_reader(data_file_gen);
+
+        // TODO [RyanStan 3-25-24] This code is duplicated in remove. Extract it into a separate function.
+        let read = reader
+            .seek(SeekFrom::Start(index.offset))
+            .and_then(|_| read_entry_payload(reader, &mut payload));
+        if let Err(err) = read {
+            let truncated = err.kind() == io::ErrorKind::UnexpectedEof;
+            let err = GetError {
+                kind: GetErrorKind::Io(err),
+                key: key.clone(),
+            };
+            if truncated {
+                return self.handle_read_corruption(
+                    data_file_gen,
+                    index,
+                    CorruptionKind::TruncatedEntry,
+                    err,
+                );
+            }
+            return Err(err);
+        }
+
+        // The keydir only points at values. An entry of a different length than the keydir
+        // recorded, or that's a tombstone, is as corrupt as one that can't be deserialized.
+        let decoded = LogFileEntryRef::decode(&payload).and_then(|entry| {
+            if payload.len() as u64 != index.len {
+                return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                    "entry is {} bytes long, but the keydir expects {} bytes",
+                    payload.len(),
+                    index.len
+                ))));
+            }
+            match entry.value {
+                Some(value) => Ok((entry.key, entry.transforms, value)),
+                None => Err(Box::new(bincode::ErrorKind::Custom(
+                    "entry is a tombstone".to_string(),
+                ))),
+            }
+        });
+        let (entry_key, transforms, value) = match decoded {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                let err = GetError {
+                    kind: GetErrorKind::Deserialize(err),
+                    key: key.clone(),
+                };
+                return self.handle_read_corruption(
+                    data_file_gen,
+                    index,
+                    CorruptionKind::CorruptEntry,
+                    err,
+                );
+            }
+        };
+        if entry_key != key.as_slice() {
+            let err = GetError {
+                kind: GetErrorKind::KeyMismatch {
+                    generation: data_file_gen,
+                    offset: index.offset,
+                },
+                key: key.clone(),
+            };
+            return self.handle_read_corruption(
+                data_file_gen,
+                index,
+                CorruptionKind::KeyMismatch,
+                err,
+            );
+        }
+
+        let value = self
+            .value_transforms
+            .reverse(transforms, value)
+            .map_err(|err| GetError {
+                kind: GetErrorKind::Io(err),
+                key: key.clone(),
+            })?;
+        buf.clear();
+        buf.extend_from_slice(&value);
+
+        if let Some(cache) = &self.cache {
+            cache.lock().expect(CACHE_POISON_ERR).insert(
+                key.clone(),
+                data_file_gen,
+                index.offset,
+                access_count,
+                value.to_vec(),
+            );
+        }
+
+        self.scratch = payload;
+        Ok(true)
+    }
+
+    /// Returns the values of several keys, in the same order as the keys.
+    ///
+    /// The data files are read in the order the values are stored in them, rather than
+    /// in the order of the keys. This minimizes seeking between reads.
+    pub fn get_many_sorted(
+        &mut self,
+        keydir: &KeyDir,
+        keys: &[Vec<u8>],
+    ) -> Result<Vec<Option<Vec<u8>>>, GetError> {
+        let mut read_order: Vec<usize> = (0..keys.len()).collect();
+        read_order.sort_by_key(|&i| {
+            keydir
+                .get(&keys[i])
+                .map(|keydir_entry| (keydir_entry.data_file_gen, keydir_entry.index.offset))
+        });
+
+        let mut values = vec![None; keys.len()];
+        for i in read_order {
+            values[i] = self.get(keydir, &keys[i])?;
+        }
+        Ok(values)
+    }
 }
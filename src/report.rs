@@ -0,0 +1,123 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    logfile::LogFileIterator,
+    migrate::MIGRATE_TEMP_FILE_SUFFIX,
+    utils::{classify_file, FileKind},
+    GenerationNumber,
+};
+
+/// Describes the problems that `open` worked around while opening a store.
+///
+/// Returned by [`crate::RustcaskBuilder::open_with_report`]. None of these problems prevent
+/// the store from opening, but they may mean that data was lost, or that the directory needs
+/// to be cleaned up.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpenReport {
+    anomalies: Vec<OpenAnomaly>,
+}
+
+/// A problem that `open` worked around. See [`OpenReport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OpenAnomaly {
+    /// The data file ends with a partially written entry, which is ignored. No complete entry
+    /// follows it. This is what an interrupted write leaves behind. If the data file is the active
+    /// data file, the partial entry is cut off, so that new entries aren't written after it.
+    TruncatedEntry {
+        generation: GenerationNumber,
+        offset: u64,
+    },
+    /// An entry in the data file couldn't be deserialized, and was skipped.
+    CorruptEntry {
+        generation: GenerationNumber,
+        offset: u64,
+    },
+    /// The hint file couldn't be read or failed verification, so the keydir was rebuilt
+    /// from the data file instead.
+    HintFileRejected { generation: GenerationNumber },
+    /// The generation's data file couldn't be read, so it was skipped. Keys whose latest value
+    /// or tombstone is in this generation are unavailable, or return an older value.
+    /// Only reported when skipping unreadable generations is enabled.
+    /// See [`crate::RustcaskBuilder::set_skip_unreadable_generations`].
+    UnreadableGeneration { generation: GenerationNumber },
+    /// A temporary file left behind by an interrupted `Rustcask::migrate`.
+    /// It's ignored, and can be deleted.
+    StaleTempFile { path: PathBuf },
+    /// A file whose name looks like a rustcask file, but isn't one that rustcask recognizes.
+    /// It's ignored.
+    UnrecognizedFile { path: PathBuf },
+}
+
+impl OpenReport {
+    /// Returns the problems that were worked around, in the order they were found.
+    pub fn anomalies(&self) -> &[OpenAnomaly] {
+        &self.anomalies
+    }
+
+    /// Returns true if the store opened without working around any problems.
+    pub fn is_clean(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+
+    pub(crate) fn record(&mut self, anomaly: OpenAnomaly) {
+        self.anomalies.push(anomaly);
+    }
+
+    /// Records the files in the rustcask directory that the store ignores,
+    /// but whose names suggest they were written by rustcask.
+    pub(crate) fn scan_directory(&mut self, rustcask_dir: &Path) -> Result<(), io::Error> {
+        let mut paths = fs::read_dir(rustcask_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, io::Error>>()?;
+        paths.sort();
+
+        for path in paths {
+            if classify_file(&path) != FileKind::Unknown {
+                continue;
+            }
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            if file_name.ends_with(MIGRATE_TEMP_FILE_SUFFIX) {
+                self.record(OpenAnomaly::StaleTempFile { path });
+            } else if file_name.contains("rustcask") {
+                self.record(OpenAnomaly::UnrecognizedFile { path });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describes the entries of one generation's data file, as checked by
+/// [`crate::Rustcask::verify_generation`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenerationVerifyReport {
+    pub generation: GenerationNumber,
+    /// The number of entries that were read successfully, including tombstones and
+    /// overwritten values.
+    pub entries: u64,
+    /// The offsets of the entries that couldn't be deserialized, or whose checksums didn't match.
+    pub corrupt_offsets: Vec<u64>,
+    /// The offset of the partially written entry at the end of the data file, if there is one.
+    pub truncated_offset: Option<u64>,
+}
+
+impl GenerationVerifyReport {
+    /// Returns true if every entry in the data file could be read.
+    pub fn is_clean(&self) -> bool {
+        self.corrupt_offsets.is_empty() && self.truncated_offset.is_none()
+    }
+
+    /// Reads every entry of a data file, and records the ones that can't be read.
+    pub(crate) fn scan(generation: GenerationNumber, mut log_iter: LogFileIterator) -> Self {
+        let entries = log_iter.by_ref().count() as u64;
+        Self {
+            generation,
+            entries,
+            corrupt_offsets: log_iter.corrupt_offsets().to_vec(),
+            truncated_offset: log_iter.truncated_offset(),
+        }
+    }
+}
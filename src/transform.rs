@@ -0,0 +1,176 @@
+use std::{borrow::Cow, fmt::Debug, io, sync::Arc};
+
+/// A reversible transformation of values, such as compression or encryption.
+///
+/// Transforms are added to a store with [`crate::RustcaskBuilder::add_value_transform`].
+/// Every value that's written is passed through the transforms in the order they were added,
+/// and the tags of the transforms are stored with the value. Reads reverse the transforms named
+/// by the stored tags, in the opposite order, so values stay readable after transforms are
+/// added or reordered.
+///
+/// A transform must stay configured for as long as values written with it are in the store.
+pub trait ValueTransform: Debug + Send + Sync {
+    /// Identifies the transform in the data files. Every transform added to a store must have
+    /// a different tag, and a tag shouldn't be reused for a different transform.
+    fn tag(&self) -> u8;
+
+    /// Transforms a value before it's written.
+    fn apply(&self, value: &[u8]) -> Vec<u8>;
+
+    /// Recovers the value that `apply` was called with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value can't be recovered, for example because it's corrupt.
+    fn reverse(&self, value: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Whether the transform compresses values. Compression is skipped for values below the
+    /// store's compression min size, and the skipped transform's tag isn't stored with them.
+    /// See [`crate::RustcaskBuilder::set_compression_min_size`].
+    ///
+    /// Defaults to false.
+    fn is_compression(&self) -> bool {
+        false
+    }
+}
+
+/// The value transforms of a store, in the order they're applied.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ValueTransforms {
+    transforms: Vec<Arc<dyn ValueTransform>>,
+
+    // Values shorter than this many bytes aren't compressed.
+    compression_min_size: usize,
+}
+
+impl ValueTransforms {
+    pub fn push(&mut self, transform: Arc<dyn ValueTransform>) {
+        self.transforms.push(transform);
+    }
+
+    pub fn set_compression_min_size(&mut self, bytes: usize) {
+        self.compression_min_size = bytes;
+    }
+
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// Returns a tag that's shared by more than one transform, if there is one.
+    pub fn duplicate_tag(&self) -> Option<u8> {
+        self.transforms
+            .iter()
+            .enumerate()
+            .find_map(|(i, transform)| {
+                self.transforms[..i]
+                    .iter()
+                    .any(|earlier| earlier.tag() == transform.tag())
+                    .then(|| transform.tag())
+            })
+    }
+
+    /// Applies the transforms to the value. Returns the transformed value,
+    /// and the tags of the transforms that were applied, in order.
+    ///
+    /// Every transform is applied, except for compression transforms
+    /// when the value is shorter than the compression min size.
+    pub fn apply(&self, value: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+        let skip_compression = value.len() < self.compression_min_size;
+        let mut tags = Vec::new();
+        let value = self
+            .transforms
+            .iter()
+            .filter(|transform| !(skip_compression && transform.is_compression()))
+            .fold(value, |value, transform| {
+                tags.push(transform.tag());
+                transform.apply(&value)
+            });
+        (value, tags)
+    }
+
+    /// Reverses the transforms named by `tags` that were applied to the value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if a tag doesn't name a configured transform,
+    /// or the error of a transform that fails to reverse.
+    pub fn reverse<'a>(&self, tags: &[u8], value: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        let mut value = Cow::Borrowed(value);
+        for tag in tags.iter().rev() {
+            let transform = self
+                .transforms
+                .iter()
+                .find(|transform| transform.tag() == *tag)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "the value was written with value transform {}, which isn't configured",
+                            tag
+                        ),
+                    )
+                })?;
+            value = Cow::Owned(transform.reverse(&value)?);
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, sync::Arc};
+
+    use super::{ValueTransform, ValueTransforms};
+
+    #[derive(Debug)]
+    struct AddByte(u8);
+
+    impl ValueTransform for AddByte {
+        fn tag(&self) -> u8 {
+            self.0
+        }
+
+        fn apply(&self, value: &[u8]) -> Vec<u8> {
+            let mut value = value.to_vec();
+            value.push(self.0);
+            value
+        }
+
+        fn reverse(&self, value: &[u8]) -> io::Result<Vec<u8>> {
+            match value.split_last() {
+                Some((last, rest)) if *last == self.0 => Ok(rest.to_vec()),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "missing byte")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_transforms_reversed_in_opposite_order() {
+        let mut transforms = ValueTransforms::default();
+        transforms.push(Arc::new(AddByte(1)));
+        transforms.push(Arc::new(AddByte(2)));
+
+        let (value, tags) = transforms.apply(b"value".to_vec());
+        assert_eq!(value, b"value\x01\x02");
+        assert_eq!(tags, [1, 2]);
+        assert_eq!(transforms.reverse(&tags, &value).unwrap(), &b"value"[..]);
+
+        // Only the transforms named by the tags are reversed.
+        assert_eq!(
+            transforms.reverse(&[1], b"value\x01").unwrap(),
+            &b"value"[..]
+        );
+        assert_eq!(
+            transforms.reverse(&[3], b"value\x03").unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        assert_eq!(transforms.duplicate_tag(), None);
+        transforms.push(Arc::new(AddByte(1)));
+        assert_eq!(transforms.duplicate_tag(), Some(1));
+    }
+}
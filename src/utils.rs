@@ -1,14 +1,72 @@
 use regex::Regex;
 
-use crate::GenerationNumber;
+use crate::{lock::LOCK_FILE_NAME, meta::META_FILE_NAME, GenerationNumber};
 use std::{
-    fs::{self},
+    fs::{self, File},
     io,
     path::{Path, PathBuf},
 };
 
 pub const KEYDIR_POISON_ERR: &str = "Another thread crashed while holding keydir lock. Panicking.";
 
+/// Decides whether a file in the rustcask directory belongs to the store.
+/// See [`crate::RustcaskBuilder::set_file_filter`].
+pub type FileFilter = fn(&Path) -> bool;
+
+/// The default file filter, which accepts every file.
+pub fn include_all_files(_: &Path) -> bool {
+    true
+}
+
+/// The kinds of files that can be found in a rustcask directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    /// `<generation>.rustcask.data`
+    Data,
+    /// `<generation>.rustcask.hint`
+    Hint,
+    /// `<generation>.rustcask.bloom`
+    Bloom,
+    /// `<generation>.rustcask.snapshot`
+    Snapshot,
+    /// `<generation>.rustcask.retired`, which marks a generation that was replaced by a merge
+    /// and is waiting to be deleted.
+    Retired,
+    /// `rustcask.lock`
+    Lock,
+    /// `rustcask.meta`
+    Meta,
+    /// Any file that isn't a rustcask file.
+    Unknown,
+}
+
+/// Classifies a file in the rustcask directory by its name.
+pub fn classify_file(path: &Path) -> FileKind {
+    let file_name = match path.file_name() {
+        Some(file) => file.to_string_lossy(),
+        None => return FileKind::Unknown,
+    };
+    if file_name == LOCK_FILE_NAME {
+        return FileKind::Lock;
+    }
+    if file_name == META_FILE_NAME {
+        return FileKind::Meta;
+    }
+
+    let re = Regex::new(r"^\d+\.rustcask\.(data|hint|bloom|snapshot|retired)$").unwrap();
+    let Some(captures) = re.captures(&file_name) else {
+        return FileKind::Unknown;
+    };
+    match &captures[1] {
+        "data" => FileKind::Data,
+        "hint" => FileKind::Hint,
+        "bloom" => FileKind::Bloom,
+        "snapshot" => FileKind::Snapshot,
+        "retired" => FileKind::Retired,
+        _ => unreachable!("The regex only matches known extensions"),
+    }
+}
+
 pub fn data_file_path(rustcask_dir: &Path, generation: &GenerationNumber) -> PathBuf {
     rustcask_dir.join(format!("{}.rustcask.data", generation))
 }
@@ -17,15 +75,25 @@ pub fn hint_file_path(rustcask_dir: &Path, generation: GenerationNumber) -> Path
     rustcask_dir.join(format!("{}.rustcask.hint", generation))
 }
 
+pub fn retired_file_path(rustcask_dir: &Path, generation: GenerationNumber) -> PathBuf {
+    rustcask_dir.join(format!("{}.rustcask.retired", generation))
+}
+
 /// Returns the generations that are present within a directory.
-pub fn list_generations(rustcask_dir: &Path) -> Result<Vec<GenerationNumber>, io::Error> {
+/// Data files rejected by the file filter, and retired generations, are ignored.
+pub fn list_generations(
+    rustcask_dir: &Path,
+    file_filter: FileFilter,
+) -> Result<Vec<GenerationNumber>, io::Error> {
     let mut generations: Vec<GenerationNumber> = Vec::new();
     let entries = fs::read_dir(rustcask_dir)?;
     for entry in entries {
         let entry = entry?.path();
-        if is_data_file(&entry) {
+        if is_data_file(&entry) && file_filter(&entry) {
             let gen: GenerationNumber = parse_generation_number(entry);
-            generations.push(gen);
+            if !retired_file_path(rustcask_dir, gen).exists() {
+                generations.push(gen);
+            }
         }
     }
 
@@ -33,13 +101,27 @@ pub fn list_generations(rustcask_dir: &Path) -> Result<Vec<GenerationNumber>, io
 }
 
 pub fn is_data_file(path: &Path) -> bool {
-    let file_name = match path.file_name() {
-        Some(file) => file,
-        None => return false,
-    };
+    classify_file(path) == FileKind::Data
+}
 
-    let re = Regex::new(r"^\d+\.rustcask\.data$").unwrap();
-    re.is_match(&file_name.to_string_lossy())
+pub fn is_hint_file(path: &Path) -> bool {
+    classify_file(path) == FileKind::Hint
+}
+
+/// Moves a file, falling back to copying and then deleting the source
+/// if the destination is on a different filesystem.
+pub fn move_file(from: &Path, to: &Path) -> Result<(), io::Error> {
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => copy_then_delete(from, to),
+        Err(err) => Err(err),
+    }
+}
+
+fn copy_then_delete(from: &Path, to: &Path) -> Result<(), io::Error> {
+    fs::copy(from, to)?;
+    File::open(to)?.sync_all()?;
+    fs::remove_file(from)
 }
 
 /// Returns the generation of a hint or data file
@@ -64,23 +146,19 @@ pub mod tests {
 
     use crate::{
         logfile::LogFileIterator,
-        utils::{is_data_file, list_generations, parse_generation_number},
+        utils::{
+            classify_file, copy_then_delete, include_all_files, is_data_file, is_hint_file,
+            list_generations, parse_generation_number, FileKind,
+        },
     };
 
-    /// Return the names of the files within a directory
+    /// Return the names of the files within a directory, except for the lock file
     pub fn file_names(temp_dir_path: &Path) -> Vec<String> {
         let data_files = fs::read_dir(temp_dir_path).unwrap();
         let data_files: Vec<String> = data_files
-            .map(|dir_entry| {
-                dir_entry
-                    .unwrap()
-                    .path()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-            })
+            .map(|dir_entry| dir_entry.unwrap().path())
+            .filter(|path| classify_file(path) != FileKind::Lock)
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
             .collect();
         data_files
     }
@@ -115,16 +193,77 @@ pub mod tests {
     #[test]
     fn test_is_data_file() {
         let dir = tempdir().unwrap();
-        let data_file = dir.path().join("/tmp/384304/0.rustcask.data");
+        let data_file = dir.path().join("0.rustcask.data");
         assert!(is_data_file(&data_file));
 
-        let hint_file = dir.path().join("/tmp/384304/0.rustcask.hint");
+        let hint_file = dir.path().join("0.rustcask.hint");
         assert!(!is_data_file(&hint_file));
 
-        let random_file = dir.path().join("/tmp/3432432/some-lock-file.lock");
+        let random_file = dir.path().join("some-lock-file.lock");
         assert!(!is_data_file(&random_file));
     }
 
+    #[test]
+    fn test_is_hint_file() {
+        let dir = tempdir().unwrap();
+        assert!(is_hint_file(&dir.path().join("0.rustcask.hint")));
+        assert!(!is_hint_file(&dir.path().join("0.rustcask.data")));
+        assert!(!is_hint_file(&dir.path().join("some-lock-file.lock")));
+    }
+
+    #[test]
+    fn test_classify_file() {
+        let dir = tempdir().unwrap();
+        let cases = [
+            ("0.rustcask.data", FileKind::Data),
+            ("12.rustcask.hint", FileKind::Hint),
+            ("3.rustcask.bloom", FileKind::Bloom),
+            ("4.rustcask.snapshot", FileKind::Snapshot),
+            ("5.rustcask.retired", FileKind::Retired),
+            ("rustcask.lock", FileKind::Lock),
+            ("rustcask.meta", FileKind::Meta),
+            ("some-lock-file.lock", FileKind::Unknown),
+            ("0.rustcask.data.migrating", FileKind::Unknown),
+            ("backup.rustcask.data", FileKind::Unknown),
+            ("0.rustcask.wal", FileKind::Unknown),
+        ];
+        for (file_name, kind) in cases {
+            assert_eq!(
+                classify_file(&dir.path().join(file_name)),
+                kind,
+                "{}",
+                file_name
+            );
+        }
+    }
+
+    #[test]
+    fn test_list_generations_with_file_filter() {
+        let dir = tempdir().unwrap();
+        for number in 0..4 {
+            File::create(dir.path().join(format!("{}.rustcask.data", number))).unwrap();
+        }
+
+        let mut generations =
+            list_generations(dir.path(), |path| !path.ends_with("3.rustcask.data")).unwrap();
+        generations.sort_unstable();
+        assert_eq!(generations, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_copy_then_delete() {
+        let src_dir = tempdir().unwrap();
+        let dest_dir = tempdir().unwrap();
+        let from = src_dir.path().join("0.rustcask.data");
+        let to = dest_dir.path().join("0.rustcask.data");
+        fs::write(&from, b"data").unwrap();
+
+        copy_then_delete(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(fs::read(&to).unwrap(), b"data");
+    }
+
     #[test]
     fn test_list_generations() {
         let dir = tempdir().unwrap();
@@ -134,21 +273,27 @@ pub mod tests {
             File::create(dir.path().join(format!("{}.rustcask.hint", number))).unwrap();
         }
 
-        let mut generations = list_generations(dir.path()).unwrap();
+        let mut generations = list_generations(dir.path(), include_all_files).unwrap();
         generations.sort_unstable();
         let expected_range = 0..5;
         let expected_gen_values: Vec<u64> = expected_range.collect();
         assert_eq!(generations, expected_gen_values);
+
+        // Retired generations are ignored.
+        File::create(dir.path().join("2.rustcask.retired")).unwrap();
+        let mut generations = list_generations(dir.path(), include_all_files).unwrap();
+        generations.sort_unstable();
+        assert_eq!(generations, [0, 1, 3, 4]);
     }
 
     #[test]
     fn test_parse_generation_number() {
         let dir = tempdir().unwrap();
-        let data_file = dir.path().join("/tmp/384304/0.rustcask.data");
+        let data_file = dir.path().join("0.rustcask.data");
         assert_eq!(parse_generation_number(data_file), 0);
 
         let dir = tempdir().unwrap();
-        let data_file = dir.path().join("/tmp/384304/1000.rustcask.hint");
+        let data_file = dir.path().join("1000.rustcask.hint");
         assert_eq!(parse_generation_number(data_file), 1000);
     }
 }
@@ -0,0 +1,231 @@
+//! A randomized workload for checking that the store stays consistent under concurrent use.
+//!
+//! Several threads share a store through cloned handles, and interleave sets, gets, removes,
+//! and merges. Each thread owns a range of keys, and keeps a model of what its keys should hold.
+//! A thread's own keys are only written by that thread, so every get of an owned key must match
+//! the model exactly, no matter what the other threads are doing. Threads also read each other's
+//! keys, and check that the value was written to that key by its owner.
+//!
+//! The operations each thread performs are chosen by a random number generator seeded from the
+//! workload's seed, so a failing workload performs the same operations when it's run again.
+//! The interleaving of the threads isn't deterministic.
+
+use std::{collections::BTreeMap, path::Path, thread};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{Rustcask, RustcaskBuilder};
+
+/// Describes a workload. See [`run_workload`].
+#[derive(Clone, Debug)]
+pub struct WorkloadConfig {
+    pub seed: u64,
+    pub threads: usize,
+    pub ops_per_thread: usize,
+    pub keys_per_thread: usize,
+    // The largest value written, in bytes. Values are at least a few bytes long.
+    pub max_value_len: usize,
+    // The chance that an operation is a merge, as one in this many operations.
+    pub merge_one_in: u32,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            threads: 4,
+            ops_per_thread: 1000,
+            keys_per_thread: 64,
+            max_value_len: 256,
+            merge_one_in: 250,
+        }
+    }
+}
+
+/// The key-value pairs that the store should hold.
+pub type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Runs the workload against the store in `rustcask_dir`, and checks that the store holds
+/// exactly what the model says, both once the threads are done and after the store is reopened.
+///
+/// Panics, naming the seed, if the store is inconsistent.
+pub fn run_workload(builder: &RustcaskBuilder, rustcask_dir: &Path, config: &WorkloadConfig) {
+    let store = builder.clone().open(rustcask_dir).unwrap();
+
+    let workers: Vec<_> = (0..config.threads)
+        .map(|worker| {
+            let store = store.clone();
+            let config = config.clone();
+            thread::spawn(move || run_worker(store, worker, &config))
+        })
+        .collect();
+    let mut model = Model::new();
+    for worker in workers {
+        model.extend(worker.join().expect("A workload thread panicked."));
+    }
+
+    check_store(store, &model, config.seed);
+    let store = builder.clone().open(rustcask_dir).unwrap();
+    check_store(store, &model, config.seed);
+}
+
+/// Performs a worker's share of the workload, and returns the model of its keys.
+fn run_worker(mut store: Rustcask, worker: usize, config: &WorkloadConfig) -> Model {
+    let mut rng = StdRng::seed_from_u64(config.seed.wrapping_add(worker as u64));
+    let mut model = Model::new();
+
+    for op in 0..config.ops_per_thread {
+        let key_index = rng.gen_range(0..config.keys_per_thread);
+        let key = workload_key(worker, key_index);
+        let context = || format!("seed {}, worker {}, op {}", config.seed, worker, op);
+
+        if rng.gen_ratio(1, config.merge_one_in) {
+            store
+                .merge()
+                .unwrap_or_else(|err| panic!("merge failed ({}): {}", context(), err));
+            continue;
+        }
+        match rng.gen_range(0..100) {
+            0..=44 => {
+                let value = workload_value(worker, key_index, op, &mut rng, config.max_value_len);
+                store
+                    .set(key.clone(), value.clone())
+                    .unwrap_or_else(|err| panic!("set failed ({}): {}", context(), err));
+                model.insert(key, value);
+            }
+            45..=59 => {
+                let removed = store
+                    .remove(key.clone())
+                    .unwrap_or_else(|err| panic!("remove failed ({}): {}", context(), err));
+                assert_eq!(removed, model.remove(&key), "remove ({})", context());
+            }
+            60..=84 => {
+                let value = store
+                    .get(&key)
+                    .unwrap_or_else(|err| panic!("get failed ({}): {}", context(), err));
+                assert_eq!(value.as_ref(), model.get(&key), "get ({})", context());
+            }
+            _ => {
+                // Another worker's key may change at any time, but it may only ever hold
+                // values that its owner wrote to it.
+                let owner = rng.gen_range(0..config.threads);
+                let key = workload_key(owner, key_index);
+                let value = store
+                    .get(&key)
+                    .unwrap_or_else(|err| panic!("get failed ({}): {}", context(), err));
+                if let Some(value) = value {
+                    assert!(
+                        value.starts_with(&value_prefix(owner, key_index)),
+                        "get of worker {}'s key returned a value written elsewhere ({})",
+                        owner,
+                        context()
+                    );
+                }
+            }
+        }
+    }
+    model
+}
+
+/// Checks that the store holds exactly the key-value pairs in the model.
+fn check_store(mut store: Rustcask, model: &Model, seed: u64) {
+    let contents: Model = store
+        .iter_ordered()
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|err| panic!("iterating failed (seed {}): {}", seed, err));
+    assert!(
+        contents == *model,
+        "store holds {} keys, but the model holds {} (seed {})",
+        contents.len(),
+        model.len(),
+        seed
+    );
+    for (key, value) in model {
+        assert_eq!(
+            store.get(key).unwrap().as_ref(),
+            Some(value),
+            "seed {}",
+            seed
+        );
+    }
+}
+
+fn workload_key(worker: usize, key_index: usize) -> Vec<u8> {
+    format!("worker-{}-key-{}", worker, key_index).into_bytes()
+}
+
+/// Returns a value for a worker's key that identifies who wrote it, padded to a random length.
+fn workload_value(
+    worker: usize,
+    key_index: usize,
+    op: usize,
+    rng: &mut StdRng,
+    max_value_len: usize,
+) -> Vec<u8> {
+    let mut value = value_prefix(worker, key_index);
+    value.extend_from_slice(op.to_string().as_bytes());
+    let padding = rng.gen_range(0..=max_value_len.saturating_sub(value.len()));
+    value.resize(value.len() + padding, b'.');
+    value
+}
+
+fn value_prefix(worker: usize, key_index: usize) -> Vec<u8> {
+    format!("{}/{}/", worker, key_index).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::Rustcask;
+
+    use super::{run_workload, WorkloadConfig};
+
+    #[test]
+    fn test_concurrent_workload_matches_model() {
+        for seed in 0..2 {
+            let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+            let builder = Rustcask::builder().set_max_data_file_size(4096);
+            let config = WorkloadConfig {
+                seed,
+                ..WorkloadConfig::default()
+            };
+            run_workload(&builder, temp_dir.path(), &config);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_workload_with_merge_options() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let builder = Rustcask::builder()
+            .set_max_data_file_size(4096)
+            .set_merge_threads(2)
+            .set_merge_max_extra_bytes(16 * 1024)
+            .set_inline_value_threshold(32)
+            .set_in_place_updates(true)
+            .set_hint_compression(true);
+        run_workload(&builder, temp_dir.path(), &WorkloadConfig::default());
+    }
+
+    /// A much longer run of the workload. Run it with `cargo test -- --ignored`.
+    /// Set `RUSTCASK_WORKLOAD_SEED` to repeat a failing run.
+    #[test]
+    #[ignore]
+    fn test_long_concurrent_workload() {
+        let seed = std::env::var("RUSTCASK_WORKLOAD_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random);
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let builder = Rustcask::builder().set_max_data_file_size(64 * 1024);
+        let config = WorkloadConfig {
+            seed,
+            threads: 8,
+            ops_per_thread: 50_000,
+            keys_per_thread: 1024,
+            max_value_len: 1024,
+            merge_one_in: 2000,
+        };
+        run_workload(&builder, temp_dir.path(), &config);
+    }
+}
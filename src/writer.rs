@@ -1,37 +1,85 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs::{self, File, OpenOptions},
     io::{self, BufWriter, Read, Seek, SeekFrom, Write},
-    path::PathBuf,
-    sync::{Arc, RwLock},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::Duration,
 };
 
-use log::{debug, trace};
+use log::{debug, error, info, trace, warn};
 
 use crate::{
+    clock::now_millis,
     error::{
         MergeError, MergeErrorKind, OpenError, OpenErrorKind, RemoveError, RemoveErrorKind,
         SetError, SetErrorKind,
     },
-    keydir::KeyDir,
-    logfile::{LogFileEntry, LogIndex},
+    hint::{hint_entry_len, write_hint_file, HintFileEntry, HINT_FILE_HEADER_LEN},
+    keydir::{KeyDir, KeyDirEntry},
+    logfile::{
+        write_data_file_header, LogFileEntry, LogFileIterator, LogIndex, DATA_FILE_HEADER_LEN,
+    },
+    pins::PinGuard,
+    rate_limit::{RateLimited, RateLimiter},
     readers::Readers,
-    utils::{data_file_path, list_generations, KEYDIR_POISON_ERR},
-    GenerationNumber,
+    utils::{data_file_path, hint_file_path, list_generations, KEYDIR_POISON_ERR},
+    GenerationNumber, RustcaskBuilder,
 };
 
+/// Once an automatic merge has been triggered, another one won't be triggered
+/// until the dead entry ratio falls below this fraction of the auto-merge ratio.
+const AUTO_MERGE_REARM_FACTOR: f64 = 0.5;
+
+/// The number of live entries each merge thread reads before the entries are written
+/// to the merge output. This bounds the memory used by a merge.
+const MERGE_BATCH_ENTRIES_PER_THREAD: usize = 1024;
+
 /// The Writer is responsible for writing data to the rustcask directory.
 ///
 /// The Writer is wrapped in an Arc<Mutex<>> within the Rustcask struct to allow for concurrent access.
 #[derive(Debug)]
 pub struct Writer {
     pub(crate) active_generation: GenerationNumber,
-    pub(crate) active_data_file: BufWriter<File>,
+    // None until the first write when the active data file is created lazily.
+    pub(crate) active_data_file: Option<BufWriter<File>>,
     pub(crate) active_data_file_size: u64,
     pub(crate) sync_mode: bool,
     pub(crate) max_data_file_size: u64,
+    // When set, a merge is performed once the ratio of dead entries to live keys reaches this value.
+    pub(crate) auto_merge_ratio: Option<f64>,
+    // Whether the next write that crosses the auto-merge ratio should trigger a merge.
+    pub(crate) auto_merge_armed: bool,
+    // The auto-merge ratio is checked once every this many writes.
+    pub(crate) auto_merge_check_interval: u64,
+    pub(crate) writes_since_auto_merge_check: u64,
+    // The number of threads that read live entries during a merge.
+    pub(crate) merge_threads: usize,
+    // How long the generations replaced by a merge are kept before they're deleted.
+    pub(crate) merge_delete_delay: Duration,
+    // The extra disk space a merge step may use. `None` merges every generation in one step.
+    pub(crate) merge_max_extra_bytes: Option<u64>,
+    // Whether merges compress the hint files they write.
+    pub(crate) hint_compression: bool,
+    // Values of at most this many bytes are inlined in the keydir. 0 disables inlining.
+    pub(crate) inline_value_threshold: usize,
+    // Whether values may be overwritten in place. See `RustcaskBuilder::set_in_place_updates`.
+    pub(crate) in_place_updates: bool,
+    // The number of entries read ahead when scanning a data file. 0 disables prefetching.
+    pub(crate) scan_prefetch: usize,
+    // Whether the times issued to writes are kept from going backwards.
+    pub(crate) monotonic_timestamps: bool,
+    // The last time issued to a write, in milliseconds since the UNIX epoch.
+    pub(crate) last_timestamp: Option<u64>,
+    // Limits the rate at which set and remove write to the active data file.
+    pub(crate) write_rate_limiter: Option<RateLimiter>,
     pub(crate) rustcask_directory: Arc<PathBuf>,
     pub(crate) keydir: Arc<RwLock<KeyDir>>,
     pub(crate) readers: Readers,
+    // Held for the duration of a merge, so that only one merge runs at a time.
+    // It's shared with the `Rustcask` handles, which release the writer lock while merging.
+    pub(crate) merge_lock: Arc<Mutex<()>>,
 }
 
 impl Writer {
@@ -42,8 +90,7 @@ impl Writer {
     ///
     ///  # Arguments
     ///
-    /// * `sync_mode` - A boolean indicating whether to sync data to disk after every write.
-    /// * `max_data_file_size` - The maximum size (in bytes) for a single data file.
+    /// * `options` - The configuration the store was opened with.
     /// * `rustcask_directory` - An `Arc<PathBuf>` representing the path to the RustCask directory.
     /// * `keydir` - An `Arc<RwLock<KeyDir>>` representing the key directory.
     /// * `readers` - A `Readers` instance containing the active readers.
@@ -61,47 +108,75 @@ impl Writer {
     /// * There was an I/O error listing the generations in the RustCask directory.
     /// * There was an I/O error opening the active data file.
     pub fn new(
-        sync_mode: bool,
-        max_data_file_size: u64,
+        options: &RustcaskBuilder,
         rustcask_directory: Arc<PathBuf>,
         keydir: Arc<RwLock<KeyDir>>,
         readers: Readers,
     ) -> Result<Writer, OpenError> {
-        let mut generations: Vec<GenerationNumber> = list_generations(&rustcask_directory)
-            .map_err(|err| OpenError {
-                kind: OpenErrorKind::Io(err),
-                rustcask_dir: rustcask_directory.to_string_lossy().to_string(),
+        let mut generations: Vec<GenerationNumber> =
+            list_generations(&rustcask_directory, options.file_filter).map_err(|err| {
+                OpenError {
+                    kind: OpenErrorKind::Io(err),
+                    rustcask_dir: rustcask_directory.to_string_lossy().to_string(),
+                }
             })?;
         generations.sort_unstable();
 
-        let active_generation: GenerationNumber = match generations.last() {
+        let mut active_generation: GenerationNumber = match generations.last() {
             Some(generation) => *generation,
             None => 0,
         };
+        // An unreadable data file can't be appended to, so new writes go to a new generation.
+        if keydir
+            .read()
+            .expect(KEYDIR_POISON_ERR)
+            .unreadable_generations()
+            .contains(&active_generation)
+        {
+            active_generation += 1;
+        }
 
-        let active_data_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(data_file_path(&rustcask_directory, &active_generation))
-            .map_err(|err| OpenError {
-                kind: OpenErrorKind::Io(err),
-                rustcask_dir: rustcask_directory.to_string_lossy().to_string(),
-            })?;
-
-        let active_data_file_size = active_data_file.metadata().unwrap().len();
-
-        let buffered_writer = BufWriter::new(active_data_file);
+        let (active_data_file, active_data_file_size) = if options.lazy_active_file
+            && !data_file_path(&rustcask_directory, &active_generation).exists()
+        {
+            (None, 0)
+        } else {
+            let (active_data_file, active_data_file_size) =
+                open_active_data_file(&rustcask_directory, active_generation).map_err(|err| {
+                    OpenError {
+                        kind: OpenErrorKind::Io(err),
+                        rustcask_dir: rustcask_directory.to_string_lossy().to_string(),
+                    }
+                })?;
+            (Some(active_data_file), active_data_file_size)
+        };
 
         Ok(Writer {
             active_generation,
-            active_data_file: buffered_writer,
+            active_data_file,
             active_data_file_size,
-            sync_mode,
-            max_data_file_size,
+            sync_mode: options.sync_mode,
+            max_data_file_size: options.max_data_file_size,
+            auto_merge_ratio: options.auto_merge.then_some(options.auto_merge_ratio),
+            auto_merge_armed: true,
+            auto_merge_check_interval: options.auto_merge_check_interval,
+            writes_since_auto_merge_check: 0,
+            merge_threads: options.merge_threads,
+            merge_delete_delay: options.merge_delete_delay,
+            merge_max_extra_bytes: options.merge_max_extra_bytes,
+            hint_compression: options.hint_compression,
+            inline_value_threshold: options.inline_value_threshold,
+            in_place_updates: options.in_place_updates,
+            scan_prefetch: options.scan_prefetch,
+            monotonic_timestamps: options.monotonic_timestamps,
+            last_timestamp: None,
+            write_rate_limiter: options
+                .write_rate_limit
+                .map(|bytes_per_sec| RateLimiter::new(bytes_per_sec, options.rate_limit_blocking)),
             rustcask_directory,
             keydir,
             readers,
+            merge_lock: Arc::new(Mutex::new(())),
         })
     }
 
@@ -129,45 +204,284 @@ impl Writer {
     ///
     /// * The `LogFileEntry` could not be serialized (`SetErrorKind::Serialize`).
     /// * There was an error writing to the active data file.
+    /// * The write rate limit was exceeded and rate limiting is non-blocking (`SetErrorKind::RateLimited`).
+    /// * The value was written, but the full active data file couldn't be rotated (`SetErrorKind::Rotation`).
     ///
     /// # Panics
     ///
     /// This function will panic if another thread crashed while holding the lock on the key directory.
     pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), SetError> {
+        self.set_entry(key, value, None, self.sync_mode)
+    }
+
+    /// Inserts a key-value pair, syncing it to disk if `durable` is true, regardless of
+    /// whether sync mode is on.
+    ///
+    /// # Errors
+    ///
+    /// See [`Writer::set`].
+    pub fn set_durable(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        durable: bool,
+    ) -> Result<(), SetError> {
+        self.set_entry(key, value, None, durable)
+    }
+
+    /// Inserts a key-value pair that expires once `ttl` has passed, according to the store's clock.
+    /// Expired values are no longer returned by reads, and are dropped by the next merge.
+    ///
+    /// # Errors
+    ///
+    /// See [`Writer::set`].
+    pub fn set_with_ttl(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), SetError> {
+        let ttl_millis = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let expires_at = self.next_timestamp().saturating_add(ttl_millis);
+        self.set_entry(key, value, Some(expires_at), self.sync_mode)
+    }
+
+    /// Rewrites the key's current value to the active data file, keeping its expiry time, so that
+    /// the generation it was stored in no longer holds it. Returns false if the key doesn't exist
+    /// or has expired.
+    ///
+    /// A value that's already in the active data file is left where it is.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SetErrorKind::Read` if the value couldn't be read.
+    /// Otherwise, see [`Writer::set`].
+    pub fn compact_key(&mut self, key: &Vec<u8>) -> Result<bool, SetError> {
+        let mut value = Vec::new();
+        let keydir = self.keydir.read().expect(KEYDIR_POISON_ERR);
+        let Some(keydir_entry) = keydir.get(key) else {
+            return Ok(false);
+        };
+        if keydir_entry.is_expired(now_millis(self.readers.clock.as_ref())) {
+            return Ok(false);
+        }
+        if keydir_entry.data_file_gen == self.active_generation {
+            return Ok(true);
+        }
+        let expires_at = keydir_entry.expires_at;
+        match &keydir_entry.inline_value {
+            Some(inline_value) => value.extend_from_slice(inline_value),
+            None => {
+                let read = self
+                    .readers
+                    .read_value(
+                        key,
+                        keydir_entry.data_file_gen,
+                        &keydir_entry.index,
+                        keydir_entry.access_count(),
+                        &mut value,
+                    )
+                    .map_err(|err| SetError {
+                        kind: SetErrorKind::Read(err),
+                        key: key.clone(),
+                    })?;
+                // The value is corrupt, and reads treat the key as missing.
+                if !read {
+                    return Ok(false);
+                }
+            }
+        }
+        drop(keydir);
+
+        self.set_entry(key.clone(), value, expires_at, self.sync_mode)?;
+        Ok(true)
+    }
+
+    /// Returns the time to issue to a write, in milliseconds since the UNIX epoch.
+    ///
+    /// With monotonic timestamps, the time is clamped to 1ms after the last issued time,
+    /// so that it never goes backwards when the clock does.
+    fn next_timestamp(&mut self) -> u64 {
+        let now = now_millis(self.readers.clock.as_ref());
+        if !self.monotonic_timestamps {
+            return now;
+        }
+        let timestamp = match self.last_timestamp {
+            Some(last_timestamp) => now.max(last_timestamp.saturating_add(1)),
+            None => now,
+        };
+        self.last_timestamp = Some(timestamp);
+        timestamp
+    }
+
+    /// Writes a value, and syncs it to disk if `sync` is true.
+    fn set_entry(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+        sync: bool,
+    ) -> Result<(), SetError> {
         // To maintain correctness with concurrent reads, 'set' must insert an entry into the active data file,
         // and then update the keydir. This way, a concurrent read does not see an entry in the keydir
         // before the corresponding value has been written to the data file.
+
+        // Inlined values are kept untransformed. The value is only copied when it's going to be
+        // transformed, and inlining is only possible if the transformed value is small too.
+        let inline = self.inline_value_threshold > 0 && value.len() <= self.inline_value_threshold;
+        let untransformed_value =
+            (inline && !self.readers.value_transforms.is_empty()).then(|| value.clone());
+        let (value, transforms) = self.readers.value_transforms.apply(value);
+        let inline = inline && value.len() <= self.inline_value_threshold;
+        let value_len = value.len() as u64;
         let data_file_entry = LogFileEntry {
             key,
             value: Some(value),
+            expires_at,
+            transforms,
         };
 
-        let encoded = bincode::serialize(&data_file_entry).map_err(|err| SetError {
+        let encoded = data_file_entry.encode().map_err(|err| SetError {
             kind: SetErrorKind::Serialize(err),
             key: data_file_entry.key.clone(),
         })?;
 
-        let (log_index, gen) = self.write_to_active_data_file(encoded).unwrap();
+        if let Some(write_rate_limiter) = &self.write_rate_limiter {
+            write_rate_limiter
+                .acquire(encoded.len() as u64)
+                .map_err(|RateLimited| SetError {
+                    kind: SetErrorKind::RateLimited,
+                    key: data_file_entry.key.clone(),
+                })?;
+        }
 
-        self.keydir
+        let overwritten_in_place = self.in_place_updates
+            && self
+                .overwrite_in_place(&data_file_entry.key, &encoded, value_len, expires_at, sync)
+                .map_err(|err| SetError {
+                    kind: SetErrorKind::Io(err),
+                    key: data_file_entry.key.clone(),
+                })?;
+        let written = if overwritten_in_place {
+            None
+        } else {
+            Some(
+                self.write_to_active_data_file(encoded, sync)
+                    .map_err(|err| SetError {
+                        kind: SetErrorKind::Io(err),
+                        key: data_file_entry.key.clone(),
+                    })?,
+            )
+        };
+
+        let inline_value = if inline {
+            untransformed_value.or(data_file_entry.value)
+        } else {
+            None
+        };
+        let mut keydir = self
+            .keydir
             .write()
-            .expect("Another thread crashed while holding keydir lock. Panicking.")
-            .set(data_file_entry.key.clone(), gen, log_index);
+            .expect("Another thread crashed while holding keydir lock. Panicking.");
+        if let Some((log_index, gen)) = written {
+            // Overwriting a key updates its entry without copying the key.
+            if !keydir.overwrite(&data_file_entry.key, gen, &log_index, value_len, expires_at) {
+                keydir.set(
+                    data_file_entry.key.clone(),
+                    gen,
+                    log_index,
+                    value_len,
+                    expires_at,
+                );
+            }
+        }
+        if let Some(inline_value) = inline_value {
+            keydir.set_inline_value(&data_file_entry.key, inline_value.into_boxed_slice());
+        }
+        drop(keydir);
+        self.readers.counters.record_set();
+
+        // The value is written and visible to reads before the active data file is rotated,
+        // so a failed rotation doesn't lose it.
+        self.rotate_if_full().map_err(|err| SetError {
+            kind: SetErrorKind::Rotation(err),
+            key: data_file_entry.key.clone(),
+        })?;
+
+        self.maybe_auto_merge();
 
         Ok(())
     }
 
+    /// Overwrites the key's current entry with the encoded entry, if the current entry is in the
+    /// active data file and has the same length. Returns whether the entry was overwritten.
+    ///
+    /// The keydir write lock is held while the entry is overwritten, so that reads never see a
+    /// partially overwritten entry. Entries in a pinned active generation are never overwritten,
+    /// because pinned generations are read outside of the keydir lock.
+    ///
+    /// If writing fails, the entry may be left partially overwritten.
+    fn overwrite_in_place(
+        &mut self,
+        key: &Vec<u8>,
+        encoded_log_file_entry: &[u8],
+        value_len: u64,
+        expires_at: Option<u64>,
+        sync: bool,
+    ) -> Result<bool, io::Error> {
+        let keydir_lock = self.keydir.clone();
+        let mut keydir = keydir_lock.write().expect(KEYDIR_POISON_ERR);
+        let log_index = match keydir.get(key) {
+            Some(keydir_entry)
+                if keydir_entry.data_file_gen == self.active_generation
+                    && keydir_entry.index.len == encoded_log_file_entry.len() as u64 =>
+            {
+                keydir_entry.index.clone()
+            }
+            _ => return Ok(false),
+        };
+        if self.readers.pins.is_pinned(self.active_generation) {
+            return Ok(false);
+        }
+
+        let active_data_file = self.active_data_file()?;
+        let end = active_data_file.stream_position()?;
+        active_data_file.seek(SeekFrom::Start(log_index.offset))?;
+        let written = active_data_file
+            .write_all(encoded_log_file_entry)
+            .and_then(|()| active_data_file.flush());
+        active_data_file.seek(SeekFrom::Start(end))?;
+        written?;
+        if sync {
+            active_data_file.get_ref().sync_all()?;
+            self.readers.counters.record_synced_write();
+        }
+        self.readers
+            .counters
+            .record_bytes_written(encoded_log_file_entry.len() as u64);
+        trace!(
+            "Overwrote {} bytes in place at offset {} in data file (gen={})",
+            encoded_log_file_entry.len(),
+            log_index.offset,
+            self.active_generation
+        );
+
+        keydir.overwrite_in_place(key, value_len, expires_at);
+        self.readers.forget_cached_value(key);
+        Ok(true)
+    }
+
     /// Writes the encoded log file entry to the active data file.
     ///
-    /// This function appends the encoded log file entry to the active data file. If the active data
-    /// file exceeds the maximum size after writing the entry, it rotates to a new data file with an
-    /// incremented generation number.
+    /// This function appends the encoded log file entry to the active data file.
     ///
-    /// This function does not modify the keydir. That is up to the caller to do.
+    /// This function does not modify the keydir or rotate the active data file. That is up to the
+    /// caller to do, by calling `rotate_if_full` once the keydir has been updated.
     ///
     /// # Arguments
     ///
     /// * `encoded_log_file_entry` - Encoded bytes to write to the active data file.
+    /// * `sync` - Whether to sync the active data file to disk after writing.
     ///
     /// # Returns
     ///
@@ -177,16 +491,22 @@ impl Writer {
     fn write_to_active_data_file(
         &mut self,
         encoded_log_file_entry: Vec<u8>,
+        sync: bool,
     ) -> Result<(LogIndex, GenerationNumber), io::Error> {
-        let file_offset = self.active_data_file.stream_position()?;
-        self.active_data_file.write_all(&encoded_log_file_entry)?;
-        self.active_data_file.flush()?;
-        if self.sync_mode {
+        let active_data_file = self.active_data_file()?;
+        let file_offset = active_data_file.stream_position()?;
+        active_data_file.write_all(&encoded_log_file_entry)?;
+        active_data_file.flush()?;
+        if sync {
             // Force the write to disk.
-            self.active_data_file.get_ref().sync_all()?;
+            active_data_file.get_ref().sync_all()?;
+            self.readers.counters.record_synced_write();
         }
         let len_encoded_data = encoded_log_file_entry.len();
         self.active_data_file_size += len_encoded_data as u64;
+        self.readers
+            .counters
+            .record_bytes_written(len_encoded_data as u64);
 
         trace!(
             "Wrote {} bytes to data file (gen={})",
@@ -196,10 +516,6 @@ impl Writer {
 
         let written_generation = self.active_generation;
 
-        if self.active_data_file_size >= self.max_data_file_size {
-            self.rotate_active_data_file();
-        }
-
         Ok((
             LogIndex {
                 offset: file_offset,
@@ -209,33 +525,155 @@ impl Writer {
         ))
     }
 
-    fn rotate_active_data_file(&mut self) {
-        // TODO [RyanStan 07/22/24]
-        // Errors during rotation should return a "rotation" error so that the caller knows the value was successfully written,
-        // but that the rotation didn't work as expected.
-        self.active_generation += 1;
+    /// Rotates the active data file if it has reached the maximum data file size.
+    fn rotate_if_full(&mut self) -> Result<(), io::Error> {
+        if self.active_data_file_size >= self.max_data_file_size {
+            self.rotate_active_data_file()?;
+        }
+        Ok(())
+    }
+
+    /// Seals the active data file and creates a new active data file for the next generation.
+    ///
+    /// If rotation fails, the current active data file stays active, and rotation is attempted
+    /// again after the next write.
+    fn rotate_active_data_file(&mut self) -> Result<(), io::Error> {
+        self.rotate_active_data_file_to(self.active_generation + 1)
+    }
+
+    fn rotate_active_data_file_to(
+        &mut self,
+        next_generation: GenerationNumber,
+    ) -> Result<(), io::Error> {
+        // Sync the data file being rotated out, so that `sync` only has to sync the active data file.
+        if let Some(active_data_file) = self.active_data_file.as_mut() {
+            active_data_file.flush()?;
+            active_data_file.get_ref().sync_all()?;
+        }
         trace!(
             "Rotating active data file. New generation start: {}",
-            self.active_generation
+            next_generation
         );
 
-        let active_data_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(data_file_path(
-                &self.rustcask_directory,
-                &self.active_generation,
-            ))
-            .expect("Error opening active data file");
-
-        self.active_data_file = BufWriter::new(active_data_file);
+        let (active_data_file, active_data_file_size) =
+            open_active_data_file(&self.rustcask_directory, next_generation)?;
 
-        self.active_data_file_size = 0;
+        self.active_generation = next_generation;
+        self.active_data_file = Some(active_data_file);
+        self.active_data_file_size = active_data_file_size;
         debug!(
             "Rotated active data file. New active generation: {}",
             self.active_generation
         );
+        Ok(())
+    }
+
+    /// Flushes any buffered writes to the active data file to the operating system.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        if let Some(active_data_file) = self.active_data_file.as_mut() {
+            active_data_file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the active data file and syncs it to disk.
+    pub fn sync(&mut self) -> Result<(), io::Error> {
+        self.flush()?;
+        if let Some(active_data_file) = self.active_data_file.as_ref() {
+            active_data_file.get_ref().sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Writes every live entry to `out`, in the data file format.
+    ///
+    /// The active data file is flushed before the data files are scanned, and the caller's
+    /// exclusive access to the writer keeps new entries from being appended during the scan.
+    ///
+    /// Returns the number of entries written.
+    pub fn export<W: Write>(&mut self, mut out: W) -> Result<u64, io::Error> {
+        self.flush()?;
+
+        let mut generations = list_generations(&self.rustcask_directory, self.readers.file_filter)?;
+        generations.sort_unstable();
+
+        let keydir = self.keydir.read().expect(KEYDIR_POISON_ERR);
+        write_data_file_header(&mut out)?;
+        let mut exported: u64 = 0;
+        for generation in generations {
+            let data_file = data_file_path(&self.rustcask_directory, &generation);
+            for (entry, index) in LogFileIterator::new(data_file)?.with_prefetch(self.scan_prefetch)
+            {
+                let is_live = keydir.get(&entry.key).is_some_and(|keydir_entry| {
+                    keydir_entry.data_file_gen == generation && keydir_entry.index == index
+                });
+                if is_live {
+                    out.write_all(&entry.encode().map_err(io::Error::other)?)?;
+                    exported += 1;
+                }
+            }
+        }
+        out.flush()?;
+        Ok(exported)
+    }
+
+    /// Opens an iterator over every data file entry at or after `offset` in generation
+    /// `generation`, in write order. See [`crate::Rustcask::read_log_from`].
+    ///
+    /// The active data file is flushed before the data files are opened, and each iterator
+    /// stops at the length its data file had when it was opened. Entries written afterwards
+    /// aren't returned, and unflushed entries are never mistaken for truncated ones.
+    ///
+    /// The generations are pinned until the returned guard is dropped.
+    pub fn read_log_from(
+        &mut self,
+        generation: GenerationNumber,
+        offset: u64,
+    ) -> Result<(Vec<(GenerationNumber, LogFileIterator)>, PinGuard), io::Error> {
+        self.flush()?;
+
+        let keydir = self.keydir.read().expect(KEYDIR_POISON_ERR);
+        let mut generations = list_generations(&self.rustcask_directory, self.readers.file_filter)?;
+        generations
+            .retain(|gen| *gen >= generation && !keydir.unreadable_generations().contains(gen));
+        generations.sort_unstable();
+
+        let mut iterators = Vec::with_capacity(generations.len());
+        for gen in generations {
+            let mut iterator =
+                LogFileIterator::new(data_file_path(&self.rustcask_directory, &gen))?
+                    .with_prefetch(self.scan_prefetch);
+            if gen == generation {
+                iterator.seek_to(offset)?;
+            }
+            iterators.push((gen, iterator));
+        }
+        // The generations are pinned while the keydir lock is held, so that a merge can't
+        // delete them first.
+        let pins = self.readers.pins.pin(
+            iterators.iter().map(|(gen, _)| *gen).collect(),
+            self.rustcask_directory.clone(),
+        );
+        Ok((iterators, pins))
+    }
+
+    /// Flushes and syncs the active data file, marks it read-only, and rotates to a new
+    /// active data file.
+    ///
+    /// Returns the generation of the data file that was sealed.
+    pub fn flush_and_rotate(&mut self) -> Result<GenerationNumber, io::Error> {
+        let active_data_file = self.active_data_file()?;
+        active_data_file.flush()?;
+        active_data_file.get_ref().sync_all()?;
+
+        let sealed_generation = self.active_generation;
+        let sealed_data_file = data_file_path(&self.rustcask_directory, &sealed_generation);
+        let mut permissions = fs::metadata(&sealed_data_file)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&sealed_data_file, permissions)?;
+
+        self.rotate_active_data_file()?;
+        Ok(sealed_generation)
     }
 
     // TODO [RyanStan 7-8-24] Implement merge window support.
@@ -246,10 +684,13 @@ impl Writer {
     /// Performs a merge operation on the log data files.
     ///
     /// The merge operation combines all the live log entries from the existing data files into a new
-    /// set of data files with an incremented generation number. After the merge is complete, the
-    /// previous generations of data files are deleted.
+    /// set of data files with an incremented generation number. A hint file is written alongside each
+    /// merged data file. After the merge is complete, the previous generations of data files are deleted,
+    /// and new writes go to a fresh active data file.
     ///
-    /// This function will update the keydir.
+    /// This function will update the keydir. Writes are blocked for the whole merge, because
+    /// the writer is borrowed. `Rustcask::merge` uses `start_merge` and `finish_merge` directly,
+    /// so that writes can continue while the live entries are copied.
     ///
     /// # Errors
     ///
@@ -257,95 +698,338 @@ impl Writer {
     /// I/O error or an inconsistency in the data. The `merge_generation` field of the error contains
     /// the generation number of the merge that failed.
     pub fn merge(&mut self) -> Result<(), MergeError> {
-        let mut active_merge_gen: u64 = self.get_active_generation() + 1;
-        let initial_merge_gen = active_merge_gen;
+        let mut progress = self.begin_merge();
+        while !progress.is_done() {
+            let mut merge_job = self.start_merge(&mut progress)?;
+            merge_job.copy_live_entries(&mut self.readers)?;
+            self.finish_merge(merge_job)?;
+        }
+        Ok(())
+    }
 
-        let mut keydir_guard = self.keydir.write().expect(KEYDIR_POISON_ERR);
-        let keydir = &*keydir_guard;
-        let mut new_keydir = KeyDir::new_empty();
-        let mut merge_offset: u64 = 0;
-        let mut file_size: u64 = 0;
+    /// Begins a merge of every generation up to the active generation.
+    ///
+    /// The merge is performed in steps, each started with `start_merge` and completed with
+    /// `finish_merge`, until the returned progress is done. Unless a merge extra bytes limit
+    /// is configured, the first step merges every generation.
+    pub fn begin_merge(&self) -> MergeProgress {
+        MergeProgress {
+            last_generation: self.active_generation,
+            merged_generations: HashSet::new(),
+            kept_tombstones: 0,
+            done: false,
+        }
+    }
 
-        let previous_generations: Vec<GenerationNumber> =
-            list_generations(&self.rustcask_directory).unwrap();
+    /// Starts the next step of a merge, which merges the oldest generations that it hasn't
+    /// merged yet. See `Writer::merge_step_generations`.
+    ///
+    /// New writes are moved to a fresh active generation, and the live entries of the merged
+    /// generations are snapshotted. The merged data files are numbered between the previous
+    /// generations and the fresh active generation, so that values written while the merge is
+    /// running replace the merged values when the store is reopened. The live values of the
+    /// merged generations are the newest values of their keys, so numbering them after the
+    /// generations that later steps merge doesn't change which value is read.
+    ///
+    /// The returned job copies the live entries without holding the writer or keydir locks.
+    /// Once it's done, the step is completed with `finish_merge`.
+    ///
+    /// A merge that replaces every generation drops all tombstones, because there's no older
+    /// generation left for the removed values to reappear from. If some generations are
+    /// unreadable, they're left out of the merge, and the tombstones that may hide their values
+    /// are copied along with the live entries. See `Writer::tombstones_to_keep`.
+    pub fn start_merge(&mut self, progress: &mut MergeProgress) -> Result<MergeJob, MergeError> {
+        let first_merge_gen = self.get_active_generation() + 1;
+        let merge_error = |err| MergeError {
+            kind: MergeErrorKind::Io(err),
+            merge_generation: first_merge_gen,
+        };
 
-        let mut active_merge_data_file = BufWriter::new(
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(data_file_path(&self.rustcask_directory, &active_merge_gen))
-                .unwrap(),
+        // Unreadable data files are left in place, in case they can be recovered. Generations
+        // that an earlier step merged are skipped even if they're still on disk, because they're
+        // retired or pinned by readers.
+        let unreadable_generations = self
+            .keydir
+            .read()
+            .expect(KEYDIR_POISON_ERR)
+            .unreadable_generations()
+            .clone();
+        let mut remaining_generations: Vec<GenerationNumber> =
+            list_generations(&self.rustcask_directory, self.readers.file_filter)
+                .map_err(merge_error)?
+                .into_iter()
+                .filter(|generation| {
+                    *generation <= progress.last_generation
+                        && !unreadable_generations.contains(generation)
+                        && !progress.merged_generations.contains(generation)
+                        && !self.readers.pins.is_pending_delete(*generation)
+                })
+                .collect();
+        remaining_generations.sort_unstable();
+
+        let now = now_millis(self.readers.clock.as_ref());
+        let previous_generations = self
+            .merge_step_generations(&remaining_generations, &unreadable_generations, now)
+            .map_err(merge_error)?;
+        progress
+            .merged_generations
+            .extend(previous_generations.iter().copied());
+        progress.done = previous_generations.len() == remaining_generations.len();
+        debug!(
+            "Merging {} of the {} remaining generations in {}.",
+            previous_generations.len(),
+            remaining_generations.len(),
+            self.rustcask_directory.display()
         );
 
-        for (key, val) in keydir {
-            let reader = self.readers.get_data_file_reader(val.data_file_gen);
-            reader.seek(SeekFrom::Start(val.index.offset)).unwrap();
-            let mut buffer: Vec<u8> = vec![0; val.index.len as usize];
-            let bytes_read = reader.read(&mut buffer).map_err(|err| MergeError {
-                kind: MergeErrorKind::Io(err),
-                merge_generation: initial_merge_gen,
-            })?;
-            assert_eq!(
-                bytes_read, val.index.len as usize,
-                "Error performing merging: bytes read for live entry does not match expected byte count.
-                Aborting merge. However, new data file is still safe to read from."
-            );
-            active_merge_data_file
-                .write_all(&buffer)
-                .map_err(|err| MergeError {
-                    kind: MergeErrorKind::Io(err),
-                    merge_generation: initial_merge_gen,
-                })?;
+        // A later step doesn't leave the previous step's active data file behind as an empty
+        // generation, if nothing was written to it.
+        if self.active_generation > progress.last_generation
+            && self.active_data_file.is_some()
+            && self.active_data_file_size == 0
+        {
+            fs::remove_file(data_file_path(
+                &self.rustcask_directory,
+                &self.active_generation,
+            ))
+            .map_err(merge_error)?;
+            self.active_data_file = None;
+            self.readers.close(self.active_generation);
+        }
 
-            new_keydir.set(
-                key.clone(),
-                active_merge_gen,
-                LogIndex {
-                    offset: merge_offset,
-                    len: bytes_read as u64,
-                },
-            );
+        // Each merged data file holds at least a full data file's worth of live entries, except
+        // for the last one. So the merge never needs more generations than it replaces.
+        let last_merge_gen = first_merge_gen + previous_generations.len().max(1) as u64 - 1;
+        self.rotate_active_data_file_to(last_merge_gen + 1)
+            .map_err(merge_error)?;
+
+        let keydir = self.keydir.read().expect(KEYDIR_POISON_ERR);
+        let merged: HashSet<GenerationNumber> = previous_generations.iter().copied().collect();
+        let mut live_entries =
+            live_merge_entries(&keydir, now, |generation| merged.contains(&generation));
+        let kept_tombstones = self
+            .tombstones_to_keep(&previous_generations, &unreadable_generations, &keydir, now)
+            .map_err(merge_error)?;
+        progress.kept_tombstones += kept_tombstones.len() as u64;
+        // The dead entries are spread over the merged generations, so they're only forgotten
+        // once the last step is done. Kept tombstones are still dead entries after the merge.
+        let dead_entries = if progress.done {
+            keydir
+                .dead_entries()
+                .saturating_sub(progress.kept_tombstones)
+        } else {
+            0
+        };
+        if !kept_tombstones.is_empty() {
+            live_entries.extend(kept_tombstones);
+            live_entries.sort_unstable_by_key(|entry| (entry.data_file_gen, entry.index.offset));
+        }
+        Ok(MergeJob {
+            output_directory: self.rustcask_directory.clone(),
+            max_data_file_size: self.max_data_file_size,
+            merge_threads: self.merge_threads.max(1),
+            hint_compression: self.hint_compression,
+            first_merge_gen,
+            last_merge_gen,
+            previous_generations,
+            dead_entries,
+            live_entries,
+            merged_indexes: Vec::new(),
+            bytes_written: 0,
+            seal_last_data_file: true,
+        })
+    }
 
-            merge_offset += bytes_read as u64;
-            file_size += bytes_read as u64;
+    /// Returns the oldest of `generations`, which must be sorted, whose merged data and hint
+    /// files fit within the merge extra bytes limit, along with the header of the fresh active
+    /// data file. At least one generation is returned, so that the merge makes progress.
+    ///
+    /// The size of a generation's merged data file is estimated from the keydir entries that
+    /// point into it. Hint files are counted as if they weren't compressed. The tombstones kept
+    /// for unreadable generations aren't in the keydir, so the whole data file is counted for
+    /// generations that may keep some.
+    fn merge_step_generations(
+        &self,
+        generations: &[GenerationNumber],
+        unreadable_generations: &HashSet<GenerationNumber>,
+        now: u64,
+    ) -> Result<Vec<GenerationNumber>, io::Error> {
+        let Some(max_extra_bytes) = self.merge_max_extra_bytes else {
+            return Ok(generations.to_vec());
+        };
 
-            // Rotate the active data file if it exceeded the size threshold
-            if file_size > self.max_data_file_size {
-                active_merge_data_file.flush().map_err(|err| MergeError {
-                    kind: MergeErrorKind::Io(err),
-                    merge_generation: initial_merge_gen,
-                })?;
+        // The size of each generation's merged data file and hint file.
+        let mut merged_sizes: HashMap<GenerationNumber, (u64, u64)> = generations
+            .iter()
+            .map(|generation| (*generation, (DATA_FILE_HEADER_LEN, HINT_FILE_HEADER_LEN)))
+            .collect();
+        let keydir = self.keydir.read().expect(KEYDIR_POISON_ERR);
+        for (key, entry) in &*keydir {
+            if entry.is_expired(now) {
+                continue;
+            }
+            if let Some((data_len, hint_len)) = merged_sizes.get_mut(&entry.data_file_gen) {
+                *data_len += entry.index.len;
+                // The merged offset is at most the entry's current offset.
+                *hint_len +=
+                    hint_entry_len(key.len(), &entry.index, entry.value_len, entry.expires_at);
+            }
+        }
+        drop(keydir);
+        if let Some(&oldest_unreadable) = unreadable_generations.iter().min() {
+            for generation in generations {
+                if *generation > oldest_unreadable {
+                    let data_file = data_file_path(&self.rustcask_directory, generation);
+                    merged_sizes.entry(*generation).or_default().0 = fs::metadata(data_file)?.len();
+                }
+            }
+        }
 
-                self.rotate_merge_data_file(
-                    &mut active_merge_gen,
-                    &mut active_merge_data_file,
-                    &mut file_size,
-                    &mut merge_offset,
-                )
-                .map_err(|err| MergeError {
-                    kind: MergeErrorKind::Io(err),
-                    merge_generation: initial_merge_gen,
-                })?;
+        let mut step_bytes = DATA_FILE_HEADER_LEN;
+        let mut step_generations = Vec::new();
+        for generation in generations {
+            let (data_len, hint_len) = merged_sizes[generation];
+            step_bytes += data_len + hint_len;
+            if step_bytes > max_extra_bytes && !step_generations.is_empty() {
+                break;
             }
+            step_generations.push(*generation);
         }
+        Ok(step_generations)
+    }
 
-        active_merge_data_file.flush().map_err(|err| MergeError {
-            kind: MergeErrorKind::Io(err),
-            merge_generation: initial_merge_gen,
-        })?;
+    /// Returns the tombstones in the merged generations that must survive the merge: the newest
+    /// tombstone of each removed key that's newer than an unreadable generation. Such a tombstone
+    /// may hide a value in the unreadable generation, which would reappear once the generation is
+    /// readable again if the tombstone were dropped.
+    ///
+    /// Tombstones of keys that have a live value are dropped, because the merged value
+    /// replaces any older value. Nothing is read if every generation is readable.
+    fn tombstones_to_keep(
+        &self,
+        generations: &[GenerationNumber],
+        unreadable_generations: &HashSet<GenerationNumber>,
+        keydir: &KeyDir,
+        now: u64,
+    ) -> Result<Vec<MergeEntry>, io::Error> {
+        let Some(&oldest_unreadable) = unreadable_generations.iter().min() else {
+            return Ok(Vec::new());
+        };
+        let mut generations: Vec<GenerationNumber> = generations
+            .iter()
+            .copied()
+            .filter(|generation| *generation > oldest_unreadable)
+            .collect();
+        generations.sort_unstable();
 
-        self.active_generation = active_merge_gen;
-        *keydir_guard = new_keydir;
+        let mut tombstones: HashMap<Vec<u8>, MergeEntry> = HashMap::new();
+        for generation in generations {
+            let data_file = data_file_path(&self.rustcask_directory, &generation);
+            for (entry, index) in LogFileIterator::new(data_file)?.with_prefetch(self.scan_prefetch)
+            {
+                let is_live = keydir
+                    .get(&entry.key)
+                    .is_some_and(|keydir_entry| !keydir_entry.is_expired(now));
+                if entry.value.is_some() || is_live {
+                    continue;
+                }
+                tombstones.insert(
+                    entry.key.clone(),
+                    MergeEntry {
+                        key: entry.key,
+                        data_file_gen: generation,
+                        index,
+                        value_len: 0,
+                        expires_at: None,
+                        tombstone: true,
+                    },
+                );
+            }
+        }
+        Ok(tombstones.into_values().collect())
+    }
+
+    /// Starts copying the live entries into an empty directory, as the data files of a new store.
+    /// See `Rustcask::fork`.
+    ///
+    /// The live entries are snapshotted, and the generations they're stored in are pinned until
+    /// the returned guard is dropped. A pinned generation isn't overwritten in place or deleted
+    /// by a merge, so the copy reflects the store as it was when the fork started.
+    pub fn start_fork(&self, fork_directory: Arc<PathBuf>) -> (MergeJob, PinGuard) {
+        let now = now_millis(self.readers.clock.as_ref());
+        let keydir = self.keydir.read().expect(KEYDIR_POISON_ERR);
+        let live_entries = live_merge_entries(&keydir, now, |_| true);
+        let generations = live_entries
+            .iter()
+            .map(|entry| entry.data_file_gen)
+            .collect();
+        let pins = self
+            .readers
+            .pins
+            .pin(generations, self.rustcask_directory.clone());
+        drop(keydir);
+
+        let fork_job = MergeJob {
+            output_directory: fork_directory,
+            max_data_file_size: self.max_data_file_size,
+            merge_threads: self.merge_threads.max(1),
+            hint_compression: self.hint_compression,
+            first_merge_gen: 0,
+            last_merge_gen: GenerationNumber::MAX,
+            previous_generations: Vec::new(),
+            dead_entries: 0,
+            live_entries,
+            merged_indexes: Vec::new(),
+            bytes_written: 0,
+            seal_last_data_file: false,
+        };
+        (fork_job, pins)
+    }
+
+    /// Completes a merge whose live entries were copied, by pointing the keydir at the merged
+    /// data files and deleting the previous generations. If a merge delete delay is configured,
+    /// the previous generations are retired instead, and deleted once the delay elapses.
+    ///
+    /// Keys that were overwritten or removed while the merge was running keep their new values.
+    pub fn finish_merge(&mut self, merge_job: MergeJob) -> Result<(), MergeError> {
+        let mut keydir = self.keydir.write().expect(KEYDIR_POISON_ERR);
+        for (entry, (merge_gen, merge_index)) in
+            merge_job.live_entries.iter().zip(merge_job.merged_indexes)
+        {
+            if entry.tombstone {
+                continue;
+            }
+            keydir.relocate(
+                &entry.key,
+                entry.data_file_gen,
+                &entry.index,
+                merge_gen,
+                merge_index,
+            );
+        }
+        // The keys left in the previous generations had expired, so they weren't copied.
+        keydir.remove_generations(&merge_job.previous_generations.iter().copied().collect());
+        keydir.forget_dead_entries(merge_job.dead_entries);
 
+        // The keydir lock is held while deleting, so that reads can't pin a previous generation
+        // after it's deleted.
         // TODO [RyanStan 07/29/24] Failures here should return a message that indicates to the user
         // that merge failed during removal of generations.
-        self.delete_generations(previous_generations)
-            .map_err(|err| MergeError {
-                kind: MergeErrorKind::Io(err),
-                merge_generation: initial_merge_gen,
-            })?;
+        let replaced = if self.merge_delete_delay.is_zero() {
+            self.delete_generations(merge_job.previous_generations)
+        } else {
+            self.retire_generations(merge_job.previous_generations)
+        };
+        replaced.map_err(|err| MergeError {
+            kind: MergeErrorKind::Io(err),
+            merge_generation: merge_job.first_merge_gen,
+        })?;
+        drop(keydir);
 
+        self.readers
+            .counters
+            .record_merge_bytes_written(merge_job.bytes_written);
+        self.readers.counters.record_merge();
         Ok(())
     }
 
@@ -378,10 +1062,32 @@ impl Writer {
     ///
     /// This function will panic if another thread crashed while holding the lock on the key directory.
     pub fn remove(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>, RemoveError> {
+        let previous_value = self.remove_key(key.clone())?;
+        self.readers.counters.record_remove();
+        self.rotate_if_full().map_err(|err| RemoveError {
+            kind: RemoveErrorKind::Rotation(err),
+            key,
+        })?;
+        self.maybe_auto_merge();
+        Ok(previous_value)
+    }
+
+    fn remove_key(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>, RemoveError> {
         let tombstone = LogFileEntry::create_tombstone_entry(key);
-        let encoded_tombstone =
-            bincode::serialize(&tombstone).expect("Could not serialize tombstone");
-        self.write_to_active_data_file(encoded_tombstone).unwrap();
+        let encoded_tombstone = tombstone.encode().expect("Could not serialize tombstone");
+        if let Some(write_rate_limiter) = &self.write_rate_limiter {
+            write_rate_limiter
+                .acquire(encoded_tombstone.len() as u64)
+                .map_err(|RateLimited| RemoveError {
+                    kind: RemoveErrorKind::RateLimited,
+                    key: tombstone.key.clone(),
+                })?;
+        }
+        self.write_to_active_data_file(encoded_tombstone, self.sync_mode)
+            .map_err(|err| RemoveError {
+                kind: RemoveErrorKind::Io(err),
+                key: tombstone.key.clone(),
+            })?;
 
         match self
             .keydir
@@ -391,6 +1097,17 @@ impl Writer {
         {
             // The key was not previously in the map
             None => Ok(None),
+            // The key's value had already expired, so there's no value to return.
+            Some(keydir_entry)
+                if keydir_entry.is_expired(now_millis(self.readers.clock.as_ref())) =>
+            {
+                Ok(None)
+            }
+            // The value was inlined, so it doesn't have to be read from the data file.
+            Some(KeyDirEntry {
+                inline_value: Some(inline_value),
+                ..
+            }) => Ok(Some(inline_value.into_vec())),
             // The key was previously in the map, so we retrieve the overwritten value and return it.
             Some(keydir_entry) => {
                 let reader = self
@@ -405,67 +1122,585 @@ impl Writer {
                         key: tombstone.key.clone(),
                     })?;
 
-                let data_file_entry: LogFileEntry =
-                    bincode::deserialize_from(reader).map_err(|err| RemoveError {
+                let data_file_entry =
+                    LogFileEntry::decode_from(reader).map_err(|err| RemoveError {
                         kind: RemoveErrorKind::Deserialize(err),
                         key: tombstone.key.clone(),
                     })?;
 
-                Ok(Some(data_file_entry.value.expect(
+                let value = data_file_entry.value.expect(
                     "We returned a tombstone value from get. We should have instead returned None. 
                     The data store may not be corrupted - this indicates a programming bug.",
-                )))
+                );
+                let value = self
+                    .readers
+                    .value_transforms
+                    .reverse(&data_file_entry.transforms, &value)
+                    .map_err(|err| RemoveError {
+                        kind: RemoveErrorKind::Io(err),
+                        key: tombstone.key.clone(),
+                    })?;
+                Ok(Some(value.into_owned()))
+            }
+        }
+    }
+
+    /// Merges the data files if auto-merge is enabled and the ratio of dead entries
+    /// to live keys has crossed the configured threshold.
+    ///
+    /// This check is cheap because it relies on the dead entry count maintained by the keydir,
+    /// and it's only performed once every `auto_merge_check_interval` writes.
+    /// Once a merge has been triggered, another won't be triggered until the ratio has fallen
+    /// well below the threshold, so that a failing merge isn't retried on every write.
+    fn maybe_auto_merge(&mut self) {
+        let Some(auto_merge_ratio) = self.auto_merge_ratio else {
+            return;
+        };
+        self.writes_since_auto_merge_check += 1;
+        if self.writes_since_auto_merge_check < self.auto_merge_check_interval {
+            return;
+        }
+        self.writes_since_auto_merge_check = 0;
+        let dead_ratio = self.keydir.read().expect(KEYDIR_POISON_ERR).dead_ratio();
+
+        if !self.auto_merge_armed {
+            if dead_ratio < auto_merge_ratio * AUTO_MERGE_REARM_FACTOR {
+                self.auto_merge_armed = true;
+            }
+            return;
+        }
+
+        if dead_ratio < auto_merge_ratio || !self.can_merge() {
+            return;
+        }
+        // Skip the automatic merge if a merge is already running.
+        let merge_lock = self.merge_lock.clone();
+        let Ok(_merging) = merge_lock.try_lock() else {
+            return;
+        };
+
+        self.auto_merge_armed = false;
+        info!(
+            "Dead entry ratio {:.2} crossed the auto-merge ratio {:.2}. Merging data files.",
+            dead_ratio, auto_merge_ratio
+        );
+        match self.merge() {
+            Ok(()) => self.auto_merge_armed = true,
+            Err(err) => error!("Automatic merge failed: {}", err),
+        }
+    }
+
+    /// Deletes the data and hint files of a generation that no longer holds any live data.
+    ///
+    /// A generation can be dropped if no keydir entries point into it, and if none of its
+    /// tombstones are still needed to hide a value in an older generation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the generation is the active generation, doesn't exist,
+    /// or still holds live data.
+    pub fn drop_generation(&mut self, generation: GenerationNumber) -> Result<(), io::Error> {
+        if generation == self.active_generation {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("generation {} is the active generation", generation),
+            ));
+        }
+
+        let data_file = data_file_path(&self.rustcask_directory, &generation);
+        if !data_file.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("generation {} does not exist", generation),
+            ));
+        }
+
+        let live_keys = (&*self.keydir.read().expect(KEYDIR_POISON_ERR))
+            .into_iter()
+            .filter(|(_, entry)| entry.data_file_gen == generation)
+            .count();
+        if live_keys > 0 {
+            return Err(io::Error::other(format!(
+                "generation {} still holds {} live keys",
+                generation, live_keys
+            )));
+        }
+
+        let mut dead_entries: u64 = 0;
+        let mut tombstoned_keys: HashSet<Vec<u8>> = HashSet::new();
+        for (entry, _) in LogFileIterator::new(data_file)?.with_prefetch(self.scan_prefetch) {
+            dead_entries += 1;
+            if entry.value.is_none() {
+                tombstoned_keys.insert(entry.key);
+            }
+        }
+        if !tombstoned_keys.is_empty() {
+            for older_generation in
+                list_generations(&self.rustcask_directory, self.readers.file_filter)?
+            {
+                if older_generation >= generation {
+                    continue;
+                }
+                let older_data_file = data_file_path(&self.rustcask_directory, &older_generation);
+                let hides_older_value = LogFileIterator::new(older_data_file)?
+                    .with_prefetch(self.scan_prefetch)
+                    .any(|(entry, _)| {
+                        entry.value.is_some() && tombstoned_keys.contains(&entry.key)
+                    });
+                if hides_older_value {
+                    return Err(io::Error::other(format!(
+                        "generation {} holds tombstones for values in generation {}",
+                        generation, older_generation
+                    )));
+                }
+            }
+        }
+
+        self.readers.close(generation);
+        self.delete_generations(vec![generation])?;
+        self.keydir
+            .write()
+            .expect(KEYDIR_POISON_ERR)
+            .forget_dead_entries(dead_entries);
+        info!("Dropped generation {}.", generation);
+        Ok(())
+    }
+
+    /// Brings the keydir back in line with the data files on disk, after data files were
+    /// deleted while the store was open.
+    ///
+    /// Keys whose values were stored in a missing data file are removed from the keydir,
+    /// and the readers for missing data files are closed. If the active data file is missing,
+    /// writes move on to a new active data file for the next generation. The missing generation
+    /// isn't recreated, because other handles may still have the deleted file open, and would
+    /// read stale values from it at the offsets of new ones.
+    ///
+    /// Returns the number of keys that were removed.
+    pub fn reconcile(&mut self) -> Result<usize, io::Error> {
+        let on_disk: HashSet<GenerationNumber> =
+            list_generations(&self.rustcask_directory, self.readers.file_filter)?
+                .into_iter()
+                .collect();
+
+        let mut keydir = self.keydir.write().expect(KEYDIR_POISON_ERR);
+        let mut missing: HashSet<GenerationNumber> = (&*keydir)
+            .into_iter()
+            .map(|(_, entry)| entry.data_file_gen)
+            .filter(|generation| !on_disk.contains(generation))
+            .collect();
+        if self.active_data_file.is_some() && !on_disk.contains(&self.active_generation) {
+            missing.insert(self.active_generation);
+        }
+        if missing.is_empty() {
+            return Ok(0);
+        }
+
+        let removed_keys = keydir.remove_generations(&missing);
+        drop(keydir);
+        self.readers.close_missing();
+        // Let the other handles close their readers of the missing data files too.
+        self.readers.pins.record_deletion();
+
+        if missing.contains(&self.active_generation) {
+            // Buffered writes to the missing data file are lost with it.
+            self.active_data_file = None;
+            self.rotate_active_data_file()?;
+        }
+
+        warn!(
+            "Data files for generations {:?} are missing. Removed {} keys whose values were stored in them.",
+            missing, removed_keys
+        );
+        Ok(removed_keys)
+    }
+
+    /// Returns the active data file, creating it if it was created lazily and
+    /// hasn't been written to yet.
+    fn active_data_file(&mut self) -> Result<&mut BufWriter<File>, io::Error> {
+        match self.active_data_file {
+            Some(ref mut active_data_file) => Ok(active_data_file),
+            None => {
+                let (active_data_file, active_data_file_size) =
+                    open_active_data_file(&self.rustcask_directory, self.active_generation)?;
+                self.active_data_file_size = active_data_file_size;
+                debug!(
+                    "Created active data file for generation {}.",
+                    self.active_generation
+                );
+                Ok(self.active_data_file.insert(active_data_file))
             }
         }
     }
 
+    /// Returns whether the active data file has been created.
+    pub fn has_active_data_file(&self) -> bool {
+        self.active_data_file.is_some()
+    }
+
     pub fn get_active_generation(&self) -> GenerationNumber {
         self.active_generation
     }
 
+    #[cfg(test)]
     pub fn get_active_data_file_size(&self) -> u64 {
         self.active_data_file_size
     }
 
+    /// Deletes the files of the given generations. Generations that are pinned by readers
+    /// are deleted once they're unpinned.
     fn delete_generations(&self, previous_generations: Vec<u64>) -> Result<(), io::Error> {
         for generation in previous_generations {
-            debug!(
-                "Merge: deleting {}.",
-                data_file_path(&self.rustcask_directory, &generation)
-                    .to_string_lossy()
-                    .to_string()
-            );
-            fs::remove_file(data_file_path(&self.rustcask_directory, &generation))?;
+            self.readers
+                .pins
+                .delete_generation(&self.rustcask_directory, generation)?;
         }
         Ok(())
     }
 
-    fn rotate_merge_data_file(
-        &self,
-        active_merge_gen: &mut u64,
-        active_merge_data_file: &mut BufWriter<File>,
-        file_size: &mut u64,
-        active_merge_offset: &mut u64,
-    ) -> Result<(), io::Error> {
-        *active_merge_gen += 1;
-        *active_merge_data_file = BufWriter::new(
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(data_file_path(&self.rustcask_directory, &*active_merge_gen))?,
-        );
-        *file_size = 0;
-        *active_merge_offset = 0;
+    fn retire_generations(&self, previous_generations: Vec<u64>) -> Result<(), io::Error> {
+        for generation in previous_generations {
+            self.readers.pins.retire_generation(
+                self.rustcask_directory.clone(),
+                generation,
+                self.merge_delete_delay,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The progress of a merge that has been begun with `Writer::begin_merge`.
+///
+/// A merge runs in steps that each merge a few of the oldest generations, so that the merged
+/// data files don't need more disk space than the merge extra bytes limit.
+/// See `RustcaskBuilder::set_merge_max_extra_bytes`.
+#[derive(Debug)]
+pub struct MergeProgress {
+    // The newest generation that the merge replaces. Newer generations hold merged data files,
+    // or values written while the merge was running.
+    last_generation: GenerationNumber,
+
+    // The generations that the steps so far have merged.
+    merged_generations: HashSet<GenerationNumber>,
+
+    // The number of tombstones that the steps so far have kept.
+    kept_tombstones: u64,
+
+    // Whether every generation up to `last_generation` has been merged.
+    done: bool,
+}
+
+impl MergeProgress {
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+/// A merge that has been started with `Writer::start_merge`, or a fork that has been started
+/// with `Writer::start_fork`.
+///
+/// It holds a snapshot of the live entries, so that they can be copied to the merged data files
+/// while new writes go to the active data file.
+#[derive(Debug)]
+pub struct MergeJob {
+    // The directory the merged data files are written to.
+    output_directory: Arc<PathBuf>,
+    max_data_file_size: u64,
+    merge_threads: usize,
+    hint_compression: bool,
+
+    // The merged data files are written to the generations in this range.
+    first_merge_gen: GenerationNumber,
+    last_merge_gen: GenerationNumber,
+
+    // The generations that are replaced by the merged data files.
+    previous_generations: Vec<GenerationNumber>,
+
+    // The number of dead entries in the previous generations.
+    dead_entries: u64,
+
+    live_entries: Vec<MergeEntry>,
+
+    // Where each live entry was copied to, in the same order as `live_entries`.
+    merged_indexes: Vec<(GenerationNumber, LogIndex)>,
+
+    // The number of bytes of live entries copied so far.
+    bytes_written: u64,
+
+    // Whether the last merged data file is given a hint file, or deleted if it's empty.
+    // A fork's last data file is left as is, because it becomes the fork's active data file,
+    // and a hint file would go out of date as the fork is written to.
+    seal_last_data_file: bool,
+}
+
+/// The location of a live entry when its merge started.
+#[derive(Debug)]
+struct MergeEntry {
+    key: Vec<u8>,
+    data_file_gen: GenerationNumber,
+    index: LogIndex,
+    value_len: u64,
+    expires_at: Option<u64>,
+
+    // Whether the entry is a tombstone that's kept by the merge, rather than a live value.
+    tombstone: bool,
+}
+
+impl MergeJob {
+    /// Copies the live entries to the merged data files, writing a hint file alongside each one.
+    /// A merged data file that holds kept tombstones gets no hint file, because hint files can't
+    /// hold tombstones. It's read in full when the store is opened instead.
+    ///
+    /// `readers` is used to read the live entries. It must belong to the same store.
+    pub fn copy_live_entries(&mut self, readers: &mut Readers) -> Result<(), MergeError> {
+        let merge_error = |err| MergeError {
+            kind: MergeErrorKind::Io(err),
+            merge_generation: self.first_merge_gen,
+        };
+
+        let mut active_merge_gen = self.first_merge_gen;
+        let mut active_merge_data_file =
+            create_merge_data_file(&self.output_directory, active_merge_gen)
+                .map_err(merge_error)?;
+        let mut hint_entries: Vec<HintFileEntry> = Vec::new();
+        let mut holds_tombstones = false;
+        let mut merge_offset: u64 = DATA_FILE_HEADER_LEN;
+        let mut file_size: u64 = 0;
+
+        let mut worker_readers: Vec<Readers> =
+            (1..self.merge_threads).map(|_| readers.clone()).collect();
+        let mut merged_indexes = Vec::with_capacity(self.live_entries.len());
+
+        let mut buffers = vec![Vec::new(); self.merge_threads];
+
+        for batch in self
+            .live_entries
+            .chunks(MERGE_BATCH_ENTRIES_PER_THREAD * self.merge_threads)
+        {
+            let chunk_size = read_merge_batch(readers, &mut worker_readers, batch, &mut buffers)
+                .map_err(merge_error)?;
+            let entries = batch
+                .chunks(chunk_size)
+                .zip(&buffers)
+                .flat_map(|(chunk, buffer)| split_merge_chunk(chunk, buffer));
+
+            for (entry, bytes) in entries {
+                let bytes_read = bytes.len() as u64;
+                active_merge_data_file
+                    .write_all(bytes)
+                    .map_err(merge_error)?;
+
+                let log_index = LogIndex {
+                    offset: merge_offset,
+                    len: bytes_read,
+                };
+                if entry.tombstone {
+                    holds_tombstones = true;
+                } else {
+                    hint_entries.push(HintFileEntry {
+                        key: entry.key.clone(),
+                        index: log_index.clone(),
+                        value_len: entry.value_len,
+                        expires_at: entry.expires_at,
+                    });
+                }
+                merged_indexes.push((active_merge_gen, log_index));
+
+                merge_offset += bytes_read;
+                file_size += bytes_read;
+                self.bytes_written += bytes_read;
+
+                // Rotate the merged data file if it exceeded the size threshold. The last
+                // generation reserved for the merge takes the remaining entries.
+                if file_size > self.max_data_file_size && active_merge_gen < self.last_merge_gen {
+                    active_merge_data_file.flush().map_err(merge_error)?;
+                    if !holds_tombstones {
+                        write_hint_file(
+                            &hint_file_path(&self.output_directory, active_merge_gen),
+                            &hint_entries,
+                            self.hint_compression,
+                        )
+                        .map_err(merge_error)?;
+                    }
+                    hint_entries.clear();
+                    holds_tombstones = false;
+
+                    active_merge_gen += 1;
+                    active_merge_data_file =
+                        create_merge_data_file(&self.output_directory, active_merge_gen)
+                            .map_err(merge_error)?;
+                    file_size = 0;
+                    merge_offset = DATA_FILE_HEADER_LEN;
+                }
+            }
+        }
+
+        active_merge_data_file.flush().map_err(merge_error)?;
+        drop(active_merge_data_file);
+        if self.seal_last_data_file {
+            if file_size == 0 {
+                fs::remove_file(data_file_path(&self.output_directory, &active_merge_gen))
+                    .map_err(merge_error)?;
+            } else if !holds_tombstones {
+                write_hint_file(
+                    &hint_file_path(&self.output_directory, active_merge_gen),
+                    &hint_entries,
+                    self.hint_compression,
+                )
+                .map_err(merge_error)?;
+            }
+        }
+
+        self.merged_indexes = merged_indexes;
         Ok(())
     }
 }
 
+/// Returns the live entries in the keydir that are stored in the included generations,
+/// in the order they appear in the data files.
+/// Copying them in this order keeps the merge output deterministic, no matter how many threads
+/// read the entries. Expired values are dropped.
+fn live_merge_entries(
+    keydir: &KeyDir,
+    now: u64,
+    include_generation: impl Fn(GenerationNumber) -> bool,
+) -> Vec<MergeEntry> {
+    let mut live_entries: Vec<MergeEntry> = keydir
+        .into_iter()
+        .filter(|(_, val)| include_generation(val.data_file_gen) && !val.is_expired(now))
+        .map(|(key, val)| MergeEntry {
+            key: key.clone(),
+            data_file_gen: val.data_file_gen,
+            index: val.index.clone(),
+            value_len: val.value_len,
+            expires_at: val.expires_at,
+            tombstone: false,
+        })
+        .collect();
+    live_entries.sort_unstable_by_key(|entry| (entry.data_file_gen, entry.index.offset));
+    live_entries
+}
+
+/// Reads the serialized entries of a batch of live keys into `buffers`.
+///
+/// The batch is split into contiguous chunks, and each chunk's entries are read into its own
+/// buffer, back to back and in order. The first chunk is read by the calling thread using
+/// `readers`, and each remaining chunk is read by its own thread using one of `worker_readers`.
+/// There must be a buffer for every reader. The buffers are reused across batches, and only
+/// grow, so that merging many small entries doesn't allocate a buffer for each entry.
+///
+/// Returns the size of the chunks.
+fn read_merge_batch(
+    readers: &mut Readers,
+    worker_readers: &mut [Readers],
+    batch: &[MergeEntry],
+    buffers: &mut [Vec<u8>],
+) -> Result<usize, io::Error> {
+    let chunk_size = batch.len().div_ceil(worker_readers.len() + 1).max(1);
+    let mut chunks = batch.chunks(chunk_size);
+    let first_chunk = chunks.next().unwrap_or_default();
+    let (first_buffer, worker_buffers) = buffers
+        .split_first_mut()
+        .expect("There's a merge buffer for every reader");
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = worker_readers
+            .iter_mut()
+            .zip(worker_buffers)
+            .zip(chunks)
+            .map(|((worker_readers, buffer), chunk)| {
+                scope.spawn(move || read_merge_chunk(worker_readers, chunk, buffer))
+            })
+            .collect();
+
+        read_merge_chunk(readers, first_chunk, first_buffer)?;
+        for worker in workers {
+            worker.join().expect("A merge read thread panicked.")?;
+        }
+        Ok(chunk_size)
+    })
+}
+
+fn read_merge_chunk(
+    readers: &mut Readers,
+    chunk: &[MergeEntry],
+    buffer: &mut Vec<u8>,
+) -> Result<(), io::Error> {
+    let chunk_len: u64 = chunk.iter().map(|entry| entry.index.len).sum();
+    buffer.clear();
+    buffer.resize(chunk_len as usize, 0);
+
+    let mut start = 0;
+    for entry in chunk {
+        let end = start + entry.index.len as usize;
+        let reader = readers.get_data_file_reader(entry.data_file_gen);
+        reader.seek(SeekFrom::Start(entry.index.offset))?;
+        reader.read_exact(&mut buffer[start..end])?;
+        start = end;
+    }
+    Ok(())
+}
+
+/// Pairs each entry of a chunk with its serialized bytes, which `read_merge_chunk` read
+/// back to back into `buffer`.
+fn split_merge_chunk<'a>(
+    chunk: &'a [MergeEntry],
+    buffer: &'a [u8],
+) -> impl Iterator<Item = (&'a MergeEntry, &'a [u8])> {
+    chunk.iter().scan(0, move |start, entry| {
+        let end = *start + entry.index.len as usize;
+        let bytes = &buffer[*start..end];
+        *start = end;
+        Some((entry, bytes))
+    })
+}
+
+/// Opens the data file for a generation so that it can be appended to.
+/// The data file header is written if the data file is new.
+///
+/// Returns the buffered writer, positioned at the end of the file, along with the size of the
+/// entries in the file. The size doesn't include the header.
+fn open_active_data_file(
+    rustcask_directory: &Path,
+    generation: GenerationNumber,
+) -> Result<(BufWriter<File>, u64), io::Error> {
+    let mut active_data_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(data_file_path(rustcask_directory, &generation))?;
+    let mut file_len = active_data_file.seek(SeekFrom::End(0))?;
+    if file_len == 0 {
+        write_data_file_header(&mut active_data_file)?;
+        file_len = DATA_FILE_HEADER_LEN;
+    }
+    let active_data_file_size = file_len.saturating_sub(DATA_FILE_HEADER_LEN);
+    Ok((BufWriter::new(active_data_file), active_data_file_size))
+}
+
+/// Creates a data file for a merge generation, replacing any existing file, and writes its header.
+fn create_merge_data_file(
+    rustcask_directory: &Path,
+    generation: GenerationNumber,
+) -> Result<BufWriter<File>, io::Error> {
+    let mut merge_data_file = BufWriter::new(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(data_file_path(rustcask_directory, &generation))?,
+    );
+    write_data_file_header(&mut merge_data_file)?;
+    Ok(merge_data_file)
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
         io::Write,
-        path::PathBuf,
+        path::Path,
         sync::{Arc, RwLock},
     };
 
@@ -473,11 +1708,13 @@ mod tests {
 
     use crate::{
         keydir::KeyDir,
+        logfile::DATA_FILE_HEADER_LEN,
         readers::Readers,
         utils::{
             data_file_path,
             tests::{file_names, get_keys_values},
         },
+        RustcaskBuilder,
     };
 
     use super::Writer;
@@ -511,11 +1748,16 @@ mod tests {
 
         let mut writer = create_test_writer(&rustcask_directory, keydir);
 
-        writer.active_data_file.write_all(b"test data").unwrap();
+        writer
+            .active_data_file
+            .as_mut()
+            .unwrap()
+            .write_all(b"test data")
+            .unwrap();
 
         let initial_generation = writer.active_generation;
 
-        writer.rotate_active_data_file();
+        writer.rotate_active_data_file().unwrap();
 
         assert_eq!(writer.active_generation, initial_generation + 1);
 
@@ -539,12 +1781,13 @@ mod tests {
 
         let test_bytes: Vec<u8> = "test".to_string().into_bytes();
         let (log_index, generation) = writer
-            .write_to_active_data_file(test_bytes.clone())
+            .write_to_active_data_file(test_bytes.clone(), false)
             .unwrap();
+        writer.rotate_if_full().unwrap();
 
         assert_eq!(writer.active_generation, initial_generation + 1);
         assert_eq!(generation, initial_generation); // The bytes should have been written to the original generation data file.
-        assert_eq!(log_index.offset, 0);
+        assert_eq!(log_index.offset, DATA_FILE_HEADER_LEN);
         assert_eq!(log_index.len, test_bytes.len().try_into().unwrap());
     }
 
@@ -560,39 +1803,72 @@ mod tests {
 
         let test_bytes: Vec<u8> = "test".to_string().into_bytes();
         let (mut log_index, mut generation) = writer
-            .write_to_active_data_file(test_bytes.clone())
+            .write_to_active_data_file(test_bytes.clone(), false)
             .unwrap();
 
         assert_eq!(writer.active_generation, initial_generation);
         assert_eq!(generation, initial_generation);
-        assert_eq!(log_index.offset, 0);
+        assert_eq!(log_index.offset, DATA_FILE_HEADER_LEN);
         assert_eq!(log_index.len, test_bytes.len().try_into().unwrap());
 
         let more_test_bytes = "more-test-bytes".to_string().into_bytes();
         (log_index, generation) = writer
-            .write_to_active_data_file(more_test_bytes.clone())
+            .write_to_active_data_file(more_test_bytes.clone(), false)
             .unwrap();
-        assert_eq!(log_index.offset, test_bytes.len() as u64);
+        assert_eq!(
+            log_index.offset,
+            DATA_FILE_HEADER_LEN + test_bytes.len() as u64
+        );
         assert_eq!(log_index.len, more_test_bytes.len().try_into().unwrap());
         assert_eq!(writer.active_generation, initial_generation);
         assert_eq!(generation, initial_generation);
     }
 
-    fn create_test_writer(rustcask_dir: &PathBuf, keydir: KeyDir) -> Writer {
+    #[test]
+    fn test_auto_merge_not_retried_while_disarmed() {
+        let temp_dir = TempDir::new().unwrap();
+        let rustcask_directory = temp_dir.path().to_path_buf();
+        let mut writer = create_test_writer(&rustcask_directory, KeyDir::new_empty());
+        for value in ["a", "b", "c"] {
+            writer
+                .set("key".as_bytes().to_vec(), value.as_bytes().to_vec())
+                .unwrap();
+        }
+
+        // Simulate the state after an automatic merge failed.
+        writer.auto_merge_ratio = Some(1.0);
+        writer.auto_merge_armed = false;
+
+        writer
+            .set("key".as_bytes().to_vec(), "d".as_bytes().to_vec())
+            .unwrap();
+        assert_eq!(writer.active_generation, 0);
+        assert!(!writer.auto_merge_armed);
+
+        // A manual merge brings the ratio back down, which re-arms auto-merge.
+        writer.merge().unwrap();
+        writer
+            .set("other-key".as_bytes().to_vec(), "value".as_bytes().to_vec())
+            .unwrap();
+        assert!(writer.auto_merge_armed);
+    }
+
+    fn create_test_writer(rustcask_dir: &Path, keydir: KeyDir) -> Writer {
         // TODO [RyanStan 08/13/24] In the future, we may want to create mock keydir and readers.
         // Then, this function should take a keydir and reader as input.
         // We'll also have to refactor those types to be traits.
-        let readers = Readers::new(Arc::new(rustcask_dir.clone())).unwrap();
+        let readers = Readers::new(
+            Arc::new(rustcask_dir.to_path_buf()),
+            &RustcaskBuilder::default(),
+        )
+        .unwrap();
 
-        let writer = Writer::new(
-            false,
-            1024,
-            Arc::new(rustcask_dir.clone()),
+        Writer::new(
+            &RustcaskBuilder::default().set_max_data_file_size(1024),
+            Arc::new(rustcask_dir.to_path_buf()),
             Arc::new(RwLock::new(keydir)),
             readers,
         )
-        .unwrap();
-
-        writer
+        .unwrap()
     }
 }
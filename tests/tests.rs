@@ -1,11 +1,20 @@
-use rustcask::Rustcask;
+use rustcask::error::{GetError, GetErrorKind, OpenErrorKind, SetErrorKind};
+use rustcask::{
+    CacheEviction, Clock, CorruptionEvent, CorruptionKind, GenerationVerifyReport, LogCheckpoint,
+    LogFileEntry, OnCorruption, OpenAnomaly, Rustcask, ValueTransform,
+};
 
+use std::collections::HashMap;
 use std::fs::{self};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 
 use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
-use std::sync::{Arc, Barrier};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use tempfile::TempDir;
 
@@ -36,6 +45,45 @@ fn get_stored_value() {
     assert_eq!(store.get(&keys[1]).unwrap(), Some(values[1].clone()));
 }
 
+#[test]
+fn get_into_reuses_buffer() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    store.set_str("long", "a-fairly-long-value").unwrap();
+    store.set_str("short", "short").unwrap();
+    store.set_str("empty", "").unwrap();
+
+    let mut buf = Vec::new();
+    assert!(store
+        .get_into(&"long".as_bytes().to_vec(), &mut buf)
+        .unwrap());
+    assert_eq!(buf, "a-fairly-long-value".as_bytes());
+    let capacity = buf.capacity();
+
+    assert!(store
+        .get_into(&"short".as_bytes().to_vec(), &mut buf)
+        .unwrap());
+    assert_eq!(buf, "short".as_bytes());
+    assert_eq!(buf.capacity(), capacity);
+
+    assert!(store
+        .get_into(&"empty".as_bytes().to_vec(), &mut buf)
+        .unwrap());
+    assert!(buf.is_empty());
+
+    store.set_str("empty", "no-longer-empty").unwrap();
+    assert!(store
+        .get_into(&"empty".as_bytes().to_vec(), &mut buf)
+        .unwrap());
+    assert_eq!(buf, "no-longer-empty".as_bytes());
+
+    // A missing key leaves the buffer untouched.
+    assert!(!store
+        .get_into(&"missing".as_bytes().to_vec(), &mut buf)
+        .unwrap());
+    assert_eq!(buf, "no-longer-empty".as_bytes());
+}
+
 #[test]
 fn overwrite_value() {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
@@ -64,6 +112,40 @@ fn get_non_existent_value() {
     assert_eq!(store.get(&key.clone()).unwrap(), None);
 }
 
+#[test]
+fn get_stored_str() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+
+    store.set_str("leader", "instance-a").unwrap();
+    assert_eq!(
+        store.get_str("leader").unwrap(),
+        Some("instance-a".to_string())
+    );
+    assert_eq!(
+        store.get(&"leader".as_bytes().to_vec()).unwrap(),
+        Some("instance-a".as_bytes().to_vec())
+    );
+    assert_eq!(store.get_str("follower").unwrap(), None);
+}
+
+#[test]
+fn get_str_invalid_utf8() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+
+    store
+        .set("key".as_bytes().to_vec(), vec![0xff, 0xfe, 0xfd])
+        .unwrap();
+    assert!(matches!(
+        store.get_str("key"),
+        Err(GetError {
+            kind: GetErrorKind::InvalidUtf8(_),
+            ..
+        })
+    ));
+}
+
 #[test]
 fn remove_key() {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
@@ -211,6 +293,265 @@ fn test_merge_with_rotate() {
     );
 }
 
+#[test]
+fn test_merge_without_garbage() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let temp_dir_path = temp_dir.path();
+    let mut store = Rustcask::builder()
+        .set_sync_mode(true)
+        .open(temp_dir_path)
+        .unwrap();
+
+    store
+        .set(
+            "leader".as_bytes().to_vec(),
+            "instance-a".as_bytes().to_vec(),
+        )
+        .unwrap();
+
+    // With nothing to drop, the merge grows the directory by the hint file it writes
+    // and the header of the fresh active data file.
+    let rustcask_dir_size = get_total_directory_size(temp_dir_path);
+    let data_file_size = fs::metadata(temp_dir_path.join("0.rustcask.data"))
+        .unwrap()
+        .len();
+    store.merge().unwrap();
+    let hint_file_size = fs::metadata(temp_dir_path.join("1.rustcask.hint"))
+        .unwrap()
+        .len();
+    let active_file_size = fs::metadata(temp_dir_path.join("2.rustcask.data"))
+        .unwrap()
+        .len();
+    assert_eq!(
+        fs::metadata(temp_dir_path.join("1.rustcask.data"))
+            .unwrap()
+            .len(),
+        data_file_size
+    );
+    assert_eq!(
+        get_total_directory_size(temp_dir_path),
+        rustcask_dir_size + hint_file_size + active_file_size
+    );
+
+    assert_eq!(
+        store.get(&"leader".as_bytes().to_vec()).unwrap(),
+        Some("instance-a".as_bytes().to_vec())
+    );
+}
+
+#[test]
+fn drop_generation() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    // Rotate after every write, so that each write lands in its own generation.
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(1)
+        .open(temp_dir.path())
+        .unwrap();
+
+    store.set_str("leader", "instance-a").unwrap(); // generation 0
+    store.set_str("leader", "instance-b").unwrap(); // generation 1
+
+    // Generation 2 is the active generation, and generation 1 holds the live value.
+    assert!(store.drop_generation(2).is_err());
+    assert!(store.drop_generation(1).is_err());
+    assert!(store.drop_generation(5).is_err());
+
+    store.drop_generation(0).unwrap();
+    assert!(!temp_dir.path().join("0.rustcask.data").exists());
+    assert_eq!(
+        store.get_str("leader").unwrap(),
+        Some("instance-b".to_string())
+    );
+
+    drop(store);
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    assert_eq!(
+        store.get_str("leader").unwrap(),
+        Some("instance-b".to_string())
+    );
+}
+
+#[test]
+fn drop_generation_with_tombstones() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(1)
+        .open(temp_dir.path())
+        .unwrap();
+
+    store.set_str("leader", "instance-a").unwrap(); // generation 0
+    store.remove("leader".as_bytes().to_vec()).unwrap(); // generation 1
+
+    // The tombstone in generation 1 hides the value in generation 0.
+    assert!(store.drop_generation(1).is_err());
+
+    store.drop_generation(0).unwrap();
+    store.drop_generation(1).unwrap();
+
+    drop(store);
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    assert_eq!(store.get_str("leader").unwrap(), None);
+}
+
+#[test]
+fn read_only_handle() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+
+    let num_keyvals = 16;
+    for i in 0..num_keyvals {
+        store
+            .set_str(&format!("key{}", i), &format!("value{}", i))
+            .unwrap();
+    }
+
+    let read_only_store = store.into_read_only();
+
+    let mut handles = Vec::with_capacity(num_keyvals);
+    for i in 0..num_keyvals {
+        let mut read_only_store = read_only_store.clone();
+        handles.push(thread::spawn(move || {
+            assert_eq!(
+                read_only_store
+                    .get(&format!("key{}", i).into_bytes())
+                    .unwrap(),
+                Some(format!("value{}", i).into_bytes())
+            );
+        }));
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn move_store() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let old_dir = temp_dir.path().join("old");
+    let new_dir = temp_dir.path().join("new");
+    fs::create_dir(&old_dir).unwrap();
+
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(64)
+        .open(&old_dir)
+        .unwrap();
+    let num_keyvals = 32;
+    for i in 0..num_keyvals {
+        store
+            .set(
+                format!("key{}", i).into_bytes(),
+                format!("value{}", i).into_bytes(),
+            )
+            .unwrap();
+    }
+    store.merge().unwrap();
+    store
+        .set("key0".as_bytes().to_vec(), "new-value0".as_bytes().to_vec())
+        .unwrap();
+
+    let mut store = store.move_to(&new_dir).unwrap();
+
+    assert_eq!(fs::read_dir(&old_dir).unwrap().count(), 0);
+    assert_eq!(
+        store.get(&"key0".as_bytes().to_vec()).unwrap(),
+        Some("new-value0".as_bytes().to_vec())
+    );
+    for i in 1..num_keyvals {
+        assert_eq!(
+            store.get(&format!("key{}", i).into_bytes()).unwrap(),
+            Some(format!("value{}", i).into_bytes())
+        );
+    }
+
+    // Writes go to the new directory
+    store
+        .set(
+            "key-after-move".as_bytes().to_vec(),
+            "value".as_bytes().to_vec(),
+        )
+        .unwrap();
+    drop(store);
+    let mut store = Rustcask::builder().open(&new_dir).unwrap();
+    assert_eq!(
+        store.get(&"key-after-move".as_bytes().to_vec()).unwrap(),
+        Some("value".as_bytes().to_vec())
+    );
+}
+
+#[test]
+fn move_store_with_open_clone() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    let _store_clone = store.clone();
+
+    assert!(store.move_to(&temp_dir.path().join("new")).is_err());
+}
+
+#[test]
+fn move_store_moves_directory_lock() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let old_dir = temp_dir.path().join("old");
+    let new_dir = temp_dir.path().join("new");
+    fs::create_dir(&old_dir).unwrap();
+
+    let mut store = Rustcask::builder().open(&old_dir).unwrap();
+    store.set_str("key", "value").unwrap();
+    assert!(is_locked_by_store(&old_dir));
+
+    let mut store = store.move_to(&new_dir).unwrap();
+    assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+    assert!(is_locked_by_store(&new_dir));
+
+    // The old directory's lock file is gone along with the store's files.
+    assert_eq!(fs::read_dir(&old_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn move_store_to_locked_directory() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let old_dir = temp_dir.path().join("old");
+    let new_dir = temp_dir.path().join("new");
+    fs::create_dir(&old_dir).unwrap();
+    fs::create_dir(&new_dir).unwrap();
+
+    let mut store = Rustcask::builder().open(&old_dir).unwrap();
+    store.set_str("key", "value").unwrap();
+    // A store with a lazily created active data file leaves the directory empty, but locked.
+    let _other_store = Rustcask::builder()
+        .set_lazy_active_file(true)
+        .open(&new_dir)
+        .unwrap();
+
+    let err = store.move_to(&new_dir).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+    // Nothing was moved.
+    let mut store = Rustcask::builder().open(&old_dir).unwrap();
+    assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+}
+
+#[test]
+fn move_store_with_open_readers() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    store.set_str("key", "value").unwrap();
+
+    let read_only = store.clone().into_read_only();
+    assert!(store.move_to(&temp_dir.path().join("new")).is_err());
+    drop(read_only);
+
+    let store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    let mut iter = store.iter_ordered();
+    assert!(iter.next().is_some());
+    assert!(store.move_to(&temp_dir.path().join("new")).is_err());
+    drop(iter);
+
+    // Nothing was moved.
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+}
+
 /// Calculates the total size of files in a given directory and its subdirectories.
 ///
 /// # Arguments
@@ -220,20 +561,1962 @@ fn test_merge_with_rotate() {
 /// # Returns
 ///
 /// The total size of files in the directory and its subdirectories, in bytes.
-fn get_total_directory_size(path: &Path) -> u64 {
-    let mut total_size: u64 = 0;
+#[test]
+fn counters_across_handles() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = Rustcask::builder().open(temp_dir.path()).unwrap();
 
-    if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let entry_path = entry.path();
-                if entry_path.is_dir() {
-                    total_size += get_total_directory_size(&entry_path);
-                } else {
-                    if let Ok(metadata) = entry_path.metadata() {
-                        total_size += metadata.st_size();
-                    }
-                }
+    let num_threads = 8;
+    let ops_per_thread = 16;
+    let barrier = Arc::new(Barrier::new(num_threads));
+    let mut handles = Vec::with_capacity(num_threads);
+
+    for thread_id in 0..num_threads {
+        let barrier = Arc::clone(&barrier);
+        let mut store = store.clone();
+        handles.push(thread::spawn(move || {
+            barrier.wait();
+            for i in 0..ops_per_thread {
+                let key = format!("key-{}-{}", thread_id, i).into_bytes();
+                store.set(key.clone(), "value".as_bytes().to_vec()).unwrap();
+                assert!(store.get(&key).unwrap().is_some());
+                store.remove(key).unwrap();
+            }
+        }));
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let total_ops = (num_threads * ops_per_thread) as u64;
+    let counters = store.counters();
+    assert_eq!(counters.gets, total_ops);
+    assert_eq!(counters.sets, total_ops);
+    assert_eq!(counters.removes, total_ops);
+    assert_eq!(counters.merges, 0);
+    assert!(counters.bytes_written > 0);
+    assert!(counters.bytes_read > 0);
+
+    let mut store = store;
+    store.merge().unwrap();
+    assert_eq!(store.counters().merges, 1);
+}
+
+#[test]
+fn sync_survives_restart() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+
+    store.set_str("leader", "instance-a").unwrap();
+    store.remove("leader".as_bytes().to_vec()).unwrap();
+    store.set_str("follower", "instance-b").unwrap();
+    store.sync().unwrap();
+
+    // Simulate a hard restart: the handle is never dropped, so nothing runs on shutdown.
+    std::mem::forget(store);
+
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    assert_eq!(store.get_str("leader").unwrap(), None);
+    assert_eq!(
+        store.get_str("follower").unwrap(),
+        Some("instance-b".to_string())
+    );
+}
+
+#[test]
+fn reconcile_after_external_delete() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+
+    store.set_str("a", "value-a").unwrap();
+    store.set_str("b", "value-b").unwrap();
+    let sealed_generation = store.flush_and_rotate().unwrap();
+    store.set_str("c", "value-c").unwrap();
+    // Read from the sealed generation, so that this handle has a reader open for it.
+    assert_eq!(store.get_str("a").unwrap(), Some("value-a".to_string()));
+
+    fs::remove_file(
+        temp_dir
+            .path()
+            .join(format!("{}.rustcask.data", sealed_generation)),
+    )
+    .unwrap();
+
+    assert_eq!(store.reconcile().unwrap(), 2);
+    assert_eq!(store.reconcile().unwrap(), 0);
+
+    assert_eq!(store.get_str("a").unwrap(), None);
+    assert_eq!(store.get_str("b").unwrap(), None);
+    assert_eq!(store.get_str("c").unwrap(), Some("value-c".to_string()));
+
+    // The store keeps working, and a reopened store agrees with it.
+    store.set_str("a", "new-value-a").unwrap();
+    drop(store);
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    assert_eq!(store.get_str("a").unwrap(), Some("new-value-a".to_string()));
+    assert_eq!(store.get_str("b").unwrap(), None);
+    assert_eq!(store.get_str("c").unwrap(), Some("value-c".to_string()));
+}
+
+#[test]
+fn reconcile_missing_active_data_file_with_clones() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store_a = Rustcask::builder().open(temp_dir.path()).unwrap();
+    let mut store_b = store_a.clone();
+
+    store_a.set_str("key", "old-value").unwrap();
+    // Both handles have a reader open for the active data file.
+    assert_eq!(
+        store_a.get_str("key").unwrap(),
+        Some("old-value".to_string())
+    );
+    assert_eq!(
+        store_b.get_str("key").unwrap(),
+        Some("old-value".to_string())
+    );
+
+    let data_files: Vec<String> = get_file_names(temp_dir.path())
+        .into_iter()
+        .filter(|file_name| file_name.ends_with(".rustcask.data"))
+        .collect();
+    assert_eq!(data_files.len(), 1);
+    fs::remove_file(temp_dir.path().join(&data_files[0])).unwrap();
+
+    assert_eq!(store_a.reconcile().unwrap(), 1);
+    store_a.set_str("key", "new-value").unwrap();
+    assert_eq!(
+        store_a.get_str("key").unwrap(),
+        Some("new-value".to_string())
+    );
+    assert_eq!(
+        store_b.get_str("key").unwrap(),
+        Some("new-value".to_string())
+    );
+}
+
+#[test]
+fn access_counts_drive_lfu_eviction() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    // Room for two of the cached keys and values below.
+    let mut store = Rustcask::builder()
+        .set_value_cache_capacity(40)
+        .set_cache_eviction(CacheEviction::Lfu)
+        .open(temp_dir.path())
+        .unwrap();
+
+    for key in ["key-a", "key-b", "key-c"] {
+        store.set_str(key, "value-123").unwrap();
+    }
+    assert_eq!(store.access_count(&"key-a".as_bytes().to_vec()), Some(0));
+
+    for _ in 0..5 {
+        store.get_str("key-a").unwrap();
+    }
+    store.get_str("key-b").unwrap();
+    assert_eq!(store.access_count(&"key-a".as_bytes().to_vec()), Some(5));
+    assert_eq!(store.access_count(&"key-b".as_bytes().to_vec()), Some(1));
+
+    // Caching "key-c" evicts "key-b", which has been read less often than "key-a",
+    // even though "key-a" was cached first.
+    store.get_str("key-c").unwrap();
+    let before = store.counters();
+    store.get_str("key-a").unwrap();
+    store.get_str("key-b").unwrap();
+    let after = store.counters();
+    assert_eq!(after.cache_hits - before.cache_hits, 1);
+    assert_eq!(after.cache_misses - before.cache_misses, 1);
+}
+
+#[test]
+fn clones_share_value_cache_and_counters() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store_a = Rustcask::builder()
+        .set_value_cache_capacity(1024)
+        .open(temp_dir.path())
+        .unwrap();
+    let mut store_b = store_a.clone();
+    store_a.set_str("key", "old-value").unwrap();
+
+    // The second read through handle B is served from the cache.
+    assert_eq!(
+        store_b.get_str("key").unwrap(),
+        Some("old-value".to_string())
+    );
+    assert_eq!(
+        store_b.get_str("key").unwrap(),
+        Some("old-value".to_string())
+    );
+    assert_eq!(store_a.counters().cache_hits, 1);
+
+    // A set through handle A invalidates the value that handle B cached.
+    store_a.set_str("key", "new-value").unwrap();
+    assert_eq!(
+        store_b.get_str("key").unwrap(),
+        Some("new-value".to_string())
+    );
+    store_a.remove(b"key".to_vec()).unwrap();
+    assert_eq!(store_b.get_str("key").unwrap(), None);
+    assert_eq!(store_a.counters(), store_b.counters());
+}
+
+#[test]
+fn warm_cache_preloads_values() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_value_cache_capacity(1024)
+        .open(temp_dir.path())
+        .unwrap();
+    for key in ["key-a", "key-b", "key-c"] {
+        store.set_str(key, "value-123").unwrap();
+    }
+
+    let warmed = vec![b"key-a".to_vec(), b"key-b".to_vec(), b"missing".to_vec()];
+    store.warm_cache(&warmed).unwrap();
+
+    let before = store.counters();
+    assert_eq!(
+        store.get_str("key-a").unwrap(),
+        Some("value-123".to_string())
+    );
+    assert_eq!(
+        store.get_str("key-b").unwrap(),
+        Some("value-123".to_string())
+    );
+    let after = store.counters();
+    assert_eq!(after.cache_hits - before.cache_hits, 2);
+    assert_eq!(after.cache_misses, before.cache_misses);
+    assert_eq!(after.bytes_read, before.bytes_read);
+
+    // Keys that weren't warmed are still read from disk.
+    store.get_str("key-c").unwrap();
+    assert_eq!(store.counters().cache_misses - after.cache_misses, 1);
+}
+
+#[test]
+fn access_counts_disabled_by_default() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    store.set_str("key", "value").unwrap();
+    store.get_str("key").unwrap();
+    assert_eq!(store.access_count(&"key".as_bytes().to_vec()), None);
+    assert_eq!(store.counters().cache_misses, 0);
+}
+
+#[test]
+fn exists_and_stored_size_reports_present_absent_and_removed_keys() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    let present = b"present".to_vec();
+    let removed = b"removed".to_vec();
+    store.set(present.clone(), vec![1; 100]).unwrap();
+    store.set(removed.clone(), b"value".to_vec()).unwrap();
+    store.set(present.clone(), vec![2; 1000]).unwrap();
+    store.remove(removed.clone()).unwrap();
+
+    assert_eq!(store.exists_and_stored_size(&present), Some(1000));
+    assert_eq!(store.exists_and_stored_size(&removed), None);
+    assert_eq!(store.exists_and_stored_size(&b"absent".to_vec()), None);
+
+    // The sizes are rebuilt from the data files on open, and from the hint files after a merge.
+    for merge in [false, true] {
+        if merge {
+            store.merge().unwrap();
+        }
+        drop(store);
+        store = Rustcask::builder().open(temp_dir.path()).unwrap();
+        assert_eq!(store.exists_and_stored_size(&present), Some(1000));
+        assert_eq!(store.exists_and_stored_size(&removed), None);
+    }
+}
+
+#[test]
+fn get_many_sorted_preserves_key_order() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(256)
+        .open(temp_dir.path())
+        .unwrap();
+
+    for i in 0..50 {
+        store
+            .set_str(&format!("key{}", i), &format!("value{}", i))
+            .unwrap();
+    }
+    // Overwrite some keys, so that their values are stored after later keys.
+    for i in (0..50).step_by(3) {
+        store
+            .set_str(&format!("key{}", i), &format!("new-value{}", i))
+            .unwrap();
+    }
+
+    let keys: Vec<Vec<u8>> = [45, 3, 100, 0, 17, 3, 31, 8]
+        .iter()
+        .map(|i| format!("key{}", i).into_bytes())
+        .collect();
+    let values = store.get_many_sorted(&keys).unwrap();
+
+    let expected: Vec<Option<Vec<u8>>> = [
+        Some("new-value45"),
+        Some("new-value3"),
+        None,
+        Some("new-value0"),
+        Some("value17"),
+        Some("new-value3"),
+        Some("value31"),
+        Some("value8"),
+    ]
+    .iter()
+    .map(|value| value.map(|value| value.as_bytes().to_vec()))
+    .collect();
+    assert_eq!(values, expected);
+    assert_eq!(store.get_many(&keys).unwrap(), expected);
+}
+
+#[test]
+fn export_includes_active_data_file() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(128)
+        .open(temp_dir.path())
+        .unwrap();
+
+    for i in 0..10 {
+        store
+            .set_str(&format!("key{}", i), &format!("value{}", i))
+            .unwrap();
+    }
+    store.remove("key3".as_bytes().to_vec()).unwrap();
+    store.set_str("key5", "new-value5").unwrap();
+    // The latest write lands in the active data file.
+    store.set_str("latest", "latest-value").unwrap();
+
+    let mut snapshot = Vec::new();
+    assert_eq!(store.export(&mut snapshot).unwrap(), 10);
+
+    let export_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(export_dir.path().join("0.rustcask.data"), snapshot).unwrap();
+    let mut exported = Rustcask::builder().open(export_dir.path()).unwrap();
+    assert_eq!(
+        exported.get_str("latest").unwrap(),
+        Some("latest-value".to_string())
+    );
+    assert_eq!(
+        exported.get_str("key5").unwrap(),
+        Some("new-value5".to_string())
+    );
+    assert_eq!(exported.get_str("key3").unwrap(), None);
+    assert_eq!(
+        exported.get_str("key9").unwrap(),
+        Some("value9".to_string())
+    );
+}
+
+#[test]
+fn write_rate_limit_throttles_writes() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let bytes_per_sec = 100_000;
+    let mut store = Rustcask::builder()
+        .set_write_rate_limit(bytes_per_sec)
+        .open(temp_dir.path())
+        .unwrap();
+
+    let value = vec![0; 1000];
+    let start = Instant::now();
+    for i in 0..300 {
+        store
+            .set(format!("key{}", i).into_bytes(), value.clone())
+            .unwrap();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    // The first second's worth of bytes is written in a burst, and the rest at the limit.
+    let bytes_written = store.counters().bytes_written;
+    assert!(bytes_written > 300_000);
+    let expected = (bytes_written - bytes_per_sec) as f64 / bytes_per_sec as f64;
+    assert!(elapsed >= expected * 0.9, "elapsed {}s", elapsed);
+    assert!(elapsed < expected * 2.0, "elapsed {}s", elapsed);
+}
+
+#[test]
+fn non_blocking_rate_limit() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_write_rate_limit(1000)
+        .set_rate_limit_blocking(false)
+        .open(temp_dir.path())
+        .unwrap();
+
+    store.set("key".as_bytes().to_vec(), vec![0; 1000]).unwrap();
+    let err = store
+        .set("key".as_bytes().to_vec(), vec![0; 1000])
+        .unwrap_err();
+    assert!(matches!(err.kind, SetErrorKind::RateLimited));
+    assert_eq!(
+        store.get(&"key".as_bytes().to_vec()).unwrap(),
+        Some(vec![0; 1000])
+    );
+}
+
+#[test]
+fn ttl_expires_with_injected_clock() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let clock = Arc::new(ManualClock::new());
+    let builder = Rustcask::builder().set_clock(clock.clone());
+    let mut store = builder.clone().open(temp_dir.path()).unwrap();
+
+    store
+        .set_with_ttl(
+            "session".as_bytes().to_vec(),
+            "token".as_bytes().to_vec(),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+    store.set_str("permanent", "value").unwrap();
+
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(store.get_str("session").unwrap(), Some("token".to_string()));
+
+    // The expiry is stored in the data file, so it survives a restart.
+    drop(store);
+    let mut store = builder.open(temp_dir.path()).unwrap();
+    assert_eq!(store.get_str("session").unwrap(), Some("token".to_string()));
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(store.get_str("session").unwrap(), None);
+    assert_eq!(store.remove("session".as_bytes().to_vec()).unwrap(), None);
+    assert_eq!(
+        store.get_str("permanent").unwrap(),
+        Some("value".to_string())
+    );
+}
+
+#[test]
+fn monotonic_timestamps_survive_clock_jumping_back() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let clock = Arc::new(ManualClock::new());
+    let ttl = Duration::from_secs(60);
+    let expires_at = |store: &Rustcask, key: &str| {
+        store
+            .locate(&key.as_bytes().to_vec())
+            .unwrap()
+            .expires_at()
+            .unwrap()
+    };
+
+    for monotonic_timestamps in [false, true] {
+        let mut store = Rustcask::builder()
+            .set_clock(clock.clone())
+            .set_monotonic_timestamps(monotonic_timestamps)
+            .open(temp_dir.path())
+            .unwrap();
+        store
+            .set_with_ttl(b"first".to_vec(), b"a".to_vec(), ttl)
+            .unwrap();
+        clock.rewind(Duration::from_secs(5));
+        store
+            .set_with_ttl(b"second".to_vec(), b"b".to_vec(), ttl)
+            .unwrap();
+        store
+            .set_with_ttl(b"third".to_vec(), b"c".to_vec(), ttl)
+            .unwrap();
+
+        let first = expires_at(&store, "first");
+        if monotonic_timestamps {
+            assert_eq!(expires_at(&store, "second"), first + 1);
+            assert_eq!(expires_at(&store, "third"), first + 2);
+        } else {
+            assert_eq!(expires_at(&store, "second"), first - 5_000);
+            assert_eq!(expires_at(&store, "third"), first - 5_000);
+        }
+
+        // Once the clock catches up, writes use its time again.
+        clock.advance(Duration::from_secs(10));
+        store
+            .set_with_ttl(b"fourth".to_vec(), b"d".to_vec(), ttl)
+            .unwrap();
+        assert_eq!(expires_at(&store, "fourth"), first + 5_000);
+    }
+}
+
+#[test]
+fn merge_drops_expired_values() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let clock = Arc::new(ManualClock::new());
+    let mut store = Rustcask::builder()
+        .set_clock(clock.clone())
+        .open(temp_dir.path())
+        .unwrap();
+
+    for i in 0..100 {
+        store
+            .set_with_ttl(
+                format!("key{}", i).into_bytes(),
+                vec![0; 1000],
+                Duration::from_secs(10),
+            )
+            .unwrap();
+    }
+    let size_before_expiry = get_total_directory_size(temp_dir.path());
+
+    clock.advance(Duration::from_secs(10));
+    store.merge().unwrap();
+    assert!(get_total_directory_size(temp_dir.path()) < size_before_expiry / 10);
+    assert_eq!(store.get_str("key0").unwrap(), None);
+}
+
+/// A clock that only moves when it's advanced.
+#[derive(Debug)]
+struct ManualClock {
+    now: Mutex<SystemTime>,
+}
+
+impl ManualClock {
+    fn new() -> Self {
+        ManualClock {
+            now: Mutex::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+        }
+    }
+
+    fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    fn rewind(&self, duration: Duration) {
+        *self.now.lock().unwrap() -= duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[test]
+fn failed_rotation_keeps_written_value() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(1)
+        .open(temp_dir.path())
+        .unwrap();
+
+    // A directory in place of the next data file makes creating it fail.
+    let next_data_file = temp_dir.path().join("1.rustcask.data");
+    fs::create_dir(&next_data_file).unwrap();
+
+    let err = store.set_str("key", "value").unwrap_err();
+    assert!(matches!(err.kind, SetErrorKind::Rotation(_)));
+    assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+
+    // Rotation is retried after the next write, once the fault is cleared.
+    fs::remove_dir(&next_data_file).unwrap();
+    store.set_str("other-key", "other-value").unwrap();
+    assert!(next_data_file.is_file());
+    assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+    assert_eq!(
+        store.get_str("other-key").unwrap(),
+        Some("other-value".to_string())
+    );
+}
+
+#[test]
+fn file_filter_ignores_foreign_data_files() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    // A file that belongs to another application but looks like a rustcask data file.
+    fs::write(
+        temp_dir.path().join("999.rustcask.data"),
+        b"not rustcask data",
+    )
+    .unwrap();
+
+    assert!(Rustcask::builder().open(temp_dir.path()).is_err());
+
+    let builder = Rustcask::builder().set_file_filter(|path| !path.ends_with("999.rustcask.data"));
+    let mut store = builder.clone().open(temp_dir.path()).unwrap();
+    store.set_str("key", "value").unwrap();
+    drop(store);
+
+    let mut store = builder.open(temp_dir.path()).unwrap();
+    assert_eq!(store.get_str("key").unwrap(), Some("value".to_string()));
+    assert!(temp_dir.path().join("0.rustcask.data").is_file());
+}
+
+#[test]
+fn inlined_values_survive_reopen() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let builder = Rustcask::builder().set_inline_value_threshold(16);
+    let mut store = builder.clone().open(temp_dir.path()).unwrap();
+
+    store.set_str("small", "inline").unwrap();
+    store.set_str("large", &"x".repeat(100)).unwrap();
+    assert_eq!(store.get_str("small").unwrap(), Some("inline".to_string()));
+    assert_eq!(store.counters().bytes_read, 0);
+
+    // The inlined value is rebuilt from the data file.
+    drop(store);
+    let mut store = builder.clone().open(temp_dir.path()).unwrap();
+    assert_eq!(store.get_str("small").unwrap(), Some("inline".to_string()));
+    assert_eq!(store.counters().bytes_read, 0);
+    assert_eq!(store.get_str("large").unwrap(), Some("x".repeat(100)));
+    assert!(store.counters().bytes_read > 100);
+
+    // After a merge, the keydir is rebuilt from hint files, which don't hold values.
+    store.merge().unwrap();
+    drop(store);
+    let mut store = builder.open(temp_dir.path()).unwrap();
+    assert_eq!(store.get_str("small").unwrap(), Some("inline".to_string()));
+    assert_eq!(store.counters().bytes_read, 0);
+    assert_eq!(
+        store.remove("small".as_bytes().to_vec()).unwrap(),
+        Some("inline".as_bytes().to_vec())
+    );
+    assert_eq!(store.get_str("small").unwrap(), None);
+}
+
+#[test]
+fn open_report_lists_anomalies() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    store.set_str("leader", "instance-a").unwrap();
+    store.set_str("leader", "instance-b").unwrap();
+    store.merge().unwrap();
+    store.set_str("follower", "instance-c").unwrap();
+    drop(store);
+
+    let (_, report) = Rustcask::builder().open_with_report(dir).unwrap();
+    assert!(report.is_clean());
+
+    // Generation 1 was written by the merge, and generation 2 is the active data file.
+    fs::write(dir.join("1.rustcask.hint"), b"corrupt hint").unwrap();
+    let active_data_file = dir.join("2.rustcask.data");
+    let active_len = fs::metadata(&active_data_file).unwrap().len();
+    let mut truncated_entry = 100u32.to_le_bytes().to_vec();
+    truncated_entry.extend_from_slice(b"partial");
+    fs::write(
+        &active_data_file,
+        [fs::read(&active_data_file).unwrap(), truncated_entry].concat(),
+    )
+    .unwrap();
+    fs::write(dir.join("0.rustcask.data.migrating"), b"").unwrap();
+    fs::write(dir.join("notes.rustcask.txt"), b"").unwrap();
+    fs::write(dir.join("unrelated.txt"), b"").unwrap();
+
+    let (mut store, report) = Rustcask::builder().open_with_report(dir).unwrap();
+    assert_eq!(
+        report.anomalies(),
+        [
+            OpenAnomaly::StaleTempFile {
+                path: dir.join("0.rustcask.data.migrating")
+            },
+            OpenAnomaly::UnrecognizedFile {
+                path: dir.join("notes.rustcask.txt")
+            },
+            OpenAnomaly::HintFileRejected { generation: 1 },
+            OpenAnomaly::TruncatedEntry {
+                generation: 2,
+                offset: active_len
+            },
+        ]
+    );
+    assert_eq!(
+        store.get_str("leader").unwrap(),
+        Some("instance-b".to_string())
+    );
+
+    // The partial entry was cut off, so new writes can be read back after a restart.
+    assert_eq!(fs::metadata(&active_data_file).unwrap().len(), active_len);
+    store.set_str("follower", "instance-d").unwrap();
+    drop(store);
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    assert_eq!(
+        store.get_str("follower").unwrap(),
+        Some("instance-d".to_string())
+    );
+}
+
+#[test]
+fn open_keeps_entries_after_corrupt_length_in_active_data_file() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    store.set_str("first", "a").unwrap();
+    store.set_str("second", "b").unwrap();
+    store.set_str("third", "c").unwrap();
+    let location = store.locate(&b"second".to_vec()).unwrap();
+    drop(store);
+
+    // Make the value length of the middle entry run past the end of the data file.
+    let active_data_file = dir.join("0.rustcask.data");
+    let mut bytes = fs::read(&active_data_file).unwrap();
+    let active_len = bytes.len() as u64;
+    let value_len_offset = location.offset() as usize + 4;
+    bytes[value_len_offset..value_len_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    fs::write(&active_data_file, bytes).unwrap();
+
+    // The entry is corrupt rather than truncated, because a complete entry follows it,
+    // so the active data file isn't cut off there.
+    let (mut store, report) = Rustcask::builder().open_with_report(dir).unwrap();
+    assert_eq!(
+        report.anomalies(),
+        [OpenAnomaly::CorruptEntry {
+            generation: 0,
+            offset: location.offset()
+        }]
+    );
+    assert_eq!(fs::metadata(&active_data_file).unwrap().len(), active_len);
+    assert_eq!(store.get_str("first").unwrap(), Some("a".to_string()));
+    assert_eq!(store.get_str("second").unwrap(), None);
+    assert_eq!(store.get_str("third").unwrap(), Some("c".to_string()));
+
+    store.set_str("fourth", "d").unwrap();
+    drop(store);
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    assert_eq!(store.get_str("third").unwrap(), Some("c".to_string()));
+    assert_eq!(store.get_str("fourth").unwrap(), Some("d".to_string()));
+}
+
+#[test]
+fn open_skips_unreadable_generation() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    store.set_str("in-gen-0", "a").unwrap();
+    store.set_str("overwritten-in-gen-1", "old").unwrap();
+    store.flush_and_rotate().unwrap();
+    store.set_str("in-gen-1", "b").unwrap();
+    store.set_str("overwritten-in-gen-1", "new").unwrap();
+    store.flush_and_rotate().unwrap();
+    store.set_str("in-gen-2", "c").unwrap();
+    drop(store);
+
+    // A directory in place of the data file makes it unreadable, even for root.
+    let unreadable = dir.join("1.rustcask.data");
+    fs::remove_file(&unreadable).unwrap();
+    fs::create_dir(&unreadable).unwrap();
+
+    assert!(Rustcask::builder().open(dir).is_err());
+
+    let (mut store, report) = Rustcask::builder()
+        .set_skip_unreadable_generations(true)
+        .open_with_report(dir)
+        .unwrap();
+    assert_eq!(
+        report.anomalies(),
+        [OpenAnomaly::UnreadableGeneration { generation: 1 }]
+    );
+    assert_eq!(store.get_str("in-gen-0").unwrap(), Some("a".to_string()));
+    assert_eq!(store.get_str("in-gen-1").unwrap(), None);
+    assert_eq!(
+        store.get_str("overwritten-in-gen-1").unwrap(),
+        Some("old".to_string())
+    );
+    assert_eq!(store.get_str("in-gen-2").unwrap(), Some("c".to_string()));
+
+    // The store stays writable, and merges leave the unreadable generation in place.
+    store.set_str("in-gen-2", "d").unwrap();
+    store.merge().unwrap();
+    assert!(unreadable.is_dir());
+    assert_eq!(store.get_str("in-gen-2").unwrap(), Some("d".to_string()));
+}
+
+#[test]
+fn merge_drops_tombstones_unless_generations_are_unreadable() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    store.set_str("removed", "a").unwrap();
+    store.flush_and_rotate().unwrap();
+    store.remove(b"removed".to_vec()).unwrap();
+    store.set_str("kept", "b").unwrap();
+    store.flush_and_rotate().unwrap();
+    assert_eq!(store.tombstones().unwrap().count(), 1);
+
+    // A merge of every generation leaves no tombstones behind.
+    let mut full_merge_store = store.fork(&dir.join("fork")).unwrap();
+    full_merge_store.merge().unwrap();
+    assert_eq!(full_merge_store.tombstones().unwrap().count(), 0);
+    assert_eq!(full_merge_store.get_str("removed").unwrap(), None);
+    drop(full_merge_store);
+    drop(store);
+
+    // Make the generation holding the removed value unreadable, so the merge leaves it out.
+    let data_file = dir.join("0.rustcask.data");
+    let moved_data_file = dir.join("0.rustcask.data.moved");
+    fs::rename(&data_file, &moved_data_file).unwrap();
+    fs::create_dir(&data_file).unwrap();
+    let mut store = Rustcask::builder()
+        .set_skip_unreadable_generations(true)
+        .open(dir)
+        .unwrap();
+    store.merge().unwrap();
+    drop(store);
+
+    // The tombstone survived the merge, so the value stays removed once it's readable again.
+    fs::remove_dir(&data_file).unwrap();
+    fs::rename(&moved_data_file, &data_file).unwrap();
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    assert_eq!(store.get_str("removed").unwrap(), None);
+    assert_eq!(store.get_str("kept").unwrap(), Some("b".to_string()));
+    let tombstones: Vec<Vec<u8>> = store.tombstones().unwrap().map(|(key, _, _)| key).collect();
+    assert_eq!(tombstones, [b"removed".to_vec()]);
+}
+
+#[test]
+fn get_batch_stream_matches_individual_gets() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder()
+        .set_inline_value_threshold(4)
+        .open(dir)
+        .unwrap();
+    for i in 0..100 {
+        let value = if i % 3 == 0 { "tiny" } else { "a longer value" };
+        store.set_str(&format!("key-{}", i), value).unwrap();
+        if i % 25 == 0 {
+            store.flush_and_rotate().unwrap();
+        }
+    }
+    store.remove(b"key-7".to_vec()).unwrap();
+
+    let keys: Vec<Vec<u8>> = (0..110)
+        .rev()
+        .map(|i| format!("key-{}", i).into_bytes())
+        .collect();
+    let streamed: HashMap<Vec<u8>, Option<Vec<u8>>> = store
+        .get_batch_stream(keys.clone())
+        .into_iter()
+        .map(|(key, value)| (key, value.unwrap()))
+        .collect();
+
+    assert_eq!(streamed.len(), keys.len());
+    for key in keys {
+        assert_eq!(streamed[&key], store.get(&key).unwrap());
+    }
+}
+
+#[test]
+fn get_batch_stream_pins_generations_across_merge() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    for i in 0..200 {
+        store.set_str(&format!("key-{}", i), "before").unwrap();
+    }
+    store.flush_and_rotate().unwrap();
+    let old_data_file = dir.join("0.rustcask.data");
+
+    let keys: Vec<Vec<u8>> = (0..200)
+        .map(|i| format!("key-{}", i).into_bytes())
+        .collect();
+    let stream = store.get_batch_stream(keys);
+
+    // The stream reads ahead of the receiver until its channel is full,
+    // so it's still reading from the old data file while the merge runs.
+    let (first_key, first_value) = stream.recv().unwrap();
+    for i in 0..200 {
+        store.set_str(&format!("key-{}", i), "after").unwrap();
+    }
+    store.merge().unwrap();
+    assert!(old_data_file.exists());
+
+    let mut values = vec![(first_key, first_value.unwrap())];
+    values.extend(stream.iter().map(|(key, value)| (key, value.unwrap())));
+    assert_eq!(values.len(), 200);
+    assert!(values
+        .iter()
+        .all(|(_, value)| value.as_deref() == Some(b"before".as_slice())));
+    assert!(!old_data_file.exists());
+    assert_eq!(store.get_str("key-0").unwrap(), Some("after".to_string()));
+}
+
+#[test]
+fn generations_pinned_during_merge_stay_deleted_after_reopen() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    for i in 0..200 {
+        store.set_str(&format!("key-{}", i), "value").unwrap();
+    }
+    store.set_str("removed", "value").unwrap();
+    store.flush_and_rotate().unwrap();
+    store.remove(b"removed".to_vec()).unwrap();
+    store.flush_and_rotate().unwrap();
+
+    let keys: Vec<Vec<u8>> = (0..200)
+        .map(|i| format!("key-{}", i).into_bytes())
+        .collect();
+    let stream = store.get_batch_stream(keys);
+    let (_, value) = stream.recv().unwrap();
+    assert_eq!(value.unwrap(), Some(b"value".to_vec()));
+
+    // The merge drops the tombstone, while the old generation holding the removed key
+    // is still pinned by the stream.
+    store.merge().unwrap();
+    assert!(dir.join("0.rustcask.data").exists());
+    drop(store);
+
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    assert_eq!(store.get_str("removed").unwrap(), None);
+    assert_eq!(store.get_str("key-0").unwrap(), Some("value".to_string()));
+    drop(stream);
+}
+
+#[test]
+fn open_rejects_invalid_config() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let invalid_configs = [
+        Rustcask::builder().set_max_data_file_size(0),
+        Rustcask::builder()
+            .set_auto_merge(true)
+            .set_auto_merge_ratio(f64::NAN),
+        Rustcask::builder().set_value_cache_capacity(u64::MAX),
+    ];
+    for builder in invalid_configs {
+        let err = builder.open(dir).unwrap_err();
+        assert!(
+            matches!(err.kind, OpenErrorKind::InvalidConfig(_)),
+            "{}",
+            err
+        );
+    }
+    // The config is validated before anything is written to the directory.
+    assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+
+    // A ratio that would be invalid is fine while auto-merge is disabled.
+    Rustcask::builder()
+        .set_auto_merge_ratio(f64::NAN)
+        .open(dir)
+        .unwrap();
+}
+
+#[test]
+fn read_log_from_returns_every_record_in_order() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    store.set_str("a", "1").unwrap();
+    store.set_str("b", "2").unwrap();
+    store.flush_and_rotate().unwrap();
+    store.set_str("a", "3").unwrap();
+    store.remove(b"b".to_vec()).unwrap();
+
+    let records: Vec<(LogFileEntry, u64, u64)> = store.read_log_from(0, 0).unwrap().collect();
+    let changes: Vec<(&[u8], Option<&[u8]>)> = records
+        .iter()
+        .map(|(entry, _, _)| (entry.key.as_slice(), entry.value.as_deref()))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (b"a".as_slice(), Some(b"1".as_slice())),
+            (b"b", Some(b"2")),
+            (b"a", Some(b"3")),
+            (b"b", None),
+        ]
+    );
+    let generations: Vec<u64> = records
+        .iter()
+        .map(|(_, generation, _)| *generation)
+        .collect();
+    assert_eq!(generations, [0, 0, 1, 1]);
+
+    // Resuming from a position returns the record at the position, and everything after it.
+    let (_, generation, offset) = records[2];
+    store.set_str("c", "4").unwrap();
+    let resumed: Vec<Vec<u8>> = store
+        .read_log_from(generation, offset)
+        .unwrap()
+        .map(|(entry, _, _)| entry.key)
+        .collect();
+    assert_eq!(resumed, [b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+}
+
+#[test]
+fn read_log_resumes_from_checkpoint_exactly_once() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(256)
+        .open(temp_dir.path())
+        .unwrap();
+    for i in 0..40 {
+        store
+            .set_str(&format!("key{}", i % 25), &format!("value{}", i))
+            .unwrap();
+        if i % 7 == 0 {
+            store.remove(format!("key{}", i % 25).into_bytes()).unwrap();
+        }
+    }
+    let expected: Vec<(u64, u64)> = store
+        .read_log_from(0, 0)
+        .unwrap()
+        .map(|(_, generation, offset)| (generation, offset))
+        .collect();
+    assert!(expected.iter().any(|(generation, _)| *generation > 1));
+
+    // Iterate over half of the records, and then resume from the checkpoint.
+    let mut records = store
+        .read_log_from_checkpoint(LogCheckpoint::start())
+        .unwrap();
+    let mut positions: Vec<(u64, u64)> = records
+        .by_ref()
+        .take(expected.len() / 2)
+        .map(|(_, generation, offset)| (generation, offset))
+        .collect();
+    let checkpoint = records.checkpoint();
+    drop(records);
+
+    let checkpoint = LogCheckpoint::new(checkpoint.generation(), checkpoint.offset());
+    positions.extend(
+        store
+            .read_log_from_checkpoint(checkpoint)
+            .unwrap()
+            .map(|(_, generation, offset)| (generation, offset)),
+    );
+    assert_eq!(positions, expected);
+
+    // A checkpoint at the end of the log picks up records written since.
+    let mut records = store
+        .read_log_from_checkpoint(LogCheckpoint::start())
+        .unwrap();
+    assert_eq!(records.by_ref().count(), expected.len());
+    let end = records.checkpoint();
+    drop(records);
+    store.set_str("new", "value").unwrap();
+    let resumed: Vec<Vec<u8>> = store
+        .read_log_from_checkpoint(end)
+        .unwrap()
+        .map(|(entry, _, _)| entry.key)
+        .collect();
+    assert_eq!(resumed, [b"new".to_vec()]);
+
+    // A merge replaces the checkpoint's generation, so the checkpoint can't be resumed from.
+    store.merge().unwrap();
+    let err = store.read_log_from_checkpoint(checkpoint).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+/// Compresses runs of repeated bytes into (run length, byte) pairs.
+#[derive(Debug)]
+struct RunLengthCompress;
+
+impl ValueTransform for RunLengthCompress {
+    fn tag(&self) -> u8 {
+        1
+    }
+
+    fn apply(&self, value: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        for chunk in value.chunk_by(|a, b| a == b) {
+            for run in chunk.chunks(u8::MAX as usize) {
+                compressed.extend([run.len() as u8, run[0]]);
+            }
+        }
+        compressed
+    }
+
+    fn reverse(&self, value: &[u8]) -> io::Result<Vec<u8>> {
+        if !value.len().is_multiple_of(2) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "odd length"));
+        }
+        Ok(value
+            .chunks(2)
+            .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0] as usize))
+            .collect())
+    }
+
+    fn is_compression(&self) -> bool {
+        true
+    }
+}
+
+/// Stands in for encryption by XORing every byte with a key.
+#[derive(Debug)]
+struct XorEncrypt(u8);
+
+impl ValueTransform for XorEncrypt {
+    fn tag(&self) -> u8 {
+        2
+    }
+
+    fn apply(&self, value: &[u8]) -> Vec<u8> {
+        value.iter().map(|byte| byte ^ self.0).collect()
+    }
+
+    fn reverse(&self, value: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(self.apply(value))
+    }
+}
+
+#[test]
+fn compress_then_encrypt_round_trip() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let builder = Rustcask::builder()
+        .add_value_transform(Arc::new(RunLengthCompress))
+        .add_value_transform(Arc::new(XorEncrypt(0x5a)))
+        .set_inline_value_threshold(8);
+    let mut store = builder.clone().open(dir).unwrap();
+
+    let long_value = "a".repeat(1000) + &"b".repeat(1000);
+    store.set_str("long", &long_value).unwrap();
+    store.set_str("short", "abc").unwrap();
+    store.set_str("removed", "zzzz").unwrap();
+    assert_eq!(store.get_str("long").unwrap(), Some(long_value.clone()));
+    assert_eq!(store.get_str("short").unwrap(), Some("abc".to_string()));
+    assert_eq!(
+        store.remove(b"removed".to_vec()).unwrap(),
+        Some(b"zzzz".to_vec())
+    );
+
+    // The values are stored compressed and encrypted.
+    store.sync().unwrap();
+    let data_file = fs::read(dir.join("0.rustcask.data")).unwrap();
+    assert!(data_file.len() < long_value.len() / 4);
+    assert!(!data_file.windows(3).any(|window| window == b"abc"));
+
+    store.merge().unwrap();
+    drop(store);
+    let mut store = builder.open(dir).unwrap();
+    assert_eq!(store.get_str("long").unwrap(), Some(long_value));
+    assert_eq!(store.get_str("short").unwrap(), Some("abc".to_string()));
+    assert_eq!(store.get_str("removed").unwrap(), None);
+}
+
+#[test]
+fn read_values_written_with_a_different_pipeline() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+
+    let mut store = Rustcask::builder()
+        .add_value_transform(Arc::new(RunLengthCompress))
+        .open(dir)
+        .unwrap();
+    store.set_str("compressed", "aaaa").unwrap();
+    drop(store);
+
+    // The pipeline is reordered and extended. Older values are read with the transforms
+    // they were written with.
+    let mut store = Rustcask::builder()
+        .add_value_transform(Arc::new(XorEncrypt(0x5a)))
+        .add_value_transform(Arc::new(RunLengthCompress))
+        .open(dir)
+        .unwrap();
+    assert_eq!(
+        store.get_str("compressed").unwrap(),
+        Some("aaaa".to_string())
+    );
+    store.set_str("encrypted", "bbbb").unwrap();
+    assert_eq!(
+        store.get_str("encrypted").unwrap(),
+        Some("bbbb".to_string())
+    );
+    drop(store);
+
+    // Values written with a transform that's no longer configured can't be read.
+    let mut store = Rustcask::builder()
+        .add_value_transform(Arc::new(RunLengthCompress))
+        .open(dir)
+        .unwrap();
+    assert_eq!(
+        store.get_str("compressed").unwrap(),
+        Some("aaaa".to_string())
+    );
+    let err = store.get(&b"encrypted".to_vec()).unwrap_err();
+    assert!(matches!(&err.kind, GetErrorKind::Io(err) if err.kind() == io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn same_size_overwrite_in_place() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let data_file = dir.join("0.rustcask.data");
+    let mut store = Rustcask::builder()
+        .set_in_place_updates(true)
+        .set_value_cache_capacity(1024)
+        .open(dir)
+        .unwrap();
+
+    store.set_str("key", "aaaa").unwrap();
+    store.set_str("other", "cccc").unwrap();
+    assert_eq!(store.get_str("key").unwrap(), Some("aaaa".to_string()));
+    let len = fs::metadata(&data_file).unwrap().len();
+
+    store.set_str("key", "bbbb").unwrap();
+    assert_eq!(fs::metadata(&data_file).unwrap().len(), len);
+    assert_eq!(store.get_str("key").unwrap(), Some("bbbb".to_string()));
+    assert_eq!(store.get_str("other").unwrap(), Some("cccc".to_string()));
+
+    // A value of a different size is appended.
+    store.set_str("key", "ddddd").unwrap();
+    assert!(fs::metadata(&data_file).unwrap().len() > len);
+    store.set_str("key", "eeeee").unwrap();
+    drop(store);
+
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    assert_eq!(store.get_str("key").unwrap(), Some("eeeee".to_string()));
+    assert_eq!(store.get_str("other").unwrap(), Some("cccc".to_string()));
+}
+
+#[test]
+fn tombstones_list_removed_keys() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    store.set_str("a", "1").unwrap();
+    store.set_str("b", "2").unwrap();
+    store.remove(b"a".to_vec()).unwrap();
+    store.flush_and_rotate().unwrap();
+    store.set_str("a", "3").unwrap();
+    store.remove(b"b".to_vec()).unwrap();
+    store.remove(b"a".to_vec()).unwrap();
+
+    let tombstones: Vec<(Vec<u8>, u64)> = store
+        .tombstones()
+        .unwrap()
+        .map(|(key, generation, _)| (key, generation))
+        .collect();
+    assert_eq!(
+        tombstones,
+        [(b"a".to_vec(), 0), (b"b".to_vec(), 1), (b"a".to_vec(), 1)]
+    );
+
+    store.merge().unwrap();
+    assert_eq!(store.tombstones().unwrap().count(), 0);
+}
+
+#[test]
+fn writes_continue_during_merge() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(4096)
+        .open(dir)
+        .unwrap();
+    for i in 0..2000 {
+        store.set_str(&format!("key-{}", i), "before").unwrap();
+    }
+    for i in 0..1000 {
+        store.set_str(&format!("key-{}", i), "overwritten").unwrap();
+    }
+
+    let done = Arc::new(AtomicBool::new(false));
+    let mut writer_store = store.clone();
+    let writer_done = done.clone();
+    let writer = thread::spawn(move || {
+        let mut written = 0;
+        while !writer_done.load(Ordering::Relaxed) || written < 500 {
+            writer_store
+                .set_str(&format!("new-{}", written), &written.to_string())
+                .unwrap();
+            if written % 2 == 0 {
+                writer_store
+                    .remove(format!("key-{}", written).into_bytes())
+                    .unwrap();
+            }
+            written += 1;
+        }
+        written
+    });
+
+    store.merge().unwrap();
+    done.store(true, Ordering::Relaxed);
+    let written = writer.join().unwrap();
+
+    let check = |store: &mut Rustcask| {
+        for i in 0..written {
+            assert_eq!(
+                store.get_str(&format!("new-{}", i)).unwrap(),
+                Some(i.to_string())
+            );
+        }
+        for i in 0..2000 {
+            let expected = if i % 2 == 0 && i < written {
+                None
+            } else if i < 1000 {
+                Some("overwritten".to_string())
+            } else {
+                Some("before".to_string())
+            };
+            assert_eq!(store.get_str(&format!("key-{}", i)).unwrap(), expected);
+        }
+    };
+    check(&mut store);
+
+    drop(store);
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(4096)
+        .open(dir)
+        .unwrap();
+    check(&mut store);
+}
+
+#[test]
+fn open_read_only_directory() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder().open(dir).unwrap();
+    store.set_str("key", "value").unwrap();
+    store.flush_and_rotate().unwrap();
+    store.set_str("other", "value").unwrap();
+    drop(store);
+
+    let set_read_only = |read_only: bool| {
+        let mode = if read_only { 0o444 } else { 0o644 };
+        for entry in fs::read_dir(dir).unwrap() {
+            fs::set_permissions(entry.unwrap().path(), fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let dir_mode = if read_only { 0o555 } else { 0o755 };
+        fs::set_permissions(dir, fs::Permissions::from_mode(dir_mode)).unwrap();
+    };
+    let files_before = get_file_names(dir);
+    set_read_only(true);
+
+    let mut store = Rustcask::builder().open_read_only(dir).unwrap();
+    assert_eq!(
+        store.get(&"key".as_bytes().to_vec()).unwrap(),
+        Some("value".as_bytes().to_vec())
+    );
+    assert_eq!(
+        store.get(&"other".as_bytes().to_vec()).unwrap(),
+        Some("value".as_bytes().to_vec())
+    );
+    assert_eq!(store.get(&"missing".as_bytes().to_vec()).unwrap(), None);
+
+    set_read_only(false);
+    assert_eq!(get_file_names(dir), files_before);
+}
+
+#[test]
+fn iter_ordered_is_deterministic() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(256)
+        .open(dir)
+        .unwrap();
+    for i in 0..100 {
+        store
+            .set_str(&format!("key-{}", i), &i.to_string())
+            .unwrap();
+    }
+    store.set_str("key-0", "overwritten").unwrap();
+    store.remove("key-1".as_bytes().to_vec()).unwrap();
+
+    let collect = |store: &Rustcask| -> Vec<(Vec<u8>, Vec<u8>)> {
+        store.iter_ordered().map(|entry| entry.unwrap()).collect()
+    };
+    let entries = collect(&store);
+    assert_eq!(entries.len(), 99);
+    assert_eq!(entries, collect(&store));
+
+    // Entries follow the order the values were written in.
+    assert_eq!(entries[0].0, "key-2".as_bytes());
+    assert_eq!(
+        entries.last().unwrap(),
+        &(
+            "key-0".as_bytes().to_vec(),
+            "overwritten".as_bytes().to_vec()
+        )
+    );
+
+    drop(store);
+    let store = Rustcask::builder()
+        .set_max_data_file_size(256)
+        .open(dir)
+        .unwrap();
+    assert_eq!(entries, collect(&store));
+}
+
+#[test]
+fn write_amplification_counts_overwrites_and_merges() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    assert_eq!(store.write_amplification(), 0.0);
+
+    // Every entry has the same key and value lengths, so every entry has the same encoded size.
+    for i in 0..10 {
+        store.set_str("a", &format!("value-{}", i)).unwrap();
+    }
+    store.set_str("b", "value-0").unwrap();
+
+    // 11 entries were written, and 2 are live.
+    assert_eq!(store.write_amplification(), 11.0 / 2.0);
+
+    // The merge copies the 2 live entries.
+    store.merge().unwrap();
+    assert_eq!(store.write_amplification(), 13.0 / 2.0);
+
+    let entry_len = LogFileEntry {
+        key: "a".as_bytes().to_vec(),
+        value: Some("value-0".as_bytes().to_vec()),
+        expires_at: None,
+        transforms: Vec::new(),
+    }
+    .encode()
+    .unwrap()
+    .len() as u64;
+    let counters = store.counters();
+    assert_eq!(counters.bytes_written, 11 * entry_len);
+    assert_eq!(counters.merge_bytes_written, 2 * entry_len);
+}
+
+#[test]
+fn small_values_skip_compression() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder()
+        .add_value_transform(Arc::new(RunLengthCompress))
+        .add_value_transform(Arc::new(XorEncrypt(0x5a)))
+        .set_compression_min_size(64)
+        .open(dir)
+        .unwrap();
+
+    let large_value = "a".repeat(64);
+    store.set_str("small", "abc").unwrap();
+    store.set_str("large", &large_value).unwrap();
+
+    let transforms: HashMap<Vec<u8>, Vec<u8>> = store
+        .read_log_from(0, 0)
+        .unwrap()
+        .map(|(entry, _, _)| (entry.key, entry.transforms))
+        .collect();
+    // Encryption isn't skipped for small values.
+    assert_eq!(transforms[b"small".as_slice()], [2]);
+    assert_eq!(transforms[b"large".as_slice()], [1, 2]);
+
+    assert_eq!(store.get_str("small").unwrap(), Some("abc".to_string()));
+    assert_eq!(store.get_str("large").unwrap(), Some(large_value));
+}
+
+#[test]
+fn fork_is_independent() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let source_dir = temp_dir.path().join("source");
+    let fork_dir = temp_dir.path().join("fork");
+    fs::create_dir(&source_dir).unwrap();
+    let mut source = Rustcask::builder()
+        .set_max_data_file_size(256)
+        .open(&source_dir)
+        .unwrap();
+    for i in 0..50 {
+        source.set_str(&format!("key-{}", i), "before").unwrap();
+    }
+    source.set_str("key-0", "overwritten").unwrap();
+    source.remove("key-1".as_bytes().to_vec()).unwrap();
+
+    let mut fork = source.fork(&fork_dir).unwrap();
+    assert!(source.fork(&fork_dir).is_err());
+
+    source.set_str("key-2", "source").unwrap();
+    source.remove("key-3".as_bytes().to_vec()).unwrap();
+    fork.set_str("key-2", "fork").unwrap();
+    fork.set_str("fork-only", "fork").unwrap();
+
+    let check = |store: &mut Rustcask, key_2: &str, key_3: Option<&str>, fork_only| {
+        assert_eq!(
+            store.get_str("key-0").unwrap(),
+            Some("overwritten".to_string())
+        );
+        assert_eq!(store.get_str("key-1").unwrap(), None);
+        assert_eq!(store.get_str("key-2").unwrap(), Some(key_2.to_string()));
+        assert_eq!(store.get_str("key-3").unwrap(), key_3.map(String::from));
+        assert_eq!(store.get_str("fork-only").unwrap(), fork_only);
+        for i in 4..50 {
+            assert_eq!(
+                store.get_str(&format!("key-{}", i)).unwrap(),
+                Some("before".to_string())
+            );
+        }
+    };
+    check(&mut source, "source", None, None);
+    check(&mut fork, "fork", Some("before"), Some("fork".to_string()));
+
+    // The fork was copied without the removed values and their tombstones.
+    drop(fork);
+    let mut fork = Rustcask::builder().open(&fork_dir).unwrap();
+    check(&mut fork, "fork", Some("before"), Some("fork".to_string()));
+    assert_eq!(fork.tombstones().unwrap().count(), 0);
+    assert_eq!(source.tombstones().unwrap().count(), 2);
+}
+
+#[test]
+fn set_durable_overrides_sync_mode() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    store.set_str("before", "value").unwrap();
+    store
+        .set_durable(
+            "critical".as_bytes().to_vec(),
+            "value".as_bytes().to_vec(),
+            true,
+        )
+        .unwrap();
+    store.set_str("after", "value").unwrap();
+    assert_eq!(store.counters().synced_writes, 1);
+    assert_eq!(
+        store.get_str("critical").unwrap(),
+        Some("value".to_string())
+    );
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder()
+        .set_sync_mode(true)
+        .open(temp_dir.path())
+        .unwrap();
+    store.set_str("before", "value").unwrap();
+    store
+        .set_durable(
+            "bulk".as_bytes().to_vec(),
+            "value".as_bytes().to_vec(),
+            false,
+        )
+        .unwrap();
+    store.set_str("after", "value").unwrap();
+    assert_eq!(store.counters().synced_writes, 2);
+    assert_eq!(store.get_str("bulk").unwrap(), Some("value".to_string()));
+}
+
+#[test]
+fn merge_delete_delay_keeps_replaced_generations() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let delay = Duration::from_millis(300);
+    let mut store = Rustcask::builder()
+        .set_merge_delete_delay(delay)
+        .open(temp_dir.path())
+        .unwrap();
+    store.set(b"key".to_vec(), b"old".to_vec()).unwrap();
+    store.set(b"key".to_vec(), b"new".to_vec()).unwrap();
+
+    let merged_at = Instant::now();
+    store.merge().unwrap();
+    let file_names = get_file_names(temp_dir.path());
+    assert!(file_names.contains(&"0.rustcask.data".to_string()));
+    assert!(file_names.contains(&"0.rustcask.retired".to_string()));
+    assert_eq!(store.get(&b"key".to_vec()).unwrap(), Some(b"new".to_vec()));
+
+    while get_file_names(temp_dir.path()).contains(&"0.rustcask.data".to_string()) {
+        assert!(merged_at.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(merged_at.elapsed() >= delay);
+    assert!(!get_file_names(temp_dir.path()).contains(&"0.rustcask.retired".to_string()));
+    assert_eq!(store.get(&b"key".to_vec()).unwrap(), Some(b"new".to_vec()));
+
+    // Generations that are still retired when the store is reopened are deleted by open.
+    store.remove(b"key".to_vec()).unwrap();
+    let mut store = Rustcask::builder()
+        .set_merge_delete_delay(Duration::from_secs(3600))
+        .open(temp_dir.path())
+        .unwrap();
+    store.merge().unwrap();
+    assert!(get_file_names(temp_dir.path()).contains(&"1.rustcask.retired".to_string()));
+    drop(store);
+
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    let file_names = get_file_names(temp_dir.path());
+    assert!(!file_names.iter().any(|name| name.ends_with(".retired")));
+    assert!(!file_names.contains(&"1.rustcask.data".to_string()));
+    assert_eq!(store.get(&b"key".to_vec()).unwrap(), None);
+}
+
+#[test]
+fn merge_max_extra_bytes_bounds_merge_disk_usage() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let max_extra_bytes = 4096;
+    let mut store = Rustcask::builder()
+        .set_max_data_file_size(1024)
+        .set_merge_max_extra_bytes(max_extra_bytes)
+        .open(temp_dir.path())
+        .unwrap();
+    for round in 0..2 {
+        for i in 0..200 {
+            // Only every other key is overwritten, so the live entries are spread over every generation.
+            if round == 0 || i % 2 == 0 {
+                let value = format!("value-{}-{}", round, i).repeat(8).into_bytes();
+                store.set(format!("key-{}", i).into_bytes(), value).unwrap();
+            }
+        }
+    }
+    let size_before_merge = get_total_directory_size(temp_dir.path());
+    assert!(size_before_merge > 4 * max_extra_bytes);
+
+    // Sample the directory size while the merge runs, to find its peak.
+    let merging = Arc::new(AtomicBool::new(true));
+    let sampler = {
+        let merging = merging.clone();
+        let dir = temp_dir.path().to_path_buf();
+        thread::spawn(move || {
+            let mut peak_size = 0;
+            while merging.load(Ordering::SeqCst) {
+                peak_size = peak_size.max(get_total_directory_size(&dir));
+            }
+            peak_size
+        })
+    };
+    store.merge().unwrap();
+    merging.store(false, Ordering::SeqCst);
+    let peak_size = sampler.join().unwrap();
+
+    assert!(peak_size <= size_before_merge + max_extra_bytes);
+    assert!(get_total_directory_size(temp_dir.path()) < size_before_merge);
+    drop(store);
+
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    for i in 0..200 {
+        let round = if i % 2 == 0 { 1 } else { 0 };
+        let value = format!("value-{}-{}", round, i).repeat(8).into_bytes();
+        assert_eq!(
+            store.get(&format!("key-{}", i).into_bytes()).unwrap(),
+            Some(value)
+        );
+    }
+}
+
+#[test]
+fn merge_max_extra_bytes_skips_pinned_generations() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path();
+    let mut store = Rustcask::builder()
+        .set_merge_max_extra_bytes(1)
+        .open(dir)
+        .unwrap();
+    for generation in 0..3 {
+        for i in 0..100 {
+            store
+                .set_str(&format!("key-{}", i), &format!("value-{}", generation))
+                .unwrap();
+        }
+        store.flush_and_rotate().unwrap();
+    }
+
+    // Each step merges a single generation, and the first one is pinned by the stream,
+    // so it stays on disk after its step.
+    let keys: Vec<Vec<u8>> = (0..100)
+        .map(|i| format!("key-{}", i).into_bytes())
+        .collect();
+    let stream = store.get_batch_stream(keys);
+    let (_, value) = stream.recv().unwrap();
+    assert_eq!(value.unwrap(), Some(b"value-2".to_vec()));
+    store.merge().unwrap();
+    assert!(dir.join("2.rustcask.data").exists());
+
+    assert_eq!(stream.iter().count(), 99);
+    assert!(!dir.join("2.rustcask.data").exists());
+    for i in 0..100 {
+        assert_eq!(
+            store.get_str(&format!("key-{}", i)).unwrap(),
+            Some("value-2".to_string())
+        );
+    }
+}
+
+#[test]
+fn corruption_handler_reports_corrupt_entries() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let handler_events = events.clone();
+    let builder = Rustcask::builder().set_corruption_handler(Arc::new(move |event| {
+        handler_events.lock().unwrap().push(event)
+    }));
+
+    let mut store = builder.clone().open(temp_dir.path()).unwrap();
+    store.set(b"key".to_vec(), b"value".to_vec()).unwrap();
+    store.set(b"other".to_vec(), b"value".to_vec()).unwrap();
+    let location = store.locate(&b"key".to_vec()).unwrap();
+
+    // Overwrite the flags that mark the entry as a value rather than a tombstone. They follow
+    // the key and value lengths, and the number of value transforms.
+    let flags_offset = location.offset() + 4 + 4 + 1;
+    let data_file = temp_dir.path().join("0.rustcask.data");
+    let mut file = fs::OpenOptions::new().write(true).open(data_file).unwrap();
+    file.seek(SeekFrom::Start(flags_offset)).unwrap();
+    file.write_all(&[0xff]).unwrap();
+    drop(file);
+
+    let expected = CorruptionEvent {
+        generation: 0,
+        offset: location.offset(),
+        kind: CorruptionKind::CorruptEntry,
+    };
+    let err = store.get(&b"key".to_vec()).unwrap_err();
+    assert!(matches!(err.kind, GetErrorKind::Deserialize(_)));
+    assert_eq!(*events.lock().unwrap(), std::slice::from_ref(&expected));
+    drop(store);
+
+    // Open skips the corrupt entry, and reports it too.
+    let mut store = builder.open(temp_dir.path()).unwrap();
+    assert_eq!(*events.lock().unwrap(), [expected.clone(), expected]);
+    assert_eq!(
+        store.get(&b"other".to_vec()).unwrap(),
+        Some(b"value".to_vec())
+    );
+}
+
+#[test]
+fn corruption_handler_reports_corrupt_values() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let handler_events = events.clone();
+    let mut store = Rustcask::builder()
+        .set_corruption_handler(Arc::new(move |event| {
+            handler_events.lock().unwrap().push(event)
+        }))
+        .open(temp_dir.path())
+        .unwrap();
+    store.set(b"key".to_vec(), b"value".to_vec()).unwrap();
+    let location = store.locate(&b"key".to_vec()).unwrap();
+    assert!(store.verify_generation(0).unwrap().is_clean());
+
+    // Flip a bit in the last byte of the value, which the value checksum no longer matches.
+    let last_value_byte = location.offset() + location.len() - 1;
+    let data_file = temp_dir.path().join("0.rustcask.data");
+    let mut bytes = fs::read(&data_file).unwrap();
+    bytes[last_value_byte as usize] ^= 1;
+    fs::write(&data_file, bytes).unwrap();
+
+    let expected = CorruptionEvent {
+        generation: 0,
+        offset: location.offset(),
+        kind: CorruptionKind::CorruptEntry,
+    };
+    let err = store.get(&b"key".to_vec()).unwrap_err();
+    assert!(matches!(err.kind, GetErrorKind::Deserialize(_)));
+    assert_eq!(*events.lock().unwrap(), std::slice::from_ref(&expected));
+
+    assert!(!store.verify_generation(0).unwrap().is_clean());
+    assert_eq!(*events.lock().unwrap(), [expected.clone(), expected]);
+}
+
+#[test]
+fn on_read_corruption_controls_get_of_corrupt_entries() {
+    for mode in [
+        OnCorruption::Error,
+        OnCorruption::SkipReturnNone,
+        OnCorruption::Panic,
+    ] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let mut store = Rustcask::builder()
+            .set_on_read_corruption(mode)
+            .open(temp_dir.path())
+            .unwrap();
+        let corrupt = b"corrupt".to_vec();
+        let mismatched = b"mismatched".to_vec();
+        let flipped = b"flipped".to_vec();
+        store.set(corrupt.clone(), b"value".to_vec()).unwrap();
+        store.set(mismatched.clone(), b"value".to_vec()).unwrap();
+        store.set(flipped.clone(), b"value".to_vec()).unwrap();
+        store.set(b"other".to_vec(), b"value".to_vec()).unwrap();
+
+        // Overwrite the flags of one entry, which follow the key and value lengths and the
+        // number of value transforms, and replace the other entry with a valid entry of the
+        // same length for a different key.
+        let data_file = temp_dir.path().join("0.rustcask.data");
+        let mut file = fs::OpenOptions::new().write(true).open(data_file).unwrap();
+        let flags_offset = store.locate(&corrupt).unwrap().offset() + 4 + 4 + 1;
+        file.seek(SeekFrom::Start(flags_offset)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+        let other_entry = LogFileEntry {
+            key: b"Mismatched".to_vec(),
+            value: Some(b"value".to_vec()),
+            expires_at: None,
+            transforms: Vec::new(),
+        };
+        file.seek(SeekFrom::Start(store.locate(&mismatched).unwrap().offset()))
+            .unwrap();
+        file.write_all(&other_entry.encode().unwrap()).unwrap();
+        // Change the last byte of the third entry's value, so that it fails the value checksum.
+        let flipped_location = store.locate(&flipped).unwrap();
+        let last_value_byte = flipped_location.offset() + flipped_location.len() - 1;
+        file.seek(SeekFrom::Start(last_value_byte)).unwrap();
+        file.write_all(b"E").unwrap();
+        drop(file);
+
+        match mode {
+            OnCorruption::Error => {
+                let err = store.get(&corrupt).unwrap_err();
+                assert!(matches!(err.kind, GetErrorKind::Deserialize(_)));
+                let err = store.get(&mismatched).unwrap_err();
+                assert!(matches!(err.kind, GetErrorKind::KeyMismatch { .. }));
+                let err = store.get(&flipped).unwrap_err();
+                assert!(matches!(err.kind, GetErrorKind::Deserialize(_)));
+            }
+            OnCorruption::SkipReturnNone => {
+                assert_eq!(store.get(&corrupt).unwrap(), None);
+                assert_eq!(store.get(&mismatched).unwrap(), None);
+                assert_eq!(store.get(&flipped).unwrap(), None);
+                let contents: Vec<_> = store.iter_ordered().map(Result::unwrap).collect();
+                assert_eq!(contents, [(b"other".to_vec(), b"value".to_vec())]);
+            }
+            OnCorruption::Panic => {
+                for key in [&corrupt, &mismatched, &flipped] {
+                    let mut store = store.clone();
+                    let key = key.clone();
+                    let result = thread::spawn(move || store.get(&key)).join();
+                    assert!(result.is_err());
+                }
+            }
+        }
+        assert_eq!(
+            store.get(&b"other".to_vec()).unwrap(),
+            Some(b"value".to_vec())
+        );
+    }
+}
+
+#[test]
+fn verify_generation_reports_corrupt_entries_of_that_generation() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    store.set(b"key".to_vec(), b"value".to_vec()).unwrap();
+    store.set(b"other".to_vec(), b"value".to_vec()).unwrap();
+    let location = store.locate(&b"key".to_vec()).unwrap();
+    store.flush_and_rotate().unwrap();
+    store.set(b"key".to_vec(), b"new value".to_vec()).unwrap();
+
+    // Overwrite the flags that mark the first entry as a value rather than a tombstone.
+    let flags_offset = location.offset() + 4 + 4 + 1;
+    let data_file = temp_dir.path().join("0.rustcask.data");
+    // The generation was sealed read-only when it was rotated out.
+    fs::set_permissions(&data_file, fs::Permissions::from_mode(0o644)).unwrap();
+    let mut file = fs::OpenOptions::new().write(true).open(data_file).unwrap();
+    file.seek(SeekFrom::Start(flags_offset)).unwrap();
+    file.write_all(&[0xff]).unwrap();
+    drop(file);
+
+    let report = store.verify_generation(0).unwrap();
+    assert!(!report.is_clean());
+    assert_eq!(
+        report,
+        GenerationVerifyReport {
+            generation: 0,
+            entries: 1,
+            corrupt_offsets: vec![location.offset()],
+            truncated_offset: None,
+        }
+    );
+
+    // The active generation is flushed before it's checked.
+    let report = store.verify_generation(1).unwrap();
+    assert!(report.is_clean());
+    assert_eq!(report.entries, 1);
+
+    let err = store.verify_generation(2).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn verify_generation_reports_corrupt_values() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    store.set(b"key".to_vec(), b"value".to_vec()).unwrap();
+    store.set(b"other".to_vec(), b"value".to_vec()).unwrap();
+    let location = store.locate(&b"key".to_vec()).unwrap();
+    // Verifying flushes the active data file, so the value is on disk.
+    store.verify_generation(0).unwrap();
+
+    // Flip a bit in the last byte of the first entry's value.
+    let last_value_byte = location.offset() + location.len() - 1;
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(temp_dir.path().join("0.rustcask.data"))
+        .unwrap();
+    let mut byte = [0];
+    file.seek(SeekFrom::Start(last_value_byte)).unwrap();
+    file.read_exact(&mut byte).unwrap();
+    file.seek(SeekFrom::Start(last_value_byte)).unwrap();
+    file.write_all(&[byte[0] ^ 1]).unwrap();
+    drop(file);
+
+    let report = store.verify_generation(0).unwrap();
+    assert_eq!(
+        report,
+        GenerationVerifyReport {
+            generation: 0,
+            entries: 1,
+            corrupt_offsets: vec![location.offset()],
+            truncated_offset: None,
+        }
+    );
+}
+
+#[test]
+fn metadata_persists_across_reopen() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    assert_eq!(store.metadata().unwrap(), None);
+
+    store.set_metadata(b"schema-v1".to_vec()).unwrap();
+    store.set_metadata(b"schema-v2".to_vec()).unwrap();
+    store.set(b"key".to_vec(), b"value".to_vec()).unwrap();
+    store.merge().unwrap();
+    drop(store);
+
+    let (store, report) = Rustcask::builder()
+        .open_with_report(temp_dir.path())
+        .unwrap();
+    assert!(report.is_clean());
+    assert_eq!(store.metadata().unwrap(), Some(b"schema-v2".to_vec()));
+    assert_eq!(store.iter_ordered().count(), 1);
+}
+
+#[test]
+fn compact_key_lets_old_generation_be_dropped() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    store.set_str("cold", "rarely written").unwrap();
+    store.set_str("hot", "0").unwrap();
+    store.flush_and_rotate().unwrap();
+    for i in 1..50 {
+        store.set_str("hot", &i.to_string()).unwrap();
+    }
+
+    // Generation 0 only holds stale values of "hot", but "cold" keeps it alive.
+    assert!(store.drop_generation(0).is_err());
+
+    assert!(store.compact_key(&b"cold".to_vec()).unwrap());
+    assert!(!store.compact_key(&b"missing".to_vec()).unwrap());
+    assert_eq!(store.locate(&b"cold".to_vec()).unwrap().generation(), 1);
+
+    store.drop_generation(0).unwrap();
+    assert!(!get_file_names(temp_dir.path()).contains(&"0.rustcask.data".to_string()));
+    drop(store);
+
+    let mut store = Rustcask::builder().open(temp_dir.path()).unwrap();
+    assert_eq!(
+        store.get_str("cold").unwrap(),
+        Some("rarely written".to_string())
+    );
+    assert_eq!(store.get_str("hot").unwrap(), Some("49".to_string()));
+}
+
+/// Checks whether a store holds the lock on `dir`, by trying to lock its lock file the way
+/// another process would.
+fn is_locked_by_store(dir: &Path) -> bool {
+    let lock_file = fs::File::open(dir.join("rustcask.lock")).unwrap();
+    matches!(lock_file.try_lock(), Err(fs::TryLockError::WouldBlock))
+}
+
+fn get_file_names(dir: &Path) -> Vec<String> {
+    let mut file_names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    file_names.sort();
+    file_names
+}
+
+fn get_total_directory_size(path: &Path) -> u64 {
+    let mut total_size: u64 = 0;
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            if entry_path.is_dir() {
+                total_size += get_total_directory_size(&entry_path);
+            } else if let Ok(metadata) = entry_path.metadata() {
+                total_size += metadata.st_size();
             }
         }
     }